@@ -0,0 +1,613 @@
+// Alerts about doors left open, sent through the configured notifiers
+//
+// The door of each device is tracked from its first open reading until a closed one, unknown readings change
+// nothing. Once it has been open for `open_after_minutes` the `notify` notifiers are told, again every
+// `repeat_minutes` if set, and the `escalate` ones once it has been open for `escalate_after_minutes`. Whoever was
+// told is told again when the door is closed.
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+use std::process::{self, ExitStatus};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use serde::Serialize;
+
+use crate::archive::Capture;
+use crate::config::{AlertConfig, ConfigError, NotifierConfig, SmtpSecurity};
+use crate::door::DoorState;
+
+// Time allowed for sending each notification
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertEvent {
+    // The door has been open for too long, repeated while it stays open
+    Open,
+    // The door is still open after `escalate_after_minutes`
+    Escalated,
+    // The door was closed after an alert
+    Closed,
+}
+
+impl AlertEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AlertEvent::Open => "open",
+            AlertEvent::Escalated => "escalated",
+            AlertEvent::Closed => "closed",
+        }
+    }
+}
+
+impl fmt::Display for AlertEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// What notifiers are given, webhooks are sent it as JSON
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub device: String,
+    pub event: AlertEvent,
+    pub open_since: DateTime<Utc>,
+    pub open_minutes: i64,
+    // eg. "garage door has been open for 25 minutes"
+    pub message: String,
+    // Capture the alert was raised on, when captures are archived
+    pub capture_id: Option<String>,
+}
+
+// A way of sending alerts, see `NotifierConfig`
+pub trait Notifier: Send + Sync {
+    fn notify(&self, alert: &Alert) -> Result<(), NotifyError>;
+}
+
+#[derive(Debug)]
+pub enum NotifyError {
+    Webhook(Box<ureq::Error>),
+    Email(lettre::error::Error),
+    Smtp(lettre::transport::smtp::Error),
+    Command(io::Error),
+    // The command ran but did not succeed
+    CommandFailed(ExitStatus),
+}
+
+impl fmt::Display for NotifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotifyError::Webhook(err) => write!(f, "webhook failed: {}", err),
+            NotifyError::Email(err) => write!(f, "invalid email: {}", err),
+            NotifyError::Smtp(err) => write!(f, "sending email failed: {}", err),
+            NotifyError::Command(err) => write!(f, "failed to run command: {}", err),
+            NotifyError::CommandFailed(status) => write!(f, "command failed: {}", status),
+        }
+    }
+}
+
+impl std::error::Error for NotifyError {}
+
+// POSTs the alert as JSON
+pub struct Webhook {
+    url: String,
+}
+
+impl Notifier for Webhook {
+    fn notify(&self, alert: &Alert) -> Result<(), NotifyError> {
+        ureq::post(&self.url)
+            .timeout(NOTIFY_TIMEOUT)
+            .send_json(alert)
+            .map_err(|err| NotifyError::Webhook(Box::new(err)))?;
+        Ok(())
+    }
+}
+
+// Emails the alert over SMTP
+pub struct Email {
+    transport: SmtpTransport,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+impl Notifier for Email {
+    fn notify(&self, alert: &Alert) -> Result<(), NotifyError> {
+        let mut body = format!(
+            "{}\n\nOpen since {}",
+            alert.message,
+            alert.open_since.to_rfc3339()
+        );
+        if let Some(capture_id) = &alert.capture_id {
+            body.push_str(&format!("\nCapture {}", capture_id));
+        }
+
+        let mut builder = Message::builder()
+            .from(self.from.clone())
+            .subject(&alert.message);
+        for to in &self.to {
+            builder = builder.to(to.clone());
+        }
+        let message = builder.body(body).map_err(NotifyError::Email)?;
+        self.transport.send(&message).map_err(NotifyError::Smtp)?;
+        Ok(())
+    }
+}
+
+// Runs a local command with the alert in its environment: ALERT_DEVICE, ALERT_EVENT, ALERT_MESSAGE,
+// ALERT_OPEN_SINCE, ALERT_OPEN_MINUTES and ALERT_CAPTURE_ID (empty when not archived)
+//
+// Note: the command is not timed out, it only holds up its own notification
+pub struct Command {
+    program: String,
+    args: Vec<String>,
+}
+
+impl Notifier for Command {
+    fn notify(&self, alert: &Alert) -> Result<(), NotifyError> {
+        let status = process::Command::new(&self.program)
+            .args(&self.args)
+            .env("ALERT_DEVICE", &alert.device)
+            .env("ALERT_EVENT", alert.event.name())
+            .env("ALERT_MESSAGE", &alert.message)
+            .env("ALERT_OPEN_SINCE", alert.open_since.to_rfc3339())
+            .env("ALERT_OPEN_MINUTES", alert.open_minutes.to_string())
+            .env(
+                "ALERT_CAPTURE_ID",
+                alert.capture_id.as_deref().unwrap_or_default(),
+            )
+            .status()
+            .map_err(NotifyError::Command)?;
+        match status.success() {
+            true => Ok(()),
+            false => Err(NotifyError::CommandFailed(status)),
+        }
+    }
+}
+
+fn notifier(config: &NotifierConfig) -> Result<Arc<dyn Notifier>, String> {
+    Ok(match config {
+        NotifierConfig::Webhook { url } => Arc::new(Webhook { url: url.clone() }),
+        NotifierConfig::Smtp {
+            server,
+            port,
+            security,
+            username,
+            password,
+            from,
+            to,
+        } => {
+            let builder = match security {
+                SmtpSecurity::Starttls => SmtpTransport::starttls_relay(server),
+                SmtpSecurity::Tls => SmtpTransport::relay(server),
+                SmtpSecurity::None => Ok(SmtpTransport::builder_dangerous(server)),
+            };
+            let mut builder = builder
+                .map_err(|err| err.to_string())?
+                .timeout(Some(NOTIFY_TIMEOUT));
+            if let Some(port) = port {
+                builder = builder.port(*port);
+            }
+            match (username, password) {
+                (Some(username), Some(password)) => {
+                    builder =
+                        builder.credentials(Credentials::new(username.clone(), password.clone()))
+                }
+                (None, None) => {}
+                _ => return Err("username and password go together".to_string()),
+            }
+
+            let mailbox = |address: &String| {
+                address
+                    .parse::<Mailbox>()
+                    .map_err(|err| format!("invalid address {}: {}", address, err))
+            };
+            if to.is_empty() {
+                return Err("no addresses to send to".to_string());
+            }
+            Arc::new(Email {
+                transport: builder.build(),
+                from: mailbox(from)?,
+                to: to.iter().map(mailbox).collect::<Result<_, _>>()?,
+            })
+        }
+        NotifierConfig::Command { command } => match command.split_first() {
+            Some((program, args)) => Arc::new(Command {
+                program: program.clone(),
+                args: args.to_vec(),
+            }),
+            None => return Err("empty command".to_string()),
+        },
+    })
+}
+
+// A notifier with the name it is configured by
+type NamedNotifier = (String, Arc<dyn Notifier>);
+
+// A door that is open, from its first open reading
+struct Episode {
+    open_since: DateTime<Utc>,
+    // When the `notify` notifiers were last told
+    alerted_at: Option<DateTime<Utc>>,
+    escalated: bool,
+}
+
+pub struct Alerts {
+    config: AlertConfig,
+    notify: Vec<NamedNotifier>,
+    escalate: Vec<NamedNotifier>,
+    // By device name
+    episodes: Mutex<HashMap<String, Episode>>,
+}
+
+impl Alerts {
+    pub fn new(
+        config: AlertConfig,
+        notifiers: &BTreeMap<String, NotifierConfig>,
+    ) -> Result<Self, ConfigError> {
+        let find = |names: &[String]| {
+            names
+                .iter()
+                .map(|name| {
+                    let notifier_config = notifiers.get(name).ok_or_else(|| {
+                        ConfigError::Invalid(format!(
+                            "alerts use notifier {}, which is not configured",
+                            name
+                        ))
+                    })?;
+                    let notifier = notifier(notifier_config).map_err(|reason| {
+                        ConfigError::Invalid(format!("notifier {}: {}", name, reason))
+                    })?;
+                    Ok((name.clone(), notifier))
+                })
+                .collect::<Result<Vec<_>, ConfigError>>()
+        };
+        let notify = find(&config.notify)?;
+        let escalate = find(&config.escalate)?;
+
+        if notify.is_empty() {
+            return Err(ConfigError::Invalid(
+                "alerts need a notifier to notify".to_string(),
+            ));
+        }
+        if config.escalate_after_minutes.is_some() == escalate.is_empty() {
+            return Err(ConfigError::Invalid(
+                "alerts need both escalate_after_minutes and notifiers to escalate to".to_string(),
+            ));
+        }
+
+        Ok(Alerts {
+            config,
+            notify,
+            escalate,
+            episodes: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // Track a reading of the door of `device` taken at `now`, sending the alerts that are due
+    pub fn record(
+        &self,
+        device: &str,
+        state: DoorState,
+        now: DateTime<Utc>,
+        capture: Option<&Capture>,
+    ) {
+        for (alert, notifiers) in self.due(device, state, now, capture) {
+            send(&alert, notifiers);
+        }
+    }
+
+    // The alerts due on a reading and who to send each to
+    fn due(
+        &self,
+        device: &str,
+        state: DoorState,
+        now: DateTime<Utc>,
+        capture: Option<&Capture>,
+    ) -> Vec<(Alert, &[NamedNotifier])> {
+        let mut due = Vec::new();
+        let mut episodes = self.episodes.lock().unwrap_or_else(PoisonError::into_inner);
+
+        match state {
+            DoorState::Unknown => {}
+            DoorState::Closed => {
+                let Some(episode) = episodes.remove(device) else {
+                    return due;
+                };
+                if episode.alerted_at.is_none() {
+                    return due;
+                }
+                let alert = alert(device, AlertEvent::Closed, &episode, now, capture);
+                due.push((alert.clone(), self.notify.as_slice()));
+                if episode.escalated {
+                    due.push((alert, self.escalate.as_slice()));
+                }
+            }
+            DoorState::Open => {
+                let episode = episodes.entry(device.to_string()).or_insert(Episode {
+                    open_since: now,
+                    alerted_at: None,
+                    escalated: false,
+                });
+                let open_for = now - episode.open_since;
+                if open_for < minutes(self.config.open_after_minutes) {
+                    return due;
+                }
+
+                let notify = match episode.alerted_at {
+                    None => true,
+                    Some(alerted_at) => self
+                        .config
+                        .repeat_minutes
+                        .is_some_and(|repeat| now - alerted_at >= minutes(repeat)),
+                };
+                if notify {
+                    episode.alerted_at = Some(now);
+                    due.push((
+                        alert(device, AlertEvent::Open, episode, now, capture),
+                        self.notify.as_slice(),
+                    ));
+                }
+
+                let escalate = self
+                    .config
+                    .escalate_after_minutes
+                    .is_some_and(|escalate_after| open_for >= minutes(escalate_after));
+                if escalate && !episode.escalated {
+                    episode.escalated = true;
+                    due.push((
+                        alert(device, AlertEvent::Escalated, episode, now, capture),
+                        self.escalate.as_slice(),
+                    ));
+                }
+            }
+        }
+        due
+    }
+}
+
+fn alert(
+    device: &str,
+    event: AlertEvent,
+    episode: &Episode,
+    now: DateTime<Utc>,
+    capture: Option<&Capture>,
+) -> Alert {
+    let open_minutes = (now - episode.open_since).num_minutes();
+    let open_for = match open_minutes {
+        1 => "1 minute".to_string(),
+        open_minutes => format!("{} minutes", open_minutes),
+    };
+    let message = match event {
+        AlertEvent::Open => format!("{} door has been open for {}", device, open_for),
+        AlertEvent::Escalated => format!("{} door is still open after {}", device, open_for),
+        AlertEvent::Closed => format!(
+            "{} door was closed after being open for {}",
+            device, open_for
+        ),
+    };
+    Alert {
+        device: device.to_string(),
+        event,
+        open_since: episode.open_since,
+        open_minutes,
+        message,
+        capture_id: capture.map(|capture| capture.id.clone()),
+    }
+}
+
+// Each notification is sent from its own thread, a slow notifier does not hold up captures or the others
+fn send(alert: &Alert, notifiers: &[NamedNotifier]) {
+    for (name, notifier) in notifiers {
+        let (alert, name, notifier) = (alert.clone(), name.clone(), notifier.clone());
+        thread::spawn(move || match notifier.notify(&alert) {
+            Ok(()) => println!(
+                "alert: sent {} alert for {} to {}",
+                alert.event, alert.device, name
+            ),
+            Err(err) => println!(
+                "alert: error: failed to send {} alert for {} to {}: {}",
+                alert.event, alert.device, name, err
+            ),
+        });
+    }
+}
+
+fn minutes(minutes: u64) -> TimeDelta {
+    TimeDelta::minutes(minutes as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn alerts(repeat_minutes: Option<u64>, escalate_after_minutes: Option<u64>) -> Alerts {
+        let config = AlertConfig {
+            open_after_minutes: 15,
+            notify: vec!["phone".to_string()],
+            repeat_minutes,
+            escalate_after_minutes,
+            escalate: match escalate_after_minutes {
+                Some(_) => vec!["pager".to_string()],
+                None => Vec::new(),
+            },
+        };
+        let command = NotifierConfig::Command {
+            command: vec!["true".to_string()],
+        };
+        let notifiers = BTreeMap::from([
+            ("phone".to_string(), command.clone()),
+            ("pager".to_string(), command),
+        ]);
+        Alerts::new(config, &notifiers).unwrap()
+    }
+
+    fn at(minutes: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, 18, 8, 0, 0).unwrap() + TimeDelta::minutes(minutes)
+    }
+
+    // The alerts due on a reading of the garage door `minutes` in, as who is told what
+    fn read(alerts: &Alerts, state: DoorState, minutes: i64) -> Vec<(AlertEvent, &str)> {
+        alerts
+            .due("garage", state, at(minutes), None)
+            .into_iter()
+            .flat_map(|(alert, notifiers)| {
+                notifiers
+                    .iter()
+                    .map(move |(name, _)| (alert.event, name.as_str()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn open_door_is_alerted_once() {
+        let alerts = alerts(None, None);
+        assert!(read(&alerts, DoorState::Open, 0).is_empty());
+        assert!(read(&alerts, DoorState::Open, 14).is_empty());
+        assert_eq!(
+            read(&alerts, DoorState::Open, 15),
+            [(AlertEvent::Open, "phone")]
+        );
+        assert!(read(&alerts, DoorState::Open, 16).is_empty());
+        assert!(read(&alerts, DoorState::Open, 600).is_empty());
+    }
+
+    #[test]
+    fn alert_says_how_long_the_door_is_open() {
+        let alerts = alerts(None, None);
+        alerts.due("garage", DoorState::Open, at(0), None);
+        let due = alerts.due("garage", DoorState::Open, at(20), None);
+
+        let (alert, _) = &due[0];
+        assert_eq!(alert.device, "garage");
+        assert_eq!(alert.open_since, at(0));
+        assert_eq!(alert.open_minutes, 20);
+        assert_eq!(alert.message, "garage door has been open for 20 minutes");
+        assert_eq!(alert.capture_id, None);
+    }
+
+    #[test]
+    fn open_door_is_alerted_again_each_repeat() {
+        let alerts = alerts(Some(10), None);
+        read(&alerts, DoorState::Open, 0);
+        assert_eq!(read(&alerts, DoorState::Open, 15).len(), 1);
+        assert!(read(&alerts, DoorState::Open, 24).is_empty());
+        assert_eq!(
+            read(&alerts, DoorState::Open, 25),
+            [(AlertEvent::Open, "phone")]
+        );
+        // Note: repeats count from the last alert, not from when they were due
+        assert!(read(&alerts, DoorState::Open, 34).is_empty());
+        assert_eq!(read(&alerts, DoorState::Open, 40).len(), 1);
+        assert!(read(&alerts, DoorState::Open, 45).is_empty());
+    }
+
+    #[test]
+    fn open_door_is_escalated_once() {
+        let alerts = alerts(None, Some(30));
+        read(&alerts, DoorState::Open, 0);
+        assert_eq!(
+            read(&alerts, DoorState::Open, 15),
+            [(AlertEvent::Open, "phone")]
+        );
+        assert!(read(&alerts, DoorState::Open, 29).is_empty());
+        assert_eq!(
+            read(&alerts, DoorState::Open, 30),
+            [(AlertEvent::Escalated, "pager")]
+        );
+        assert!(read(&alerts, DoorState::Open, 60).is_empty());
+        assert_eq!(
+            read(&alerts, DoorState::Closed, 61),
+            [(AlertEvent::Closed, "phone"), (AlertEvent::Closed, "pager")]
+        );
+    }
+
+    #[test]
+    fn late_first_reading_alerts_and_escalates_together() {
+        let alerts = alerts(None, Some(30));
+        read(&alerts, DoorState::Open, 0);
+        assert_eq!(
+            read(&alerts, DoorState::Open, 45),
+            [
+                (AlertEvent::Open, "phone"),
+                (AlertEvent::Escalated, "pager")
+            ]
+        );
+    }
+
+    #[test]
+    fn closed_door_resets() {
+        let alerts = alerts(None, Some(30));
+        read(&alerts, DoorState::Open, 0);
+        // Note: nobody was told it was open, so nobody is told it closed
+        assert!(read(&alerts, DoorState::Closed, 10).is_empty());
+        assert!(read(&alerts, DoorState::Open, 12).is_empty());
+        assert!(read(&alerts, DoorState::Open, 26).is_empty());
+        assert_eq!(
+            read(&alerts, DoorState::Open, 27),
+            [(AlertEvent::Open, "phone")]
+        );
+        assert_eq!(
+            read(&alerts, DoorState::Closed, 28),
+            [(AlertEvent::Closed, "phone")]
+        );
+        assert!(read(&alerts, DoorState::Closed, 29).is_empty());
+
+        read(&alerts, DoorState::Open, 30);
+        assert!(read(&alerts, DoorState::Open, 44).is_empty());
+        assert_eq!(read(&alerts, DoorState::Open, 45).len(), 1);
+    }
+
+    #[test]
+    fn unknown_door_changes_nothing() {
+        let alerts = alerts(None, None);
+        assert!(read(&alerts, DoorState::Unknown, 0).is_empty());
+        read(&alerts, DoorState::Open, 5);
+        assert!(read(&alerts, DoorState::Unknown, 25).is_empty());
+        let due = alerts.due("garage", DoorState::Open, at(26), None);
+        assert_eq!(due[0].0.open_since, at(5));
+    }
+
+    #[test]
+    fn devices_are_tracked_apart() {
+        let alerts = alerts(None, None);
+        read(&alerts, DoorState::Open, 0);
+        alerts.due("porch", DoorState::Open, at(10), None);
+        assert_eq!(read(&alerts, DoorState::Open, 15).len(), 1);
+        assert!(alerts
+            .due("porch", DoorState::Open, at(15), None)
+            .is_empty());
+        assert_eq!(alerts.due("porch", DoorState::Open, at(25), None).len(), 1);
+    }
+
+    #[test]
+    fn notifiers_must_be_configured() {
+        let config = AlertConfig {
+            open_after_minutes: 15,
+            notify: vec!["phone".to_string()],
+            repeat_minutes: None,
+            escalate_after_minutes: None,
+            escalate: Vec::new(),
+        };
+        assert!(Alerts::new(config.clone(), &BTreeMap::new()).is_err());
+
+        let notifiers = BTreeMap::from([(
+            "phone".to_string(),
+            NotifierConfig::Command {
+                command: vec!["true".to_string()],
+            },
+        )]);
+        let escalate_to_nobody = AlertConfig {
+            escalate_after_minutes: Some(30),
+            ..config
+        };
+        assert!(Alerts::new(escalate_to_nobody, &notifiers).is_err());
+    }
+}
@@ -0,0 +1,517 @@
+use std::io::{self, Cursor, Read};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+
+use protocol::{
+    AccessPointMode, CameraStatus, DeviceSettings, ErrorCode, FrameSize, JpegQuality, PixelFormat,
+    WifiConfig, WifiCredentials, DEFAULT_PORT,
+};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+
+use crate::archive::{Archive, Capture, Trigger};
+use crate::client::{ClientError, Device};
+use crate::json;
+use crate::pipeline::Pipeline;
+use crate::registry::Registry;
+
+// Requests are handled concurrently, requests to each device are serialized by its `Device`
+const WORKERS: usize = 4;
+// Settings bodies are tiny, anything larger is a mistake
+const MAX_BODY_LEN: u64 = 16 * 1024;
+// Captures listed by /captures unless a limit is given
+const DEFAULT_LIST_LIMIT: usize = 50;
+
+type HttpResponse = Response<Cursor<Vec<u8>>>;
+
+// Error answered with a JSON body, eg. `{"error": "device unreachable: ..."}`
+#[derive(Debug)]
+pub struct ApiError {
+    status: u16,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: u16, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<ClientError> for ApiError {
+    fn from(err: ClientError) -> Self {
+        let status = match &err {
+            ClientError::Unreachable(_)
+            | ClientError::Io(_)
+            | ClientError::BadResponse(_)
+            | ClientError::Session(_) => 502,
+            ClientError::NoKey => 500,
+            ClientError::Timeout => 504,
+            ClientError::Device { code, .. } => match code {
+                ErrorCode::UnsupportedOption => 422,
+                ErrorCode::Busy => 503,
+                _ => 502,
+            },
+        };
+        ApiError::new(status, err.to_string())
+    }
+}
+
+// What the requests are served from
+struct Context {
+    registry: Arc<Registry>,
+    pipeline: Arc<Pipeline>,
+    // Required by every request when set, see `authorize`
+    api_token: Option<String>,
+}
+
+// Serve the HTTP API for the app on `addr` (eg. "0.0.0.0:8000")
+pub fn serve(
+    addr: &str,
+    registry: Arc<Registry>,
+    pipeline: Arc<Pipeline>,
+    api_token: Option<String>,
+) -> io::Result<()> {
+    let server = Arc::new(Server::http(addr).map_err(io::Error::other)?);
+    println!("api: listening on {}", addr);
+    for device in registry.devices() {
+        match device.addr() {
+            Some(addr) => println!("api: device {} at {}", device.name(), addr),
+            None => println!("api: device {} waiting for discovery", device.name()),
+        }
+    }
+
+    let context = Arc::new(Context {
+        registry,
+        pipeline,
+        api_token,
+    });
+    let workers: Vec<_> = (0..WORKERS)
+        .map(|_| {
+            let server = server.clone();
+            let context = context.clone();
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    handle_request(request, &context);
+                }
+            })
+        })
+        .collect();
+
+    for worker in workers {
+        let _ = worker.join();
+    }
+    Ok(())
+}
+
+fn handle_request(mut request: Request, context: &Context) {
+    let method = request.method().clone();
+    let url = request.url().to_string();
+
+    let response = route(&mut request, context).unwrap_or_else(|err| {
+        println!("api: error: {} {}: {}", method, url, err.message);
+        json_response(err.status, &json::error(&err.message))
+    });
+    println!("api: {} {} -> {}", method, url, response.status_code().0);
+
+    if let Err(err) = request.respond(response) {
+        println!("api: error writing response: {}", err);
+    }
+}
+
+// Devices are addressed by name, eg. /devices/garage/capture, and captures by id, eg. /captures/garage-<time>
+fn route(request: &mut Request, context: &Context) -> Result<HttpResponse, ApiError> {
+    let registry = &context.registry;
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let segments: Vec<&str> = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+
+    // Note: reads need the token too, captures take pictures and serve images of the premises
+    authorize(request, context.api_token.as_deref())?;
+
+    match (request.method(), segments.as_slice()) {
+        (Method::Get, ["devices"]) => {
+            let devices: Vec<Value> = registry
+                .devices()
+                .iter()
+                .map(|device| json::device(device))
+                .collect();
+            Ok(json_response(200, &Value::Array(devices)))
+        }
+        (_, ["devices"]) => Err(ApiError::new(405, "method not allowed")),
+        (Method::Get, ["devices", name]) => {
+            let device = find(registry, name)?;
+            Ok(json_response(200, &json::device(&device)))
+        }
+        (_, ["devices", _]) => Err(ApiError::new(405, "method not allowed")),
+        (_, ["devices", name, endpoint]) => {
+            let device = find(registry, name)?;
+            route_device(request, &device, endpoint, &context.pipeline)
+        }
+        (_, ["captures", ..]) => {
+            let archive = context
+                .pipeline
+                .archive()
+                .ok_or_else(|| ApiError::new(404, "captures are not archived, see --archive"))?;
+            route_captures(request.method(), &segments[1..], query, archive)
+        }
+        _ => Err(ApiError::new(404, format!("no such endpoint: {}", path))),
+    }
+}
+
+// Check the request's `Authorization: Bearer <token>` header against the API token, if one is set
+fn authorize(request: &Request, api_token: Option<&str>) -> Result<(), ApiError> {
+    let Some(api_token) = api_token else {
+        return Ok(());
+    };
+    let token = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Authorization"))
+        .and_then(|header| header.value.as_str().strip_prefix("Bearer "))
+        .ok_or_else(|| {
+            ApiError::new(401, "missing API token, send Authorization: Bearer <token>")
+        })?;
+
+    // Note: compared in constant time so the token can not be guessed byte by byte
+    let matches = token.len() == api_token.len()
+        && token
+            .bytes()
+            .zip(api_token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0;
+    match matches {
+        true => Ok(()),
+        false => Err(ApiError::new(401, "invalid API token")),
+    }
+}
+
+fn find(registry: &Registry, name: &str) -> Result<Arc<Device>, ApiError> {
+    registry
+        .get(name)
+        .ok_or_else(|| ApiError::new(404, format!("no such device: {}", name)))
+}
+
+fn route_device(
+    request: &mut Request,
+    device: &Device,
+    endpoint: &str,
+    pipeline: &Pipeline,
+) -> Result<HttpResponse, ApiError> {
+    match (request.method(), endpoint) {
+        (Method::Get, "capture") => capture(device, pipeline),
+        (Method::Get, "door-state") => door_state(device, pipeline),
+        (Method::Get, "status") => Ok(json_response(200, &json::status(&device.status()?))),
+        (Method::Get, "frame-size") => {
+            let camera = camera_status(device)?;
+            Ok(json_response(200, &frame_size_json(camera.frame_size)))
+        }
+        (Method::Put, "frame-size") => {
+            let frame_size: FrameSize = parse_field(&read_json(request)?, "frame_size")?;
+            device.set_frame_size(frame_size)?;
+            Ok(json_response(200, &frame_size_json(frame_size)))
+        }
+        (Method::Get, "pixel-format") => {
+            let camera = camera_status(device)?;
+            Ok(json_response(
+                200,
+                &json!({ "pixel_format": camera.pixel_format.name() }),
+            ))
+        }
+        (Method::Put, "pixel-format") => {
+            let pixel_format: PixelFormat = parse_field(&read_json(request)?, "pixel_format")?;
+            device.set_pixel_format(pixel_format)?;
+            Ok(json_response(
+                200,
+                &json!({ "pixel_format": pixel_format.name() }),
+            ))
+        }
+        (Method::Get, "quality") => {
+            let camera = camera_status(device)?;
+            Ok(json_response(
+                200,
+                &json!({ "jpeg_quality": camera.jpeg_quality.get() }),
+            ))
+        }
+        (Method::Put, "quality") => {
+            let jpeg_quality: JpegQuality = parse_field(&read_json(request)?, "jpeg_quality")?;
+            device.set_jpeg_quality(jpeg_quality)?;
+            Ok(json_response(
+                200,
+                &json!({ "jpeg_quality": jpeg_quality.get() }),
+            ))
+        }
+        (Method::Post, "restart") => {
+            device.restart()?;
+            Ok(json_response(202, &json!({ "restarting": true })))
+        }
+        (Method::Put, "wifi") => {
+            let body = read_json(request)?;
+            let ssid = body
+                .get("ssid")
+                .and_then(Value::as_str)
+                .ok_or_else(|| ApiError::new(400, "missing field: ssid"))?;
+            let password = body.get("password").and_then(Value::as_str).unwrap_or("");
+            let credentials = WifiCredentials::new(ssid, password)
+                .ok_or_else(|| ApiError::new(422, "invalid ssid or password"))?;
+            device.set_wifi(&credentials)?;
+            Ok(json_response(
+                202,
+                &json!({ "ssid": ssid, "restarting": true }),
+            ))
+        }
+        (Method::Put, "wifi-config") => {
+            let body = read_json(request)?;
+            let access_point: AccessPointMode = parse_field(&body, "access_point")?;
+            let station = body.get("station").and_then(Value::as_bool).unwrap_or(true);
+            let channel = match body.get("channel") {
+                Some(_) => parse_field(&body, "channel")?,
+                None => 1,
+            };
+            let ssid = body.get("ssid").and_then(Value::as_str).unwrap_or("");
+            let password = body.get("password").and_then(Value::as_str).unwrap_or("");
+            let config = WifiConfig::new(station, access_point, channel, ssid, password)
+                .map_err(|err| ApiError::new(422, err.to_string()))?;
+            device.set_wifi_config(&config)?;
+            Ok(json_response(
+                202,
+                &json!({
+                    "station": station,
+                    "access_point": access_point.name(),
+                    "channel": channel,
+                    "restarting": true,
+                }),
+            ))
+        }
+        (Method::Put, "settings") => {
+            let body = read_json(request)?;
+            let name = body.get("name").and_then(Value::as_str).unwrap_or("");
+            let port = match body.get("port") {
+                Some(_) => parse_field(&body, "port")?,
+                None => DEFAULT_PORT,
+            };
+            let persist_camera = body
+                .get("persist_camera")
+                .and_then(Value::as_bool)
+                .unwrap_or(true);
+            let settings = DeviceSettings::new(name, port, persist_camera)
+                .map_err(|err| ApiError::new(422, err.to_string()))?;
+            device.set_device_settings(&settings)?;
+            Ok(json_response(
+                202,
+                &json!({
+                    "name": name,
+                    "port": port,
+                    "persist_camera": persist_camera,
+                    "restarting": true,
+                }),
+            ))
+        }
+        (Method::Post, "factory-reset") => {
+            device.factory_reset()?;
+            Ok(json_response(202, &json!({ "restarting": true })))
+        }
+        (
+            _,
+            "capture" | "door-state" | "status" | "frame-size" | "pixel-format" | "quality"
+            | "restart" | "wifi" | "wifi-config" | "settings" | "factory-reset",
+        ) => Err(ApiError::new(405, "method not allowed")),
+        _ => Err(ApiError::new(
+            404,
+            format!("no such endpoint: /devices/{}/{}", device.name(), endpoint),
+        )),
+    }
+}
+
+fn route_captures(
+    method: &Method,
+    segments: &[&str],
+    query: &str,
+    archive: &Archive,
+) -> Result<HttpResponse, ApiError> {
+    match (method, segments) {
+        (Method::Get, []) => {
+            let limit = match query_param(query, "limit") {
+                Some(limit) => limit
+                    .parse()
+                    .map_err(|_| ApiError::new(422, format!("invalid limit: {}", limit)))?,
+                None => DEFAULT_LIST_LIMIT,
+            };
+            let captures: Vec<Value> = archive
+                .list(
+                    query_param(query, "device"),
+                    query_param(query, "before"),
+                    limit,
+                )
+                .iter()
+                .map(json::capture)
+                .collect();
+            Ok(json_response(200, &Value::Array(captures)))
+        }
+        (Method::Get, [id]) => Ok(json_response(
+            200,
+            &json::capture(&find_capture(archive, id)?),
+        )),
+        (Method::Delete, [id]) => match archive.delete(id) {
+            Ok(true) => Ok(json_response(200, &json!({ "deleted": id }))),
+            Ok(false) => Err(ApiError::new(404, format!("no such capture: {}", id))),
+            Err(err) => Err(ApiError::new(
+                500,
+                format!("failed to delete {}: {}", id, err),
+            )),
+        },
+        (Method::Get, [id, "image"]) => {
+            let capture = find_capture(archive, id)?;
+            let data = archive.read(&capture).map_err(|err| {
+                ApiError::new(500, format!("failed to read {}: {}", capture.id, err))
+            })?;
+            Ok(Response::from_data(data)
+                .with_header(header(
+                    "Content-Type",
+                    image_content_type(capture.is_jpeg()),
+                ))
+                .with_header(header("X-Image-Width", &capture.width.to_string()))
+                .with_header(header("X-Image-Height", &capture.height.to_string()))
+                .with_header(header("X-Image-Pixel-Format", &capture.pixel_format)))
+        }
+        (_, [] | [_] | [_, "image"]) => Err(ApiError::new(405, "method not allowed")),
+        _ => Err(ApiError::new(
+            404,
+            format!("no such endpoint: /captures/{}", segments.join("/")),
+        )),
+    }
+}
+
+fn find_capture(archive: &Archive, id: &str) -> Result<Capture, ApiError> {
+    archive
+        .get(id)
+        .ok_or_else(|| ApiError::new(404, format!("no such capture: {}", id)))
+}
+
+// JPEG captures are sent as is, raw formats as bytes described by the X-Image-* headers
+fn capture(device: &Device, pipeline: &Pipeline) -> Result<HttpResponse, ApiError> {
+    let observed = pipeline.capture(device, Trigger::Api)?;
+    let info = observed.info;
+
+    let mut response = Response::from_data(observed.data)
+        .with_header(header(
+            "Content-Type",
+            image_content_type(info.pixel_format == PixelFormat::JPEG),
+        ))
+        .with_header(header("X-Image-Width", &info.width.to_string()))
+        .with_header(header("X-Image-Height", &info.height.to_string()))
+        .with_header(header("X-Image-Pixel-Format", info.pixel_format.name()))
+        .with_header(header(
+            "X-Image-Timestamp-Us",
+            &info.timestamp_us.to_string(),
+        ));
+    if let Some(capture) = observed.capture {
+        response.add_header(header("X-Capture-Id", &capture.id));
+    }
+    if let Some(Ok(door)) = observed.door {
+        response.add_header(header("X-Door-State", door.state.name()));
+        response.add_header(header("X-Door-Confidence", &door.confidence.to_string()));
+    }
+    Ok(response)
+}
+
+// Classify a new capture, which goes through the pipeline like any other
+fn door_state(device: &Device, pipeline: &Pipeline) -> Result<HttpResponse, ApiError> {
+    let no_door = || {
+        ApiError::new(
+            404,
+            format!("no door configured for device {}", device.name()),
+        )
+    };
+    if device.door().is_none() {
+        return Err(no_door());
+    }
+    let observed = pipeline.capture(device, Trigger::Api)?;
+    match observed.door {
+        Some(Ok(door)) => Ok(json_response(
+            200,
+            &json::door_state(&door, observed.capture.as_ref()),
+        )),
+        Some(Err(err)) => Err(ApiError::new(
+            502,
+            format!("failed to classify capture: {}", err),
+        )),
+        None => Err(no_door()),
+    }
+}
+
+fn image_content_type(jpeg: bool) -> &'static str {
+    match jpeg {
+        true => "image/jpeg",
+        false => "application/octet-stream",
+    }
+}
+
+// Current camera settings, which the device only reports while its camera is working
+fn camera_status(device: &Device) -> Result<CameraStatus, ApiError> {
+    device.status()?.camera.ok_or_else(|| {
+        ApiError::new(
+            503,
+            format!(
+                "camera not available, see /devices/{}/status",
+                device.name()
+            ),
+        )
+    })
+}
+
+fn frame_size_json(frame_size: FrameSize) -> Value {
+    json!({
+        "frame_size": frame_size.name(),
+        "width": frame_size.width(),
+        "height": frame_size.height(),
+    })
+}
+
+fn read_json(request: &mut Request) -> Result<Value, ApiError> {
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(MAX_BODY_LEN)
+        .read_to_end(&mut body)
+        .map_err(|err| ApiError::new(400, format!("failed to read body: {}", err)))?;
+    serde_json::from_slice(&body)
+        .map_err(|err| ApiError::new(400, format!("invalid JSON body: {}", err)))
+}
+
+// Parse `field` of a JSON object, given as a string (eg. "SVGA") or a number (eg. 12)
+fn parse_field<T: FromStr>(body: &Value, field: &str) -> Result<T, ApiError> {
+    let value = match body.get(field) {
+        Some(Value::String(value)) => value.clone(),
+        Some(Value::Number(value)) => value.to_string(),
+        _ => return Err(ApiError::new(400, format!("missing field: {}", field))),
+    };
+    value
+        .parse()
+        .map_err(|_| ApiError::new(422, format!("invalid {}: {}", field, value)))
+}
+
+// Value of `key` in a query string, eg. "device=garage&limit=10"
+fn query_param<'a>(query: &'a str, key: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(name, _)| *name == key)
+        .map(|(_, value)| value)
+}
+
+fn json_response(status: u16, body: &Value) -> HttpResponse {
+    Response::from_data(body.to_string())
+        .with_status_code(StatusCode(status))
+        .with_header(header("Content-Type", "application/json"))
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name, value).expect("header names and values are ASCII")
+}
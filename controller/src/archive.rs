@@ -0,0 +1,550 @@
+// Captures stored with their metadata, in a directory per device:
+//
+// <path>/<device>/<time>.jpg   image as captured, .raw for raw pixel formats
+// <path>/<device>/<time>.json  metadata, written last so only complete captures are listed
+//
+// eg. archive/garage/20261018T112055123Z.jpg, listed as capture "garage-20261018T112055123Z"
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, SubsecRound, TimeDelta, Utc};
+use protocol::{FrameSize, ImageInfo, JpegQuality, PixelFormat};
+use serde::{Deserialize, Serialize};
+
+use crate::door::Classification;
+
+const TIME_FORMAT: &str = "%Y%m%dT%H%M%S%3fZ";
+
+// What asked for a capture
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Trigger {
+    Api,
+    Cli,
+    Schedule,
+}
+
+// Oldest captures are removed first until all limits hold, limits that are not set do not apply
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Retention {
+    pub max_age_days: Option<u64>,
+    // Across all devices
+    pub max_count: Option<usize>,
+    pub max_size_mb: Option<u64>,
+}
+
+// Metadata of a stored capture
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Capture {
+    pub id: String,
+    pub device: String,
+    pub time: DateTime<Utc>,
+    pub width: u16,
+    pub height: u16,
+    // Name of the frame size with these dimensions, if any
+    pub frame_size: Option<String>,
+    pub pixel_format: String,
+    // Quality the camera was last known to use, only for JPEG captures
+    pub jpeg_quality: Option<u8>,
+    pub trigger: Trigger,
+    pub size_bytes: u64,
+    // Only for devices with a door configured
+    pub door: Option<Classification>,
+}
+
+impl Capture {
+    pub fn is_jpeg(&self) -> bool {
+        self.pixel_format == PixelFormat::JPEG.name()
+    }
+
+    fn stem(&self) -> String {
+        self.time.format(TIME_FORMAT).to_string()
+    }
+
+    fn extension(&self) -> &'static str {
+        match self.is_jpeg() {
+            true => "jpg",
+            false => "raw",
+        }
+    }
+}
+
+pub struct Archive {
+    path: PathBuf,
+    retention: Retention,
+    // Oldest first
+    index: Mutex<Vec<Capture>>,
+}
+
+impl Archive {
+    pub fn open(path: &Path, retention: Retention) -> io::Result<Self> {
+        fs::create_dir_all(path)?;
+        let archive = Archive {
+            path: path.to_path_buf(),
+            retention,
+            index: Mutex::new(Vec::new()),
+        };
+        archive.refresh()?;
+        Ok(archive)
+    }
+
+    pub fn store(
+        &self,
+        device: &str,
+        info: &ImageInfo,
+        jpeg_quality: Option<JpegQuality>,
+        trigger: Trigger,
+        door: Option<&Classification>,
+        data: &[u8],
+    ) -> io::Result<Capture> {
+        let mut index = self.lock_index();
+
+        let frame_size = FrameSize::iter()
+            .find(|frame_size| {
+                frame_size.dimensions() == (u32::from(info.width), u32::from(info.height))
+            })
+            .map(|frame_size| frame_size.name().to_string());
+        let mut capture = Capture {
+            id: String::new(),
+            device: device.to_string(),
+            time: Utc::now().trunc_subsecs(3),
+            width: info.width,
+            height: info.height,
+            frame_size,
+            pixel_format: info.pixel_format.name().to_string(),
+            jpeg_quality: jpeg_quality
+                .filter(|_| info.pixel_format == PixelFormat::JPEG)
+                .map(|jpeg_quality| jpeg_quality.get()),
+            trigger,
+            size_bytes: data.len() as u64,
+            door: door.cloned(),
+        };
+
+        // Note: captures of a device are at least 1ms apart so their names do not clash, files
+        // are created new so neither do those of another process sharing the archive
+        fs::create_dir_all(self.path.join(device))?;
+        let mut image = loop {
+            let taken = index
+                .iter()
+                .any(|other| other.device == device && other.time == capture.time);
+            if !taken {
+                match OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(self.image_path(&capture))
+                {
+                    Ok(image) => break image,
+                    Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+                    Err(err) => return Err(err),
+                }
+            }
+            capture.time += TimeDelta::milliseconds(1);
+        };
+        capture.id = format!("{}-{}", device, capture.stem());
+
+        if let Err(err) = image.write_all(data) {
+            let _ = fs::remove_file(self.image_path(&capture));
+            return Err(err);
+        }
+        let metadata_path = self.metadata_path(&capture);
+        let tmp_path = metadata_path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec_pretty(&capture)?)?;
+        fs::rename(&tmp_path, &metadata_path)?;
+
+        index.push(capture.clone());
+        self.enforce_locked(&mut index);
+        Ok(capture)
+    }
+
+    // Newest first, optionally only of `device` and older than the capture `before`
+    pub fn list(&self, device: Option<&str>, before: Option<&str>, limit: usize) -> Vec<Capture> {
+        let index = self.lock_index();
+        let end = match before {
+            Some(before) => match index.iter().position(|capture| capture.id == before) {
+                Some(position) => position,
+                None => return Vec::new(),
+            },
+            None => index.len(),
+        };
+        index[..end]
+            .iter()
+            .rev()
+            .filter(|capture| device.is_none_or(|device| capture.device == device))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    pub fn get(&self, id: &str) -> Option<Capture> {
+        self.lock_index()
+            .iter()
+            .find(|capture| capture.id == id)
+            .cloned()
+    }
+
+    pub fn read(&self, capture: &Capture) -> io::Result<Vec<u8>> {
+        fs::read(self.image_path(capture))
+    }
+
+    // Returns false if there is no such capture
+    pub fn delete(&self, id: &str) -> io::Result<bool> {
+        let mut index = self.lock_index();
+        let Some(position) = index.iter().position(|capture| capture.id == id) else {
+            return Ok(false);
+        };
+        // Note: the entry stays if the files cannot be removed, so the delete can be retried
+        self.remove_files(&index[position])?;
+        index.remove(position);
+        Ok(true)
+    }
+
+    // Reload the index from disk, picking up captures stored by other processes (eg. camctl), and apply retention
+    pub fn refresh(&self) -> io::Result<()> {
+        // Note: locked while reading, a capture stored meanwhile would be missing from the index
+        let mut index = self.lock_index();
+        let mut captures = Vec::new();
+        for device_dir in fs::read_dir(&self.path)? {
+            let device_dir = device_dir?.path();
+            if !device_dir.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(&device_dir)? {
+                let path = entry?.path();
+                if path.extension().is_none_or(|extension| extension != "json") {
+                    continue;
+                }
+                match read_metadata(&path) {
+                    Ok(capture) if self.image_path(&capture).exists() => captures.push(capture),
+                    Ok(_) => println!("archive: {} has no image, skipping", path.display()),
+                    Err(err) => println!("archive: skipping {}: {}", path.display(), err),
+                }
+            }
+        }
+        captures.sort_by(|a, b| a.time.cmp(&b.time).then_with(|| a.id.cmp(&b.id)));
+
+        *index = captures;
+        self.enforce_locked(&mut index);
+        Ok(())
+    }
+
+    fn enforce_locked(&self, index: &mut Vec<Capture>) {
+        let retention = &self.retention;
+        let oldest_kept = retention.max_age_days.map(|days| {
+            // Note: an age beyond what a date can hold keeps everything
+            i64::try_from(days)
+                .ok()
+                .and_then(TimeDelta::try_days)
+                .and_then(|max_age| Utc::now().checked_sub_signed(max_age))
+                .unwrap_or(DateTime::<Utc>::MIN_UTC)
+        });
+        let max_size = retention.max_size_mb.map(|mb| mb * 1024 * 1024);
+
+        let mut count = index.len();
+        let mut size: u64 = index.iter().map(|capture| capture.size_bytes).sum();
+        let expired = index
+            .iter()
+            .take_while(|capture| {
+                let expired = oldest_kept.is_some_and(|oldest_kept| capture.time < oldest_kept)
+                    || retention
+                        .max_count
+                        .is_some_and(|max_count| count > max_count)
+                    || max_size.is_some_and(|max_size| size > max_size);
+                if expired {
+                    count -= 1;
+                    size -= capture.size_bytes;
+                }
+                expired
+            })
+            .count();
+
+        for capture in index.drain(..expired) {
+            if let Err(err) = self.remove_files(&capture) {
+                println!("archive: failed to remove {}: {}", capture.id, err);
+            }
+        }
+        if expired > 0 {
+            println!("archive: removed {} captures past retention", expired);
+        }
+    }
+
+    fn remove_files(&self, capture: &Capture) -> io::Result<()> {
+        // Note: the metadata goes first, an image left behind is not listed
+        fs::remove_file(self.metadata_path(capture))?;
+        match fs::remove_file(self.image_path(capture)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    fn image_path(&self, capture: &Capture) -> PathBuf {
+        self.path
+            .join(&capture.device)
+            .join(format!("{}.{}", capture.stem(), capture.extension()))
+    }
+
+    fn metadata_path(&self, capture: &Capture) -> PathBuf {
+        self.path
+            .join(&capture.device)
+            .join(format!("{}.json", capture.stem()))
+    }
+
+    fn lock_index(&self) -> MutexGuard<'_, Vec<Capture>> {
+        self.index.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn read_metadata(path: &Path) -> io::Result<Capture> {
+    Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+// Refresh the archive each `interval` in a background thread, so captures also expire when none are taken
+pub fn maintain(archive: Arc<Archive>, interval: Duration) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        if let Err(err) = archive.refresh() {
+            println!("archive: error: {}", err);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    // Directory removed when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let path = std::env::temp_dir().join(format!(
+                "archive-test-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn info() -> ImageInfo {
+        ImageInfo {
+            width: 320,
+            height: 240,
+            pixel_format: PixelFormat::JPEG,
+            timestamp_us: 0,
+        }
+    }
+
+    fn store(archive: &Archive, device: &str, data: &[u8]) -> Capture {
+        archive
+            .store(
+                device,
+                &info(),
+                JpegQuality::new(12),
+                Trigger::Cli,
+                None,
+                data,
+            )
+            .unwrap()
+    }
+
+    // Writes a capture taken `age` ago the way another process would
+    fn store_aged(path: &Path, device: &str, age: TimeDelta, size: usize) {
+        let archive = Archive::open(path, Retention::default()).unwrap();
+        let mut capture = store(&archive, device, &vec![0; size]);
+        let metadata_path = archive.metadata_path(&capture);
+        fs::rename(archive.image_path(&capture), path.join("image")).unwrap();
+        fs::remove_file(metadata_path).unwrap();
+
+        capture.time = (Utc::now() - age).trunc_subsecs(3);
+        capture.id = format!("{}-{}", device, capture.stem());
+        fs::rename(path.join("image"), archive.image_path(&capture)).unwrap();
+        let metadata = serde_json::to_vec(&capture).unwrap();
+        fs::write(archive.metadata_path(&capture), metadata).unwrap();
+    }
+
+    fn ids(archive: &Archive) -> Vec<String> {
+        archive
+            .list(None, None, usize::MAX)
+            .into_iter()
+            .map(|capture| capture.id)
+            .collect()
+    }
+
+    #[test]
+    fn stored_capture_is_listed_and_read() {
+        let dir = TempDir::new();
+        let archive = Archive::open(&dir.0, Retention::default()).unwrap();
+        let capture = store(&archive, "garage", b"jpeg");
+
+        assert!(capture.id.starts_with("garage-"));
+        assert_eq!(capture.frame_size.as_deref(), Some("QVGA"));
+        assert_eq!(capture.jpeg_quality, Some(12));
+        assert_eq!(capture.size_bytes, 4);
+        assert_eq!(archive.get(&capture.id), Some(capture.clone()));
+        assert_eq!(
+            archive.list(Some("garage"), None, 10),
+            vec![capture.clone()]
+        );
+        assert!(archive.list(Some("porch"), None, 10).is_empty());
+        assert_eq!(archive.read(&capture).unwrap(), b"jpeg");
+    }
+
+    #[test]
+    fn captures_do_not_overwrite_each_other() {
+        let dir = TempDir::new();
+        // Note: two archives on one directory, as with the controller and camctl
+        let first = Archive::open(&dir.0, Retention::default()).unwrap();
+        let second = Archive::open(&dir.0, Retention::default()).unwrap();
+        for n in 0..10u8 {
+            store(&first, "garage", &[n, 0]);
+            store(&second, "garage", &[n, 1]);
+        }
+
+        first.refresh().unwrap();
+        let captures = first.list(None, None, usize::MAX);
+        assert_eq!(captures.len(), 20);
+        let mut data: Vec<_> = captures
+            .iter()
+            .map(|capture| first.read(capture).unwrap())
+            .collect();
+        data.sort();
+        data.dedup();
+        assert_eq!(data.len(), 20);
+    }
+
+    #[test]
+    fn list_is_newest_first_and_pages() {
+        let dir = TempDir::new();
+        let archive = Archive::open(&dir.0, Retention::default()).unwrap();
+        let captures: Vec<_> = (0..3).map(|_| store(&archive, "garage", b"jpeg")).collect();
+
+        let ids = ids(&archive);
+        assert_eq!(
+            ids,
+            [
+                captures[2].id.as_str(),
+                captures[1].id.as_str(),
+                captures[0].id.as_str()
+            ]
+        );
+        let page = archive.list(None, Some(&captures[2].id), 1);
+        assert_eq!(page, vec![captures[1].clone()]);
+        assert!(archive.list(None, Some("garage-unknown"), 10).is_empty());
+    }
+
+    #[test]
+    fn delete_removes_files() {
+        let dir = TempDir::new();
+        let archive = Archive::open(&dir.0, Retention::default()).unwrap();
+        let capture = store(&archive, "garage", b"jpeg");
+
+        assert!(archive.delete(&capture.id).unwrap());
+        assert!(!archive.delete(&capture.id).unwrap());
+        assert_eq!(archive.get(&capture.id), None);
+        assert!(!archive.image_path(&capture).exists());
+        assert!(!archive.metadata_path(&capture).exists());
+    }
+
+    #[test]
+    fn delete_keeps_entry_when_files_remain() {
+        let dir = TempDir::new();
+        let archive = Archive::open(&dir.0, Retention::default()).unwrap();
+        let capture = store(&archive, "garage", b"jpeg");
+        fs::remove_file(archive.metadata_path(&capture)).unwrap();
+
+        assert!(archive.delete(&capture.id).is_err());
+        assert_eq!(archive.get(&capture.id), Some(capture));
+    }
+
+    #[test]
+    fn refresh_picks_up_and_drops_captures() {
+        let dir = TempDir::new();
+        let archive = Archive::open(&dir.0, Retention::default()).unwrap();
+        let other = Archive::open(&dir.0, Retention::default()).unwrap();
+        let kept = store(&other, "porch", b"jpeg");
+        let incomplete = store(&other, "garage", b"jpeg");
+        fs::remove_file(other.image_path(&incomplete)).unwrap();
+        fs::write(dir.0.join("porch").join("broken.json"), b"{").unwrap();
+
+        assert!(ids(&archive).is_empty());
+        archive.refresh().unwrap();
+        assert_eq!(ids(&archive), [kept.id]);
+    }
+
+    #[test]
+    fn retention_keeps_newest_by_count() {
+        let dir = TempDir::new();
+        let retention = Retention {
+            max_count: Some(2),
+            ..Retention::default()
+        };
+        let archive = Archive::open(&dir.0, retention).unwrap();
+        let first = store(&archive, "garage", b"jpeg");
+        let second = store(&archive, "porch", b"jpeg");
+        let third = store(&archive, "garage", b"jpeg");
+
+        assert_eq!(ids(&archive), [third.id.as_str(), second.id.as_str()]);
+        assert!(!archive.image_path(&first).exists());
+        assert!(!archive.metadata_path(&first).exists());
+    }
+
+    #[test]
+    fn retention_keeps_newest_by_size() {
+        let dir = TempDir::new();
+        let retention = Retention {
+            max_size_mb: Some(1),
+            ..Retention::default()
+        };
+        let archive = Archive::open(&dir.0, retention).unwrap();
+        let first = store(&archive, "garage", &vec![0; 400 * 1024]);
+        let second = store(&archive, "garage", &vec![0; 400 * 1024]);
+        assert_eq!(ids(&archive), [second.id.as_str(), first.id.as_str()]);
+
+        let third = store(&archive, "garage", &vec![0; 400 * 1024]);
+        assert_eq!(ids(&archive), [third.id.as_str(), second.id.as_str()]);
+    }
+
+    #[test]
+    fn retention_removes_old_captures() {
+        let dir = TempDir::new();
+        store_aged(&dir.0, "garage", TimeDelta::days(10), 4);
+        store_aged(&dir.0, "garage", TimeDelta::days(2), 4);
+        let retention = Retention {
+            max_age_days: Some(7),
+            ..Retention::default()
+        };
+        let archive = Archive::open(&dir.0, retention).unwrap();
+
+        let captures = archive.list(None, None, usize::MAX);
+        assert_eq!(captures.len(), 1);
+        assert!(captures[0].time > Utc::now() - TimeDelta::days(3));
+    }
+
+    #[test]
+    fn retention_with_huge_age_keeps_everything() {
+        let dir = TempDir::new();
+        store_aged(&dir.0, "garage", TimeDelta::days(10_000), 4);
+        for max_age_days in [u64::MAX, i64::MAX as u64, 1 << 40] {
+            let retention = Retention {
+                max_age_days: Some(max_age_days),
+                ..Retention::default()
+            };
+            let archive = Archive::open(&dir.0, retention).unwrap();
+            assert_eq!(ids(&archive).len(), 1);
+        }
+    }
+}
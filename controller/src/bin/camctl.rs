@@ -0,0 +1,603 @@
+// Command line tool talking to a device directly, eg. `camctl --device garage capture`
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use clap::{Parser, Subcommand};
+use controller::{
+    Archive, ArchiveConfig, ClientError, Config, ConfigError, Device, DeviceConfig, Discovery,
+    DoorError, Pipeline, Registry, Trigger,
+};
+use protocol::{
+    AccessPointMode, DeviceSettings, DeviceStatus, FrameSize, JpegQuality, ParseError, PixelFormat,
+    PresharedKey, SensorModel, WifiConfig, WifiCredentials, DEFAULT_PORT,
+};
+
+// Captures are saved here unless a file is given
+const CAPTURES_DIR: &str = "captures";
+// How long to browse for a device given by name without an address
+const DISCOVERY_WAIT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Parser)]
+#[command(about = "Control an ESP32 camera device")]
+struct Args {
+    #[arg(
+        long,
+        env = "CONTROLLER_CONFIG",
+        help = "Config file naming the devices"
+    )]
+    config: Option<PathBuf>,
+    #[arg(
+        long,
+        env = "BOARD_IP",
+        help = "Name of a configured device, or its address (the port defaults to 8080)"
+    )]
+    device: Option<String>,
+    #[arg(
+        long,
+        env = "DEVICE_KEY",
+        hide_env_values = true,
+        help = "Key to authenticate with the device, as hex, overrides the config's"
+    )]
+    key: Option<PresharedKey>,
+    #[arg(
+        long,
+        default_value_t = 10_000,
+        help = "Time allowed for connecting to the device and for each request"
+    )]
+    timeout_ms: u64,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum Command {
+    #[command(
+        about = "Capture an image, stored in the config's archive or saved as captures/<timestamp>.jpg by default"
+    )]
+    Capture {
+        #[arg(short, long, help = "File to save the image to, - for stdout")]
+        output: Option<PathBuf>,
+    },
+    #[command(about = "Set the frame size, eg. SVGA or 800x600")]
+    SetFrameSize { frame_size: FrameSize },
+    #[command(about = "Set the pixel format: JPEG, RGB565, YUV422 or GRAYSCALE")]
+    SetPixelFormat { pixel_format: PixelFormat },
+    #[command(about = "Set the JPEG quality, from 0 (best) to 63")]
+    SetQuality { jpeg_quality: JpegQuality },
+    #[command(about = "Show the device and camera status")]
+    Status,
+    #[command(about = "Restart the device")]
+    Restart,
+    #[command(
+        about = "Set the wifi network the device joins, it restarts to join it (eg. while provisioning it on its access point)"
+    )]
+    SetWifi {
+        ssid: String,
+        #[arg(
+            long,
+            env = "WIFI_PASS",
+            hide_env_values = true,
+            help = "Password of the network, none for an open network"
+        )]
+        password: Option<String>,
+    },
+    #[command(
+        about = "Set whether the device joins networks and when it runs its access point, it restarts to apply it"
+    )]
+    SetWifiConfig {
+        #[arg(
+            long,
+            default_value = "provisioning",
+            help = "When to run the access point: off, provisioning (only while no network is known) or always"
+        )]
+        access_point: AccessPointMode,
+        #[arg(
+            long,
+            help = "Do not join networks, only run the access point (which has to be always on)"
+        )]
+        no_station: bool,
+        #[arg(
+            long,
+            default_value_t = 1,
+            help = "Channel of the access point, from 1 to 13, it follows the station once connected"
+        )]
+        channel: u8,
+        #[arg(
+            long,
+            help = "Name of the access point, the device's own name by default"
+        )]
+        ssid: Option<String>,
+        #[arg(
+            long,
+            env = "AP_PASS",
+            hide_env_values = true,
+            help = "WPA2 password of the access point, none for an open one"
+        )]
+        password: Option<String>,
+    },
+    #[command(
+        about = "Set the device's name and port and whether it keeps camera settings, it restarts to apply them"
+    )]
+    SetDevice {
+        #[arg(
+            long,
+            help = "Name advertised over mDNS and of the access point, derived from the MAC address by default"
+        )]
+        name: Option<String>,
+        #[arg(long, default_value_t = DEFAULT_PORT, help = "Port to listen on")]
+        port: u16,
+        #[arg(
+            long,
+            help = "Boot with the default camera settings instead of the last ones set"
+        )]
+        no_persist_camera: bool,
+    },
+    #[command(
+        about = "Erase the device's networks and settings, it restarts on its access point to be provisioned again"
+    )]
+    FactoryReset,
+    #[command(
+        about = "Capture an image and classify the door as open or closed, see door in the config"
+    )]
+    DoorState,
+    #[command(about = "List the configured devices and whether they are online")]
+    Devices,
+    #[command(about = "Find the devices advertising themselves over mDNS")]
+    Discover {
+        #[arg(
+            long,
+            default_value_t = 3,
+            help = "Seconds to wait for devices to answer"
+        )]
+        wait_s: u64,
+    },
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let result = config(&args).and_then(|config| match args.command.clone() {
+        Command::Devices => list_devices(&registry(&args, config)?),
+        Command::Discover { wait_s } => discover(Duration::from_secs(wait_s)),
+        command => {
+            let archive = config.archive.clone();
+            let device = device(&args, config)?;
+            run(&device, command, archive)
+        }
+    });
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn config(args: &Args) -> Result<Config, CliError> {
+    let mut config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    if let Some(key) = &args.key {
+        config.key = Some(key.clone());
+    }
+    Ok(config)
+}
+
+fn registry(args: &Args, config: Config) -> Result<Registry, CliError> {
+    let timeout = Duration::from_millis(args.timeout_ms);
+    Ok(Registry::new(config.devices, config.key, timeout)?)
+}
+
+// Look up `--device` by name, browsing for it when the config has no address for it, anything else is an address
+fn device(args: &Args, config: Config) -> Result<Arc<Device>, CliError> {
+    let Some(name) = &args.device else {
+        return Err(CliError::NoDevice);
+    };
+    let timeout = Duration::from_millis(args.timeout_ms);
+    let key = config.key.clone();
+    let configured = registry(args, config)?.get(name);
+    if let Some(device) = configured.as_ref().filter(|device| device.addr().is_some()) {
+        return Ok(device.clone());
+    }
+    let unconfigured = |name: &str, addr: &str| {
+        let config = DeviceConfig {
+            key: key.clone(),
+            ..DeviceConfig::new(name, addr)
+        };
+        Arc::new(Device::new(config, timeout))
+    };
+    let as_addr = || unconfigured("device", name);
+    // Note: names cannot contain '.' or ':', so these are addresses
+    if configured.is_none() && name.contains(['.', ':']) {
+        return Ok(as_addr());
+    }
+
+    let discovery = Discovery::browse().map_err(CliError::Discovery)?;
+    let deadline = Instant::now() + DISCOVERY_WAIT;
+    while let Some(found) = discovery.next(Some(deadline.saturating_duration_since(Instant::now())))
+    {
+        if found.device_name().as_deref() != Some(name) {
+            continue;
+        }
+        return Ok(match configured {
+            Some(device) => {
+                device.set_addr(&found.addr);
+                device
+            }
+            None => unconfigured(name, &found.addr),
+        });
+    }
+    match configured {
+        Some(_) => Err(CliError::NotFound(name.clone())),
+        None => Ok(as_addr()),
+    }
+}
+
+fn run(device: &Device, command: Command, archive: Option<ArchiveConfig>) -> Result<(), CliError> {
+    match command {
+        Command::Capture { output } => capture(device, output, archive),
+        Command::SetFrameSize { frame_size } => {
+            device.set_frame_size(frame_size)?;
+            println!(
+                "frame size set to {} ({}x{})",
+                frame_size,
+                frame_size.width(),
+                frame_size.height()
+            );
+            Ok(())
+        }
+        Command::SetPixelFormat { pixel_format } => {
+            device.set_pixel_format(pixel_format)?;
+            println!("pixel format set to {}", pixel_format);
+            Ok(())
+        }
+        Command::SetQuality { jpeg_quality } => {
+            device.set_jpeg_quality(jpeg_quality)?;
+            println!("jpeg quality set to {}", jpeg_quality);
+            Ok(())
+        }
+        Command::Status => {
+            print_status(&device.status()?);
+            Ok(())
+        }
+        Command::DoorState => {
+            if device.door().is_none() {
+                return Err(CliError::NoDoor);
+            }
+            let observed = pipeline(archive)?.capture(device, Trigger::Cli)?;
+            let classification = match observed.door {
+                Some(door) => door.map_err(CliError::Door)?,
+                None => return Err(CliError::NoDoor),
+            };
+            println!(
+                "door {} (confidence {:.2}, similarity to open {:.2}, to closed {:.2})",
+                classification.state,
+                classification.confidence,
+                classification.open_similarity,
+                classification.closed_similarity
+            );
+            if let Some(capture) = observed.capture {
+                println!("archived {}", capture.id);
+            }
+            Ok(())
+        }
+        Command::Restart => {
+            device.restart()?;
+            println!("device restarting");
+            Ok(())
+        }
+        Command::SetWifi { ssid, password } => {
+            let credentials = WifiCredentials::new(&ssid, password.as_deref().unwrap_or_default())
+                .ok_or(CliError::InvalidWifi)?;
+            device.set_wifi(&credentials)?;
+            println!("wifi set to {}, device restarting to join it", ssid);
+            Ok(())
+        }
+        Command::SetWifiConfig {
+            access_point,
+            no_station,
+            channel,
+            ssid,
+            password,
+        } => {
+            let config = WifiConfig::new(
+                !no_station,
+                access_point,
+                channel,
+                ssid.as_deref().unwrap_or_default(),
+                password.as_deref().unwrap_or_default(),
+            )
+            .map_err(CliError::InvalidWifiConfig)?;
+            device.set_wifi_config(&config)?;
+            println!("wifi config set, device restarting to apply it");
+            Ok(())
+        }
+        Command::SetDevice {
+            name,
+            port,
+            no_persist_camera,
+        } => {
+            let settings = DeviceSettings::new(
+                name.as_deref().unwrap_or_default(),
+                port,
+                !no_persist_camera,
+            )
+            .map_err(CliError::InvalidDeviceSettings)?;
+            device.set_device_settings(&settings)?;
+            println!("device settings set, device restarting to apply them");
+            Ok(())
+        }
+        Command::FactoryReset => {
+            device.factory_reset()?;
+            println!("device reset to factory settings, restarting");
+            Ok(())
+        }
+        Command::Devices | Command::Discover { .. } => unreachable!("handled without a device"),
+    }
+}
+
+fn list_devices(registry: &Registry) -> Result<(), CliError> {
+    if registry.is_empty() {
+        return Err(CliError::NoConfig);
+    }
+
+    for device in registry.devices() {
+        let config = device.config();
+        let state = match device.status() {
+            Ok(status) => {
+                let sensor = match &status.camera {
+                    Some(camera) => SensorModel::from_pid(camera.sensor.pid).to_string(),
+                    None => "camera not available".to_string(),
+                };
+                format!("online, firmware {}, {}", status.firmware_version, sensor)
+            }
+            Err(err) => format!("offline ({})", err),
+        };
+        let expected = [
+            config.board.map(|board| board.to_string()),
+            config.sensor.map(|sensor| sensor.to_string()),
+        ];
+        println!(
+            "{:<12} {:<22} {:<20} {}",
+            config.name,
+            device.addr().unwrap_or_else(|| "-".to_string()),
+            expected.into_iter().flatten().collect::<Vec<_>>().join(" "),
+            state
+        );
+    }
+    Ok(())
+}
+
+fn discover(wait: Duration) -> Result<(), CliError> {
+    let discovery = Discovery::browse().map_err(CliError::Discovery)?;
+    let deadline = Instant::now() + wait;
+
+    let mut seen = Vec::new();
+    while let Some(found) = discovery.next(Some(deadline.saturating_duration_since(Instant::now())))
+    {
+        if seen.contains(&found.name) {
+            continue;
+        }
+        let advertisement = &found.advertisement;
+        println!(
+            "{:<12} {:<22} {:<10} {:<8} firmware {}",
+            found.name,
+            found.addr,
+            advertisement.board.map_or("-", |board| board.name()),
+            advertisement
+                .sensor
+                .map_or_else(|| "-".to_string(), |sensor| sensor.to_string()),
+            advertisement
+                .firmware_version
+                .as_deref()
+                .unwrap_or("unknown")
+        );
+        seen.push(found.name);
+    }
+
+    match seen.is_empty() {
+        true => Err(CliError::NotFound("any device".to_string())),
+        false => Ok(()),
+    }
+}
+
+// Captures go to the archive of the config unless a file is given
+fn capture(
+    device: &Device,
+    output: Option<PathBuf>,
+    archive: Option<ArchiveConfig>,
+) -> Result<(), CliError> {
+    if let (None, Some(archive)) = (&output, archive) {
+        return capture_to_archive(device, archive);
+    }
+    let (info, data) = device.capture()?;
+
+    if output.as_deref() == Some(Path::new("-")) {
+        return io::stdout().write_all(&data).map_err(CliError::Output);
+    }
+
+    // Note: raw formats have no header, their dimensions and format are only printed
+    let path = match output {
+        Some(path) => {
+            fs::write(&path, &data).map_err(CliError::Output)?;
+            path
+        }
+        None => {
+            let extension = match info.pixel_format {
+                PixelFormat::JPEG => "jpg",
+                _ => "raw",
+            };
+            save_new(&data, extension)?
+        }
+    };
+
+    println!(
+        "saved {} ({}x{} {}, {} KB)",
+        path.display(),
+        info.width,
+        info.height,
+        info.pixel_format,
+        data.len() / 1024
+    );
+    Ok(())
+}
+
+// Note: like the controller's captures, one that can not be classified is still archived
+// Save a capture under captures/<timestamp>.<extension>, never overwriting an earlier one
+//
+// Note: captures taken within the same millisecond are numbered, eg. 20240101_120000_123-1.jpg
+fn save_new(data: &[u8], extension: &str) -> Result<PathBuf, CliError> {
+    fs::create_dir_all(CAPTURES_DIR).map_err(CliError::Output)?;
+    let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S_%3f");
+    for attempt in 0.. {
+        let name = match attempt {
+            0 => format!("{}.{}", timestamp, extension),
+            _ => format!("{}-{}.{}", timestamp, attempt, extension),
+        };
+        let path = Path::new(CAPTURES_DIR).join(name);
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(mut file) => {
+                file.write_all(data).map_err(CliError::Output)?;
+                return Ok(path);
+            }
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(CliError::Output(err)),
+        }
+    }
+    unreachable!("some capture name is free")
+}
+
+fn capture_to_archive(device: &Device, archive: ArchiveConfig) -> Result<(), CliError> {
+    let pipeline = pipeline(Some(archive))?;
+    // Note: the quality is only known from the status, which a failing camera does not include
+    device.status()?;
+    let observed = pipeline.capture(device, Trigger::Cli)?;
+    let capture = observed.capture.ok_or(CliError::NotArchived)?;
+
+    println!(
+        "archived {} ({}x{} {}, {} KB)",
+        capture.id,
+        observed.info.width,
+        observed.info.height,
+        observed.info.pixel_format,
+        observed.data.len() / 1024
+    );
+    if let Some(Ok(door)) = observed.door {
+        println!("door {} (confidence {:.2})", door.state, door.confidence);
+    }
+    Ok(())
+}
+
+// Captures go through the same pipeline as the controller's, without alerts
+fn pipeline(archive: Option<ArchiveConfig>) -> Result<Pipeline, CliError> {
+    let archive = match archive {
+        Some(archive) => Some(Arc::new(
+            Archive::open(&archive.path, archive.retention).map_err(CliError::Output)?,
+        )),
+        None => None,
+    };
+    Ok(Pipeline::new(archive, None))
+}
+
+fn print_status(status: &DeviceStatus) {
+    let uptime_secs = status.uptime_ms / 1000;
+    println!("firmware:     {}", status.firmware_version);
+    println!(
+        "uptime:       {}h {}m {}s",
+        uptime_secs / 3600,
+        uptime_secs / 60 % 60,
+        uptime_secs % 60
+    );
+    println!("reset reason: {}", status.reset_reason);
+    println!("free heap:    {} KB", status.free_heap / 1024);
+    println!("free psram:   {} KB", status.free_psram / 1024);
+    match &status.wifi {
+        Some(wifi) => println!("wifi:         {} ({} dBm)", wifi.ip, wifi.rssi),
+        None => println!("wifi:         not connected"),
+    }
+
+    let Some(camera) = &status.camera else {
+        println!("camera:       not available");
+        return;
+    };
+    println!(
+        "sensor:       {} (pid {:#x}, max {})",
+        camera.sensor.name, camera.sensor.pid, camera.sensor.max_frame_size
+    );
+    println!(
+        "frame size:   {} ({}x{})",
+        camera.frame_size,
+        camera.frame_size.width(),
+        camera.frame_size.height()
+    );
+    println!("pixel format: {}", camera.pixel_format);
+    println!("jpeg quality: {}", camera.jpeg_quality);
+    for control_value in camera.sensor_settings.values() {
+        println!("  {}", control_value);
+    }
+}
+
+#[derive(Debug)]
+enum CliError {
+    NoDevice,
+    NoConfig,
+    NotFound(String),
+    NoDoor,
+    InvalidWifi,
+    InvalidWifiConfig(ParseError),
+    InvalidDeviceSettings(ParseError),
+    Config(ConfigError),
+    Discovery(io::Error),
+    Device(ClientError),
+    Door(DoorError),
+    Output(io::Error),
+    NotArchived,
+}
+
+impl std::fmt::Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CliError::NoDevice => f.write_str("no device given, use --device"),
+            CliError::NoConfig => f.write_str("no devices configured, use --config"),
+            CliError::NotFound(name) => write!(f, "did not find {} over mDNS", name),
+            CliError::NoDoor => f.write_str("no door configured for the device, see --config"),
+            CliError::InvalidWifi => write!(
+                f,
+                "invalid wifi network, the SSID takes 1 to {} bytes and the password {} to {} (or none)",
+                WifiCredentials::MAX_SSID_LEN,
+                WifiCredentials::MIN_PASSWORD_LEN,
+                WifiCredentials::MAX_PASSWORD_LEN
+            ),
+            CliError::InvalidWifiConfig(err) => write!(f, "{}", err),
+            CliError::InvalidDeviceSettings(err) => write!(f, "{}", err),
+            CliError::Config(err) => write!(f, "{}", err),
+            CliError::Discovery(err) => write!(f, "failed to browse for devices: {}", err),
+            CliError::Device(err) => write!(f, "{}", err),
+            CliError::Door(err) => write!(f, "failed to classify capture: {}", err),
+            CliError::Output(err) => write!(f, "failed to save capture: {}", err),
+            CliError::NotArchived => f.write_str("capture was not archived, see the error above"),
+        }
+    }
+}
+
+impl From<ClientError> for CliError {
+    fn from(err: ClientError) -> Self {
+        CliError::Device(err)
+    }
+}
+
+impl From<ConfigError> for CliError {
+    fn from(err: ConfigError) -> Self {
+        CliError::Config(err)
+    }
+}
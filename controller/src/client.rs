@@ -0,0 +1,615 @@
+use std::fmt;
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use protocol::{
+    ControlValue, DecodeError, DeviceSettings, DeviceStatus, ErrorCode, Frame, FrameSize,
+    Handshake, HandshakeRequest, HandshakeResponse, ImageInfo, IncomingPacket, JpegQuality,
+    OutgoingPacket, PixelFormat, PresharedKey, RecordCipher, Role, SecureStream, SessionError,
+    WifiConfig, WifiCredentials, DEFAULT_PORT, NONCE_LEN,
+};
+
+use crate::config::DeviceConfig;
+use crate::door::DoorClassifier;
+use crate::health::Health;
+
+// Sessions idle for longer are replaced before a request, the device closes them after 60 s
+const SESSION_IDLE_LIMIT: Duration = Duration::from_secs(50);
+
+// Errors talking to a device
+#[derive(Debug)]
+pub enum ClientError {
+    // No connection could be made (eg. the device is off or the address is wrong)
+    Unreachable(io::Error),
+    // The device did not answer in time
+    Timeout,
+    // The connection failed after it was made
+    Io(io::Error),
+    // The device sent something that is not a valid response
+    BadResponse(String),
+    // The device answered with an error
+    Device { code: ErrorCode, message: String },
+    // No key is configured to authenticate with the device
+    NoKey,
+    // The device did not prove it knows the key, or the session was tampered with
+    Session(SessionError),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Unreachable(err) => write!(f, "device unreachable: {}", err),
+            ClientError::Timeout => f.write_str("device did not respond in time"),
+            ClientError::Io(err) => write!(f, "connection to device failed: {}", err),
+            ClientError::BadResponse(reason) => {
+                write!(f, "invalid response from device: {}", reason)
+            }
+            ClientError::Device { code, message } => {
+                write!(f, "device error ({}): {}", code, message)
+            }
+            ClientError::NoKey => f.write_str(
+                "no key to authenticate with the device, set key in the config or DEVICE_KEY",
+            ),
+            ClientError::Session(err) => write!(f, "device not trusted: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ClientError::Timeout,
+            io::ErrorKind::InvalidData => match err.get_ref() {
+                Some(inner) if inner.is::<DecodeError>() => {
+                    ClientError::BadResponse(inner.to_string())
+                }
+                Some(inner) => match inner.downcast_ref::<SessionError>() {
+                    Some(session_err) => ClientError::Session(*session_err),
+                    None => ClientError::Io(err),
+                },
+                None => ClientError::Io(err),
+            },
+            _ => ClientError::Io(err),
+        }
+    }
+}
+
+impl From<SessionError> for ClientError {
+    fn from(err: SessionError) -> Self {
+        ClientError::Session(err)
+    }
+}
+
+impl From<DecodeError> for ClientError {
+    fn from(err: DecodeError) -> Self {
+        ClientError::BadResponse(err.to_string())
+    }
+}
+
+// Add the default device port to addresses given without one, eg. "192.168.1.20"
+pub fn device_addr(addr: &str) -> String {
+    match addr.rsplit_once(':') {
+        Some((_, port)) if port.parse::<u16>().is_ok() => addr.to_string(),
+        _ => format!("{}:{}", addr, DEFAULT_PORT),
+    }
+}
+
+// A single authenticated session with a device
+pub struct DeviceClient {
+    stream: SecureStream<TcpStream>,
+    next_request_id: u32,
+    last_used: Instant,
+}
+
+impl DeviceClient {
+    // Connect to `addr` ("host:port") and authenticate with `key`, `timeout` applies to connecting and to each
+    // request
+    pub fn connect(addr: &str, key: &PresharedKey, timeout: Duration) -> Result<Self, ClientError> {
+        let mut last_err =
+            io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve", addr));
+        for socket_addr in addr.to_socket_addrs().map_err(ClientError::Unreachable)? {
+            match TcpStream::connect_timeout(&socket_addr, timeout) {
+                Ok(mut stream) => {
+                    stream.set_read_timeout(Some(timeout))?;
+                    stream.set_write_timeout(Some(timeout))?;
+                    stream.set_nodelay(true)?;
+                    let ciphers = authenticate(&mut stream, key)?;
+                    return Ok(DeviceClient {
+                        stream: SecureStream::new(stream, ciphers),
+                        next_request_id: 1,
+                        last_used: Instant::now(),
+                    });
+                }
+                Err(err) => last_err = err,
+            }
+        }
+        Err(ClientError::Unreachable(last_err))
+    }
+
+    // Send a request and wait for its response, device errors are returned as `ClientError::Device`
+    pub fn request(&mut self, packet: &IncomingPacket) -> Result<OutgoingPacket, ClientError> {
+        let request_id = self.send(packet)?;
+        self.receive(packet, request_id)
+    }
+
+    // Time since the last request was sent
+    pub fn idle(&self) -> Duration {
+        self.last_used.elapsed()
+    }
+
+    fn send(&mut self, packet: &IncomingPacket) -> Result<u32, ClientError> {
+        let request_id = self.next_request_id;
+        self.next_request_id = self.next_request_id.wrapping_add(1).max(1);
+        self.last_used = Instant::now();
+
+        packet.to_frame(request_id).write_to(&mut self.stream)?;
+        Ok(request_id)
+    }
+
+    fn receive(
+        &mut self,
+        packet: &IncomingPacket,
+        request_id: u32,
+    ) -> Result<OutgoingPacket, ClientError> {
+        let frame = Frame::read_from(&mut self.stream)?;
+
+        // Note: errors about the connection itself (eg. busy) are sent with request id 0
+        let response = OutgoingPacket::from_frame(&frame)?;
+        if let OutgoingPacket::Error { code, message } = response {
+            return Err(ClientError::Device { code, message });
+        }
+        if frame.request_id != request_id || frame.message_type != packet.message_type() {
+            return Err(ClientError::BadResponse(format!(
+                "expected response to request {} (type {}), got request {} (type {})",
+                request_id,
+                packet.message_type(),
+                frame.request_id,
+                frame.message_type
+            )));
+        }
+        Ok(response)
+    }
+}
+
+// Have the device prove it knows the key and prove the same to it, see `Handshake`
+fn authenticate(
+    stream: &mut TcpStream,
+    key: &PresharedKey,
+) -> Result<(RecordCipher, RecordCipher), ClientError> {
+    let mut controller_nonce = [0; NONCE_LEN];
+    getrandom::getrandom(&mut controller_nonce).map_err(|err| ClientError::Io(err.into()))?;
+
+    let hello = HandshakeRequest::Hello {
+        nonce: controller_nonce,
+    };
+    let HandshakeResponse::Challenge { nonce, proof } = exchange(stream, 1, &hello)? else {
+        return Err(ClientError::BadResponse(
+            "expected handshake challenge".into(),
+        ));
+    };
+    let handshake = Handshake::new(key, &controller_nonce, &nonce);
+    // Note: the device proves itself first, so an impostor never sees the controller's proof
+    handshake.verify(Role::Device, &proof)?;
+
+    let proof = HandshakeRequest::Proof {
+        proof: handshake.proof(Role::Controller),
+    };
+    match exchange(stream, 2, &proof)? {
+        HandshakeResponse::Accepted => Ok(handshake.ciphers(Role::Controller)),
+        HandshakeResponse::Challenge { .. } => Err(ClientError::BadResponse(
+            "expected handshake to be accepted".into(),
+        )),
+    }
+}
+
+// Send a handshake message and read the answer, device errors (eg. busy) are returned as `ClientError::Device`
+fn exchange(
+    stream: &mut TcpStream,
+    request_id: u32,
+    request: &HandshakeRequest,
+) -> Result<HandshakeResponse, ClientError> {
+    request.to_frame(request_id).write_to(stream)?;
+    let frame = Frame::read_from(stream)?;
+
+    if let Ok(OutgoingPacket::Error { code, message }) = OutgoingPacket::from_frame(&frame) {
+        return Err(ClientError::Device { code, message });
+    }
+    if frame.request_id != request_id || frame.message_type != request.message_type() {
+        return Err(ClientError::BadResponse(format!(
+            "expected handshake response to request {} (type {}), got request {} (type {})",
+            request_id,
+            request.message_type(),
+            frame.request_id,
+            frame.message_type
+        )));
+    }
+    Ok(HandshakeResponse::from_frame(&frame)?)
+}
+
+// A request that failed, and whether it reached the connection before it did
+struct Failure {
+    err: ClientError,
+    sent: bool,
+}
+
+// Whether a request can be sent again without changing anything on the device
+fn is_idempotent(packet: &IncomingPacket) -> bool {
+    matches!(packet, IncomingPacket::GetStatus | IncomingPacket::Ping)
+}
+
+// A device reached over the TCP protocol, keeping a session open between requests
+pub struct Device {
+    config: DeviceConfig,
+    addr: Mutex<Option<String>>,
+    timeout: Duration,
+    client: Mutex<Option<DeviceClient>>,
+    health: Mutex<Health>,
+    door: Option<DoorClassifier>,
+}
+
+impl Device {
+    pub fn new(config: DeviceConfig, timeout: Duration) -> Self {
+        Device {
+            addr: Mutex::new(config.address.as_deref().map(device_addr)),
+            config,
+            timeout,
+            client: Mutex::new(None),
+            health: Mutex::new(Health::default()),
+            door: None,
+        }
+    }
+
+    // Classify captures with `door`, see `DeviceConfig::door`
+    pub fn with_door(mut self, door: Option<DoorClassifier>) -> Self {
+        self.door = door;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    pub fn addr(&self) -> Option<String> {
+        self.addr
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    // Move the device to a new address, closing the session with the old one
+    pub fn set_addr(&self, addr: &str) {
+        *self.addr.lock().unwrap_or_else(PoisonError::into_inner) = Some(device_addr(addr));
+        *self.client.lock().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+
+    pub fn config(&self) -> &DeviceConfig {
+        &self.config
+    }
+
+    pub fn door(&self) -> Option<&DoorClassifier> {
+        self.door.as_ref()
+    }
+
+    pub fn health(&self) -> Health {
+        self.lock_health().clone()
+    }
+
+    pub(crate) fn update_health(&self, update: impl FnOnce(&mut Health)) {
+        update(&mut self.lock_health());
+    }
+
+    fn lock_health(&self) -> MutexGuard<'_, Health> {
+        self.health.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Send a request, reconnecting if the session was closed (eg. by the device's idle timeout)
+    pub fn request(&self, packet: &IncomingPacket) -> Result<OutgoingPacket, ClientError> {
+        let mut client = self.client.lock().unwrap_or_else(PoisonError::into_inner);
+
+        // Note: the device may have closed a session left idle, it is replaced rather than tried
+        if client
+            .as_ref()
+            .is_some_and(|connected| connected.idle() > SESSION_IDLE_LIMIT)
+        {
+            *client = None;
+        }
+
+        let reused = client.is_some();
+        let result = match self.request_with(&mut client, packet) {
+            // Note: a request that was not sent is safe to send again on a new session, one that was
+            // may have been acted on (eg. a restart) unless asking again changes nothing
+            Err(Failure {
+                err: ClientError::Io(_),
+                sent,
+            }) if reused && (!sent || is_idempotent(packet)) => {
+                self.request_with(&mut client, packet)
+            }
+            result => result,
+        }
+        .map_err(|failure| failure.err);
+        self.update_health(|health| health.record(&result));
+        result
+    }
+
+    fn request_with(
+        &self,
+        client: &mut Option<DeviceClient>,
+        packet: &IncomingPacket,
+    ) -> Result<OutgoingPacket, Failure> {
+        let unsent = |err| Failure { err, sent: false };
+        let mut connected = match client.take() {
+            Some(connected) => connected,
+            None => {
+                let addr = self.addr().ok_or_else(|| {
+                    unsent(ClientError::Unreachable(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        "address not known until the device is discovered",
+                    )))
+                })?;
+                let key = self.config.key.as_ref().ok_or(unsent(ClientError::NoKey))?;
+                DeviceClient::connect(&addr, key, self.timeout).map_err(unsent)?
+            }
+        };
+
+        let request_id = connected.send(packet).map_err(unsent)?;
+        let result = connected.receive(packet, request_id);
+        // Note: the session can only be reused if the response was read completely
+        if matches!(result, Ok(_) | Err(ClientError::Device { .. })) {
+            *client = Some(connected);
+        }
+        result.map_err(|err| Failure { err, sent: true })
+    }
+
+    pub fn capture(&self) -> Result<(ImageInfo, Vec<u8>), ClientError> {
+        match self.request(&IncomingPacket::Capture)? {
+            OutgoingPacket::Capture { info, data } => Ok((info, data)),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    // Note: the status is also recorded in the health, eg. for the JPEG quality of archived captures
+    pub fn status(&self) -> Result<DeviceStatus, ClientError> {
+        match self.request(&IncomingPacket::GetStatus)? {
+            OutgoingPacket::Status(status) => {
+                self.update_health(|health| health.record_status(&status));
+                Ok(status)
+            }
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn set_frame_size(&self, frame_size: FrameSize) -> Result<(), ClientError> {
+        self.set(IncomingPacket::SetFrameSize(frame_size))
+    }
+
+    pub fn set_pixel_format(&self, pixel_format: PixelFormat) -> Result<(), ClientError> {
+        self.set(IncomingPacket::SetPixelFormat(pixel_format))
+    }
+
+    pub fn set_jpeg_quality(&self, jpeg_quality: JpegQuality) -> Result<(), ClientError> {
+        self.set(IncomingPacket::SetJpegQuality(jpeg_quality))?;
+        self.update_health(|health| health.jpeg_quality = Some(jpeg_quality));
+        Ok(())
+    }
+
+    pub fn set_control(&self, control_value: ControlValue) -> Result<(), ClientError> {
+        self.set(IncomingPacket::SetControl(control_value))
+    }
+
+    pub fn restart(&self) -> Result<(), ClientError> {
+        self.set(IncomingPacket::Restart)?;
+        // Note: the device drops its sessions when it restarts
+        *self.client.lock().unwrap_or_else(PoisonError::into_inner) = None;
+        Ok(())
+    }
+
+    // The device restarts to join the network, if it cannot it is still reachable on its access point
+    // to be provisioned again
+    pub fn set_wifi(&self, credentials: &WifiCredentials) -> Result<(), ClientError> {
+        self.set(IncomingPacket::SetWifi(credentials.clone()))?;
+        *self.client.lock().unwrap_or_else(PoisonError::into_inner) = None;
+        Ok(())
+    }
+
+    // The device restarts to apply the config
+    pub fn set_wifi_config(&self, config: &WifiConfig) -> Result<(), ClientError> {
+        self.set(IncomingPacket::SetWifiConfig(config.clone()))?;
+        *self.client.lock().unwrap_or_else(PoisonError::into_inner) = None;
+        Ok(())
+    }
+
+    // The device restarts to apply the settings, under its new name and port if they changed
+    pub fn set_device_settings(&self, settings: &DeviceSettings) -> Result<(), ClientError> {
+        self.set(IncomingPacket::SetDeviceSettings(settings.clone()))?;
+        *self.client.lock().unwrap_or_else(PoisonError::into_inner) = None;
+        Ok(())
+    }
+
+    // The device forgets its networks and settings and restarts, it is then only reachable on its
+    // access point to be provisioned again
+    pub fn factory_reset(&self) -> Result<(), ClientError> {
+        self.set(IncomingPacket::FactoryReset)?;
+        *self.client.lock().unwrap_or_else(PoisonError::into_inner) = None;
+        Ok(())
+    }
+
+    pub fn ping(&self) -> Result<(), ClientError> {
+        match self.request(&IncomingPacket::Ping)? {
+            OutgoingPacket::Ping => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    // Apply the configured capture settings the device's camera does not already use
+    pub fn apply_capture_settings(&self, status: &DeviceStatus) -> Result<(), ClientError> {
+        let Some(camera) = &status.camera else {
+            return Ok(());
+        };
+        let settings = &self.config.capture;
+
+        // Note: the pixel format goes first as changing it reinitializes the camera
+        if let Some(pixel_format) = settings.pixel_format {
+            if pixel_format != camera.pixel_format {
+                self.set_pixel_format(pixel_format)?;
+            }
+        }
+        if let Some(frame_size) = settings.frame_size {
+            if frame_size != camera.frame_size {
+                self.set_frame_size(frame_size)?;
+            }
+        }
+        if let Some(jpeg_quality) = settings.jpeg_quality {
+            if jpeg_quality != camera.jpeg_quality {
+                self.set_jpeg_quality(jpeg_quality)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Send a request answered with a success flag
+    fn set(&self, packet: IncomingPacket) -> Result<(), ClientError> {
+        let success = match self.request(&packet)? {
+            OutgoingPacket::SetFrameSize(success)
+            | OutgoingPacket::SetPixelFormat(success)
+            | OutgoingPacket::SetJpegQuality(success)
+            | OutgoingPacket::SetControl(success)
+            | OutgoingPacket::SetWifi(success)
+            | OutgoingPacket::SetWifiConfig(success)
+            | OutgoingPacket::SetDeviceSettings(success)
+            | OutgoingPacket::FactoryReset(success)
+            | OutgoingPacket::Restart(success) => success,
+            response => return Err(unexpected(response)),
+        };
+
+        match success {
+            true => Ok(()),
+            false => Err(ClientError::Device {
+                code: ErrorCode::Unknown(0),
+                message: format!("request {:?} was not applied", packet),
+            }),
+        }
+    }
+}
+
+fn unexpected(response: OutgoingPacket) -> ClientError {
+    ClientError::BadResponse(format!(
+        "unexpected response type {}",
+        response.message_type()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+
+    fn key() -> PresharedKey {
+        PresharedKey::new(&[7; 32]).unwrap()
+    }
+
+    // Device side of the handshake, see `authenticate`
+    fn accept_session(mut stream: TcpStream) -> SecureStream<TcpStream> {
+        let frame = Frame::read_from(&mut stream).unwrap();
+        let HandshakeRequest::Hello { nonce } = HandshakeRequest::from_frame(&frame).unwrap()
+        else {
+            panic!("expected hello");
+        };
+        let device_nonce = [9; NONCE_LEN];
+        let handshake = Handshake::new(&key(), &nonce, &device_nonce);
+        let challenge = HandshakeResponse::Challenge {
+            nonce: device_nonce,
+            proof: handshake.proof(Role::Device),
+        };
+        challenge
+            .to_frame(frame.request_id)
+            .write_to(&mut stream)
+            .unwrap();
+
+        let frame = Frame::read_from(&mut stream).unwrap();
+        let HandshakeRequest::Proof { proof } = HandshakeRequest::from_frame(&frame).unwrap()
+        else {
+            panic!("expected proof");
+        };
+        handshake.verify(Role::Controller, &proof).unwrap();
+        HandshakeResponse::Accepted
+            .to_frame(frame.request_id)
+            .write_to(&mut stream)
+            .unwrap();
+        SecureStream::new(stream, handshake.ciphers(Role::Device))
+    }
+
+    // Device answering the first request of its first session and dropping the session on the
+    // second one without answering (as if it restarted), later sessions are answered. Returns the
+    // address and the requests it received.
+    fn flaky_device() -> (String, Arc<Mutex<Vec<IncomingPacket>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+        thread::spawn(move || {
+            for (session, stream) in listener.incoming().enumerate() {
+                let mut stream = accept_session(stream.unwrap());
+                for answered in 0.. {
+                    let Ok(frame) = Frame::read_from(&mut stream) else {
+                        break;
+                    };
+                    let packet = IncomingPacket::from_frame(&frame).unwrap();
+                    log.lock().unwrap().push(packet.clone());
+                    if session == 0 && answered == 1 {
+                        break;
+                    }
+                    let response = match packet {
+                        IncomingPacket::Restart => OutgoingPacket::Restart(true),
+                        _ => OutgoingPacket::Ping,
+                    };
+                    response
+                        .to_frame(frame.request_id)
+                        .write_to(&mut stream)
+                        .unwrap();
+                }
+            }
+        });
+        (addr, received)
+    }
+
+    fn device(addr: &str) -> Device {
+        let config = DeviceConfig {
+            key: Some(key()),
+            ..DeviceConfig::new("test", addr)
+        };
+        Device::new(config, Duration::from_secs(5))
+    }
+
+    #[test]
+    fn idempotent_request_is_retried_on_a_new_session() {
+        let (addr, received) = flaky_device();
+        let device = device(&addr);
+        device.ping().unwrap();
+        device.ping().unwrap();
+        assert_eq!(received.lock().unwrap().len(), 3);
+    }
+
+    // Note: the device may have acted on the request before the session was lost
+    #[test]
+    fn sent_request_is_not_replayed() {
+        let (addr, received) = flaky_device();
+        let device = device(&addr);
+        device.ping().unwrap();
+        assert!(matches!(device.restart(), Err(ClientError::Io(_))));
+        assert_eq!(
+            *received.lock().unwrap(),
+            [IncomingPacket::Ping, IncomingPacket::Restart]
+        );
+
+        // The next request opens a new session
+        device.restart().unwrap();
+        assert_eq!(received.lock().unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,256 @@
+// Controller config file (TOML), eg.
+//
+// key = "5f0c..."  # shared with the devices, see DEVICE_KEY in device/.env
+// api_token = "..."  # required by every API request
+//
+// [[device]]
+// name = "garage"
+// address = "192.168.1.20"
+// board = "Freenove"
+// sensor = "OV2640"
+// capture = { frame_size = "SVGA", jpeg_quality = 12 }
+// door = { open = "refs/garage-open.jpg", closed = "refs/garage-closed.jpg", roi = { x = 120, y = 300, width = 400, height = 180 } }
+// schedule = { cron = "*/5 * * * *", quiet_hours = "23:00-06:00" }
+//
+// [archive]
+// path = "archive"
+// max_age_days = 30
+//
+// [alerts]
+// open_after_minutes = 15
+// notify = ["phone"]
+//
+// [notifier.phone]
+// type = "webhook"
+// url = "https://example.com/hooks/garage"
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use protocol::{BoardModel, FrameSize, JpegQuality, PixelFormat, PresharedKey, SensorModel};
+use serde::{Deserialize, Deserializer};
+
+use crate::archive::Retention;
+use crate::door::Roi;
+use crate::schedule::{Cron, QuietHours};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // Key the devices are authenticated with, unless they have their own, and the one discovered devices use
+    #[serde(default, deserialize_with = "parse")]
+    pub key: Option<PresharedKey>,
+    // Bearer token every API request has to send, so only the app can capture, change, restart or
+    // reset the devices
+    pub api_token: Option<String>,
+    #[serde(default, rename = "device")]
+    pub devices: Vec<DeviceConfig>,
+    pub archive: Option<ArchiveConfig>,
+    pub alerts: Option<AlertConfig>,
+    // By name, as the alerts refer to them
+    #[serde(default, rename = "notifier")]
+    pub notifiers: BTreeMap<String, NotifierConfig>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let contents =
+            fs::read_to_string(path).map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+        toml::from_str(&contents).map_err(|err| ConfigError::Parse(path.to_path_buf(), err))
+    }
+}
+
+// Settings applied to a device when the controller first reaches it and after it restarts
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CaptureSettings {
+    #[serde(default, deserialize_with = "parse")]
+    pub frame_size: Option<FrameSize>,
+    #[serde(default, deserialize_with = "parse")]
+    pub pixel_format: Option<PixelFormat>,
+    #[serde(default, deserialize_with = "parse")]
+    pub jpeg_quality: Option<JpegQuality>,
+}
+
+impl CaptureSettings {
+    pub fn is_empty(&self) -> bool {
+        *self == CaptureSettings::default()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+    // Used to address the device in the API and CLI, eg. /devices/garage/capture
+    pub name: String,
+    // "host" or "host:port", the port defaults to 8080
+    //
+    // Note: without one the device is unreachable until it is discovered under its name
+    pub address: Option<String>,
+    // Overrides the key of the config for this device
+    #[serde(default, deserialize_with = "parse")]
+    pub key: Option<PresharedKey>,
+    #[serde(default, deserialize_with = "parse")]
+    pub board: Option<BoardModel>,
+    // Sensor the device should report, a different one is flagged in its health
+    #[serde(default, deserialize_with = "parse")]
+    pub sensor: Option<SensorModel>,
+    #[serde(default)]
+    pub capture: CaptureSettings,
+    // Captures of the device are classified as door open or closed when set
+    pub door: Option<DoorConfig>,
+    pub schedule: Option<ScheduleConfig>,
+}
+
+impl DeviceConfig {
+    pub fn new(name: &str, address: &str) -> Self {
+        DeviceConfig {
+            name: name.to_string(),
+            address: Some(address.to_string()),
+            key: None,
+            board: None,
+            sensor: None,
+            capture: CaptureSettings::default(),
+            door: None,
+            schedule: None,
+        }
+    }
+}
+
+// Reference captures of the door, taken from where the camera is mounted (eg. with `camctl capture -o`)
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DoorConfig {
+    pub open: PathBuf,
+    pub closed: PathBuf,
+    // Where the door is, in pixels of the references
+    pub roi: Roi,
+    // Captures classified with less confidence (0 to 1) are of an unknown state
+    #[serde(default = "default_min_confidence")]
+    pub min_confidence: f64,
+}
+
+fn default_min_confidence() -> f64 {
+    0.25
+}
+
+// Captures taken without being asked for, eg. to check the door
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleConfig {
+    pub cron: Cron,
+    // No scheduled captures are taken in these hours (local time), eg. "23:00-06:00"
+    pub quiet_hours: Option<QuietHours>,
+}
+
+// When doors left open are alerted about and who is told, see alert.rs
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertConfig {
+    #[serde(default = "default_open_after_minutes")]
+    pub open_after_minutes: u64,
+    // Names of the notifiers told first
+    pub notify: Vec<String>,
+    // Repeat the alert this often while the door stays open, it is sent once when not set
+    pub repeat_minutes: Option<u64>,
+    // Also tell the `escalate` notifiers once the door has been open this long
+    pub escalate_after_minutes: Option<u64>,
+    #[serde(default)]
+    pub escalate: Vec<String>,
+}
+
+fn default_open_after_minutes() -> u64 {
+    15
+}
+
+// How an alert is sent, eg. `[notifier.phone]` with `type = "webhook"`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum NotifierConfig {
+    // POST the alert as JSON
+    Webhook {
+        url: String,
+    },
+    // Email the alert
+    Smtp {
+        server: String,
+        // Defaults to 587 for starttls, 465 for tls and 25 for none
+        port: Option<u16>,
+        #[serde(default)]
+        security: SmtpSecurity,
+        username: Option<String>,
+        password: Option<String>,
+        from: String,
+        to: Vec<String>,
+    },
+    // Run a command (program and arguments) with the alert in its environment
+    Command {
+        command: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    #[default]
+    Starttls,
+    Tls,
+    // Plain text, only for a relay on the local network
+    None,
+}
+
+// Where captures are stored and for how long
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ArchiveConfig {
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub retention: Retention,
+}
+
+// Errors loading the config file
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    // The file parsed but describes an unusable set of devices (eg. duplicate names)
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "failed to read {}: {}", path.display(), err),
+            ConfigError::Parse(path, err) => {
+                write!(f, "invalid config {}: {}", path.display(), err)
+            }
+            ConfigError::Invalid(reason) => write!(f, "invalid config: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// Parse an optional setting with its FromStr impl, given as a string (eg. "SVGA") or a number (eg. 12)
+fn parse<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Setting {
+        String(String),
+        Number(i64),
+    }
+
+    let value = match Setting::deserialize(deserializer)? {
+        Setting::String(value) => value,
+        Setting::Number(value) => value.to_string(),
+    };
+    value.parse().map(Some).map_err(serde::de::Error::custom)
+}
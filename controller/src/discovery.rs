@@ -0,0 +1,161 @@
+use std::io;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mdns_sd::{IfKind, ServiceDaemon, ServiceEvent, ServiceInfo};
+use protocol::{Advertisement, SERVICE_TYPE};
+
+use crate::registry::{validate_name, Registry};
+
+// A device found advertising `SERVICE_TYPE`
+#[derive(Debug, Clone)]
+pub struct Discovered {
+    // Instance name of the service, the device name unless the firmware was built with another one
+    pub name: String,
+    pub addr: String,
+    pub advertisement: Advertisement,
+}
+
+impl Discovered {
+    // Registry name for the instance name, eg. "Side Gate" -> "side-gate", None if nothing usable is left
+    pub fn device_name(&self) -> Option<String> {
+        let name: String = self
+            .name
+            .trim()
+            .chars()
+            .map(|c| match c.to_ascii_lowercase() {
+                c @ ('a'..='z' | '0'..='9' | '-' | '_') => c,
+                _ => '-',
+            })
+            .collect();
+        // Note: a name made only of separators (eg. from "!!!") does not tell devices apart
+        if !name.chars().any(|c| c.is_ascii_alphanumeric()) {
+            return None;
+        }
+        validate_name(&name).ok().map(|()| name)
+    }
+}
+
+// Browses for devices over mDNS until dropped
+//
+// Note: loopback is included so a simulator on the same host is found too
+pub struct Discovery {
+    daemon: ServiceDaemon,
+    events: mdns_sd::Receiver<ServiceEvent>,
+}
+
+impl Discovery {
+    pub fn browse() -> io::Result<Self> {
+        let daemon = ServiceDaemon::new().map_err(io::Error::other)?;
+        daemon
+            .enable_interface(IfKind::LoopbackV4)
+            .map_err(io::Error::other)?;
+        let events = daemon.browse(&service_domain()).map_err(io::Error::other)?;
+        Ok(Discovery { daemon, events })
+    }
+
+    // Wait for the next device to be resolved, None once `timeout` passes (if given)
+    pub fn next(&self, timeout: Option<Duration>) -> Option<Discovered> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let event = match deadline {
+                Some(deadline) => self.events.recv_deadline(deadline).ok()?,
+                None => self.events.recv().ok()?,
+            };
+            if let ServiceEvent::ServiceResolved(info) = event {
+                if let Some(discovered) = discovered(&info) {
+                    return Some(discovered);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Discovery {
+    fn drop(&mut self) {
+        let _ = self.daemon.shutdown();
+    }
+}
+
+// Keep adding the devices found over mDNS to the registry, in a background thread
+pub fn discover(registry: Arc<Registry>) -> io::Result<()> {
+    let discovery = Discovery::browse()?;
+    println!("discovery: browsing for {}", SERVICE_TYPE);
+
+    thread::spawn(move || {
+        while let Some(discovered) = discovery.next(None) {
+            if !discovered.advertisement.is_compatible() {
+                println!(
+                    "discovery: ignoring {} at {}, it speaks protocol version {:?}",
+                    discovered.name, discovered.addr, discovered.advertisement.protocol_version
+                );
+                continue;
+            }
+            registry.discovered(&discovered);
+        }
+    });
+    Ok(())
+}
+
+fn service_domain() -> String {
+    format!("{}.local.", SERVICE_TYPE)
+}
+
+fn discovered(info: &ServiceInfo) -> Option<Discovered> {
+    let name = info
+        .get_fullname()
+        .strip_suffix(&service_domain())?
+        .strip_suffix('.')?;
+
+    // Note: devices answer on every interface they share with the controller, loopback only helps on the same host
+    let addresses = info.get_addresses_v4();
+    let ip = addresses
+        .iter()
+        .find(|ip| !ip.is_loopback())
+        .or_else(|| addresses.iter().next())?;
+
+    let advertisement = Advertisement::from_txt(
+        info.get_properties()
+            .iter()
+            .map(|property| (property.key(), property.val_str())),
+    );
+    Some(Discovered {
+        name: name.to_string(),
+        addr: format!("{}:{}", IpAddr::V4(**ip), info.get_port()),
+        advertisement,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device_name(name: &str) -> Option<String> {
+        Discovered {
+            name: name.to_string(),
+            addr: "192.168.1.40:8080".to_string(),
+            advertisement: Advertisement::default(),
+        }
+        .device_name()
+    }
+
+    #[test]
+    fn device_name_from_instance_name() {
+        assert_eq!(device_name("Side Gate"), Some("side-gate".to_string()));
+        assert_eq!(device_name("garage"), Some("garage".to_string()));
+        assert_eq!(
+            device_name(" Front_Door 2 "),
+            Some("front_door-2".to_string())
+        );
+    }
+
+    #[test]
+    fn device_name_with_nothing_usable_left() {
+        assert_eq!(device_name(""), None);
+        assert_eq!(device_name("   "), None);
+        assert_eq!(device_name("!!!"), None);
+        assert_eq!(device_name("日本"), None);
+    }
+}
@@ -0,0 +1,542 @@
+// Garage door state from a capture, comparing a region of interest (eg. the bottom edge of the door) with reference
+// captures of the open and closed door
+//
+// The region is scaled down to a thumbnail and correlated with the references by its brightness and by its edges, so
+// changes in lighting matter less than what is in the picture
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use image::imageops::{self, FilterType};
+use image::{GrayImage, ImageError, ImageFormat};
+use protocol::{ImageInfo, PixelFormat};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{ConfigError, DoorConfig};
+
+// Side of the square thumbnail regions are compared at
+const THUMBNAIL_SIZE: u32 = 64;
+// Regions smaller than this (in pixels of the references) cannot be told apart reliably
+const MIN_ROI_SIZE: u32 = 8;
+// A capture less similar than this to both references shows something else (eg. the camera was moved)
+const MIN_SIMILARITY: f64 = 0.3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DoorState {
+    Open,
+    Closed,
+    Unknown,
+}
+
+impl DoorState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DoorState::Open => "open",
+            DoorState::Closed => "closed",
+            DoorState::Unknown => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for DoorState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// Region of interest in pixels, eg. `roi = { x = 120, y = 300, width = 400, height = 180 }`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Roi {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Roi {
+    // The same region in an image of another size, for captures taken at another frame size than the references
+    fn scaled(&self, from: (u32, u32), to: (u32, u32)) -> Roi {
+        let scale = |value: u32, from: u32, to: u32| {
+            (u64::from(value) * u64::from(to) / u64::from(from)) as u32
+        };
+        Roi {
+            x: scale(self.x, from.0, to.0),
+            y: scale(self.y, from.1, to.1),
+            width: scale(self.width, from.0, to.0).max(1),
+            height: scale(self.height, from.1, to.1).max(1),
+        }
+    }
+}
+
+// Outcome of classifying a capture
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Classification {
+    pub state: DoorState,
+    // 0 when the capture is as close to both references, 1 when it is identical to one of them
+    //
+    // Note: an unknown state still has the confidence of the closer reference
+    pub confidence: f64,
+    // Correlation of the region with each reference, from -1 to 1
+    pub open_similarity: f64,
+    pub closed_similarity: f64,
+}
+
+// Errors reading a capture to classify
+#[derive(Debug)]
+pub enum DoorError {
+    Decode(ImageError),
+    // Raw pixels shorter than the dimensions of the capture call for
+    Truncated { expected: usize, len: usize },
+}
+
+impl fmt::Display for DoorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DoorError::Decode(err) => write!(f, "failed to decode image: {}", err),
+            DoorError::Truncated { expected, len } => {
+                write!(f, "image has {} bytes, expected at least {}", len, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DoorError {}
+
+impl From<ImageError> for DoorError {
+    fn from(err: ImageError) -> Self {
+        DoorError::Decode(err)
+    }
+}
+
+// Thumbnail of a region, with zero mean and unit length so the dot product of two is their correlation
+struct Features {
+    luma: Vec<f32>,
+    edges: Vec<f32>,
+}
+
+impl Features {
+    fn new(image: &GrayImage, roi: Roi) -> Self {
+        let region = imageops::crop_imm(image, roi.x, roi.y, roi.width, roi.height).to_image();
+        let thumbnail = imageops::resize(
+            &region,
+            THUMBNAIL_SIZE,
+            THUMBNAIL_SIZE,
+            FilterType::Triangle,
+        );
+        let luma: Vec<f32> = thumbnail
+            .pixels()
+            .map(|pixel| f32::from(pixel.0[0]))
+            .collect();
+
+        // Gradient magnitude of the inner pixels, from central differences
+        let size = THUMBNAIL_SIZE as usize;
+        let mut edges = Vec::with_capacity((size - 2) * (size - 2));
+        for y in 1..size - 1 {
+            for x in 1..size - 1 {
+                let dx = luma[y * size + x + 1] - luma[y * size + x - 1];
+                let dy = luma[(y + 1) * size + x] - luma[(y - 1) * size + x];
+                edges.push((dx * dx + dy * dy).sqrt());
+            }
+        }
+
+        Features {
+            luma: normalized(luma),
+            edges: normalized(edges),
+        }
+    }
+
+    fn similarity(&self, other: &Features) -> f64 {
+        f64::from(dot(&self.luma, &other.luma) + dot(&self.edges, &other.edges)) / 2.0
+    }
+}
+
+// Classifies the captures of a device against its references, loaded once from the config
+pub struct DoorClassifier {
+    roi: Roi,
+    // Dimensions of the references, which the region is given in
+    reference_size: (u32, u32),
+    open: Features,
+    closed: Features,
+    min_confidence: f64,
+}
+
+impl DoorClassifier {
+    pub fn load(config: &DoorConfig) -> Result<Self, ConfigError> {
+        let open = load_reference(&config.open)?;
+        let closed = load_reference(&config.closed)?;
+        if open.dimensions() != closed.dimensions() {
+            return Err(ConfigError::Invalid(format!(
+                "door references {} and {} differ in size",
+                config.open.display(),
+                config.closed.display()
+            )));
+        }
+
+        let (width, height) = open.dimensions();
+        let roi = config.roi;
+        let fits = roi
+            .x
+            .checked_add(roi.width)
+            .is_some_and(|right| right <= width)
+            && roi
+                .y
+                .checked_add(roi.height)
+                .is_some_and(|bottom| bottom <= height);
+        if !fits || roi.width < MIN_ROI_SIZE || roi.height < MIN_ROI_SIZE {
+            return Err(ConfigError::Invalid(format!(
+                "door roi of {}x{} at {},{} must fit in the {}x{} references and be at least {} pixels wide and high",
+                roi.width, roi.height, roi.x, roi.y, width, height, MIN_ROI_SIZE
+            )));
+        }
+        if !(0.0..=1.0).contains(&config.min_confidence) {
+            return Err(ConfigError::Invalid(format!(
+                "door min_confidence {} is not between 0 and 1",
+                config.min_confidence
+            )));
+        }
+
+        Ok(DoorClassifier {
+            roi,
+            reference_size: (width, height),
+            open: Features::new(&open, roi),
+            closed: Features::new(&closed, roi),
+            min_confidence: config.min_confidence,
+        })
+    }
+
+    pub fn classify(&self, info: &ImageInfo, data: &[u8]) -> Result<Classification, DoorError> {
+        let image = grayscale(info, data)?;
+        let roi = self.roi.scaled(self.reference_size, image.dimensions());
+        let features = Features::new(&image, roi);
+        let open_similarity = features.similarity(&self.open);
+        let closed_similarity = features.similarity(&self.closed);
+
+        // Note: as distances from 0 (identical) to 1 (inverted), the closer reference is trusted by how much closer
+        // it is relative to both
+        let open_distance = (1.0 - open_similarity) / 2.0;
+        let closed_distance = (1.0 - closed_similarity) / 2.0;
+        let (guess, closer, further) = match open_distance <= closed_distance {
+            true => (DoorState::Open, open_distance, closed_distance),
+            false => (DoorState::Closed, closed_distance, open_distance),
+        };
+        let confidence = match closer + further {
+            sum if sum > 0.0 => (further - closer) / sum,
+            _ => 0.0,
+        };
+
+        let state = match confidence >= self.min_confidence
+            && open_similarity.max(closed_similarity) >= MIN_SIMILARITY
+        {
+            true => guess,
+            false => DoorState::Unknown,
+        };
+        Ok(Classification {
+            state,
+            confidence: rounded(confidence),
+            open_similarity: rounded(open_similarity),
+            closed_similarity: rounded(closed_similarity),
+        })
+    }
+}
+
+fn load_reference(path: &Path) -> Result<GrayImage, ConfigError> {
+    let data = fs::read(path).map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+    image::load_from_memory(&data)
+        .map(|image| image.into_luma8())
+        .map_err(|err| ConfigError::Invalid(format!("door reference {}: {}", path.display(), err)))
+}
+
+// Brightness of a capture in any of the pixel formats
+fn grayscale(info: &ImageInfo, data: &[u8]) -> Result<GrayImage, DoorError> {
+    let (width, height) = (u32::from(info.width), u32::from(info.height));
+    let pixels = usize::from(info.width) * usize::from(info.height);
+    let luma: Vec<u8> = match info.pixel_format {
+        PixelFormat::JPEG => {
+            return Ok(image::load_from_memory_with_format(data, ImageFormat::Jpeg)?.into_luma8())
+        }
+        PixelFormat::GRAYSCALE => raw(data, pixels)?.to_vec(),
+        // Big-endian like the esp32-camera driver produces it
+        PixelFormat::RGB565 => raw(data, pixels * 2)?
+            .chunks_exact(2)
+            .map(|pixel| {
+                let value = u16::from_be_bytes([pixel[0], pixel[1]]);
+                let r = f32::from((value >> 11) & 0x1f) * 255.0 / 31.0;
+                let g = f32::from((value >> 5) & 0x3f) * 255.0 / 63.0;
+                let b = f32::from(value & 0x1f) * 255.0 / 31.0;
+                // BT.601
+                (0.299 * r + 0.587 * g + 0.114 * b).round() as u8
+            })
+            .collect(),
+        // YUYV, every other byte is the luma of a pixel
+        PixelFormat::YUV422 => raw(data, pixels * 2)?.iter().step_by(2).copied().collect(),
+    };
+    Ok(GrayImage::from_raw(width, height, luma).expect("a byte of luma per pixel"))
+}
+
+fn raw(data: &[u8], expected: usize) -> Result<&[u8], DoorError> {
+    data.get(..expected).ok_or(DoorError::Truncated {
+        expected,
+        len: data.len(),
+    })
+}
+
+// Note: a flat region (eg. a dark night) has no variation to correlate and is left at zero
+fn normalized(mut values: Vec<f32>) -> Vec<f32> {
+    let mean = values.iter().sum::<f32>() / values.len() as f32;
+    values.iter_mut().for_each(|value| *value -= mean);
+    let length = values.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if length > f32::EPSILON {
+        values.iter_mut().for_each(|value| *value /= length);
+    }
+    values
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+// Scores are reported to 3 decimals, more would only be noise
+fn rounded(value: f64) -> f64 {
+    (value * 1000.0).round() / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    // Size the references are taken at, and the region the door is in
+    const REFERENCE_SIZE: (u32, u32) = (160, 120);
+    const ROI: Roi = Roi {
+        x: 40,
+        y: 48,
+        width: 80,
+        height: 64,
+    };
+
+    // A garage seen at any frame size: the closed door has horizontal panels, through the open one a car is seen
+    // against the back wall
+    fn scene(size: (u32, u32), state: DoorState) -> GrayImage {
+        GrayImage::from_fn(size.0, size.1, |x, y| {
+            let x = x * REFERENCE_SIZE.0 / size.0;
+            let y = y * REFERENCE_SIZE.1 / size.1;
+            let in_door = (40..120).contains(&x) && (48..112).contains(&y);
+            let luma = match (in_door, state) {
+                (false, _) => 90 + (x % 16) as u8,
+                (true, DoorState::Closed) => match (y - 48) / 8 % 2 {
+                    0 => 210,
+                    _ => 130,
+                },
+                (true, _) => match (55..105).contains(&x) && (80..105).contains(&y) {
+                    true => 170,
+                    false => 30,
+                },
+            };
+            image::Luma([luma])
+        })
+    }
+
+    // Something else where the door should be, eg. after the camera was moved
+    fn elsewhere(size: (u32, u32)) -> GrayImage {
+        GrayImage::from_fn(size.0, size.1, |x, y| match (x / 6 + y / 6) % 2 {
+            0 => image::Luma([40]),
+            _ => image::Luma([220]),
+        })
+    }
+
+    fn classifier(min_confidence: f64) -> DoorClassifier {
+        DoorClassifier {
+            roi: ROI,
+            reference_size: REFERENCE_SIZE,
+            open: Features::new(&scene(REFERENCE_SIZE, DoorState::Open), ROI),
+            closed: Features::new(&scene(REFERENCE_SIZE, DoorState::Closed), ROI),
+            min_confidence,
+        }
+    }
+
+    fn info(image: &GrayImage, pixel_format: PixelFormat) -> ImageInfo {
+        ImageInfo {
+            width: image.width() as u16,
+            height: image.height() as u16,
+            pixel_format,
+            timestamp_us: 0,
+        }
+    }
+
+    fn jpeg(image: &GrayImage) -> Vec<u8> {
+        let mut data = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut data), ImageFormat::Jpeg)
+            .unwrap();
+        data
+    }
+
+    fn classify(classifier: &DoorClassifier, image: &GrayImage) -> Classification {
+        classifier
+            .classify(&info(image, PixelFormat::JPEG), &jpeg(image))
+            .unwrap()
+    }
+
+    #[test]
+    fn open_and_closed_are_told_apart() {
+        let classifier = classifier(0.25);
+
+        let open = classify(&classifier, &scene(REFERENCE_SIZE, DoorState::Open));
+        assert_eq!(open.state, DoorState::Open);
+        assert!(open.confidence > 0.5, "{:?}", open);
+        assert!(open.open_similarity > 0.9, "{:?}", open);
+
+        let closed = classify(&classifier, &scene(REFERENCE_SIZE, DoorState::Closed));
+        assert_eq!(closed.state, DoorState::Closed);
+        assert!(closed.confidence > 0.5, "{:?}", closed);
+        assert!(closed.closed_similarity > 0.9, "{:?}", closed);
+    }
+
+    #[test]
+    fn lighting_matters_less_than_the_door() {
+        let classifier = classifier(0.25);
+        let mut dusk = scene(REFERENCE_SIZE, DoorState::Closed);
+        dusk.pixels_mut()
+            .for_each(|pixel| pixel.0[0] = pixel.0[0] / 3 + 10);
+
+        assert_eq!(classify(&classifier, &dusk).state, DoorState::Closed);
+    }
+
+    #[test]
+    fn something_else_is_unknown() {
+        let classifier = classifier(0.0);
+
+        let moved = classify(&classifier, &elsewhere(REFERENCE_SIZE));
+        assert_eq!(moved.state, DoorState::Unknown);
+        assert!(moved.open_similarity < MIN_SIMILARITY, "{:?}", moved);
+        assert!(moved.closed_similarity < MIN_SIMILARITY, "{:?}", moved);
+
+        let night = GrayImage::from_pixel(REFERENCE_SIZE.0, REFERENCE_SIZE.1, image::Luma([5]));
+        let night = classify(&classifier, &night);
+        assert_eq!(night.state, DoorState::Unknown);
+        assert_eq!(night.confidence, 0.0);
+    }
+
+    #[test]
+    fn min_confidence_is_the_cutoff() {
+        // Note: a door half way, mostly looking closed
+        let open = scene(REFERENCE_SIZE, DoorState::Open);
+        let closed = scene(REFERENCE_SIZE, DoorState::Closed);
+        let between = GrayImage::from_fn(REFERENCE_SIZE.0, REFERENCE_SIZE.1, |x, y| {
+            let open = u16::from(open.get_pixel(x, y).0[0]);
+            let closed = u16::from(closed.get_pixel(x, y).0[0]);
+            image::Luma([((open * 2 + closed * 3) / 5) as u8])
+        });
+
+        let classification = classify(&classifier(0.0), &between);
+        assert_eq!(classification.state, DoorState::Closed);
+        let confidence = classification.confidence;
+        assert!(confidence > 0.0 && confidence < 0.5, "{:?}", classification);
+
+        let at_cutoff = classifier(confidence - 0.01);
+        assert_eq!(classify(&at_cutoff, &between).state, DoorState::Closed);
+        let above_cutoff = classifier(confidence + 0.01);
+        assert_eq!(classify(&above_cutoff, &between).state, DoorState::Unknown);
+    }
+
+    #[test]
+    fn roi_is_scaled_to_the_capture() {
+        let classifier = classifier(0.25);
+        for size in [(320, 240), (640, 480), (1600, 1200)] {
+            let open = classify(&classifier, &scene(size, DoorState::Open));
+            assert_eq!(open.state, DoorState::Open, "{:?}: {:?}", size, open);
+            let closed = classify(&classifier, &scene(size, DoorState::Closed));
+            assert_eq!(closed.state, DoorState::Closed, "{:?}: {:?}", size, closed);
+        }
+
+        let roi = ROI.scaled(REFERENCE_SIZE, (320, 240));
+        assert_eq!((roi.x, roi.y, roi.width, roi.height), (80, 96, 160, 128));
+        let roi = ROI.scaled(REFERENCE_SIZE, (1, 1));
+        assert_eq!((roi.x, roi.y, roi.width, roi.height), (0, 0, 1, 1));
+    }
+
+    #[test]
+    fn raw_pixel_formats_are_classified() {
+        let classifier = classifier(0.25);
+        let image = scene((320, 240), DoorState::Open);
+        let luma = image.as_raw();
+        let rgb565: Vec<u8> = luma
+            .iter()
+            .flat_map(|&luma| {
+                let (r, g, b) = (
+                    u16::from(luma >> 3),
+                    u16::from(luma >> 2),
+                    u16::from(luma >> 3),
+                );
+                (r << 11 | g << 5 | b).to_be_bytes()
+            })
+            .collect();
+        let yuv422: Vec<u8> = luma.iter().flat_map(|&luma| [luma, 128]).collect();
+
+        for (pixel_format, data) in [
+            (PixelFormat::GRAYSCALE, luma.clone()),
+            (PixelFormat::RGB565, rgb565),
+            (PixelFormat::YUV422, yuv422),
+        ] {
+            let classification = classifier
+                .classify(&info(&image, pixel_format), &data)
+                .unwrap();
+            assert_eq!(classification.state, DoorState::Open, "{:?}", pixel_format);
+            assert!(classification.open_similarity > 0.9, "{:?}", pixel_format);
+
+            let truncated =
+                classifier.classify(&info(&image, pixel_format), &data[..data.len() - 1]);
+            assert!(matches!(truncated, Err(DoorError::Truncated { .. })));
+        }
+    }
+
+    #[test]
+    fn references_are_loaded_and_checked() {
+        let dir = std::env::temp_dir().join(format!("door-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, image: &GrayImage| -> PathBuf {
+            let path = dir.join(name);
+            fs::write(&path, jpeg(image)).unwrap();
+            path
+        };
+        let mut config = DoorConfig {
+            open: write("open.jpg", &scene(REFERENCE_SIZE, DoorState::Open)),
+            closed: write("closed.jpg", &scene(REFERENCE_SIZE, DoorState::Closed)),
+            roi: ROI,
+            min_confidence: 0.25,
+        };
+
+        let classifier = DoorClassifier::load(&config).unwrap();
+        let closed = classify(&classifier, &scene((320, 240), DoorState::Closed));
+        assert_eq!(closed.state, DoorState::Closed);
+
+        config.roi.width = 200;
+        assert!(matches!(
+            DoorClassifier::load(&config),
+            Err(ConfigError::Invalid(_))
+        ));
+        config.roi = Roi { width: 4, ..ROI };
+        assert!(matches!(
+            DoorClassifier::load(&config),
+            Err(ConfigError::Invalid(_))
+        ));
+        config.roi = ROI;
+        config.min_confidence = 1.5;
+        assert!(matches!(
+            DoorClassifier::load(&config),
+            Err(ConfigError::Invalid(_))
+        ));
+        config.min_confidence = 0.25;
+        config.closed = write("small.jpg", &scene((80, 60), DoorState::Closed));
+        assert!(matches!(
+            DoorClassifier::load(&config),
+            Err(ConfigError::Invalid(_))
+        ));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,141 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use protocol::{Advertisement, DeviceStatus, JpegQuality, SensorModel};
+
+use crate::client::{ClientError, Device};
+use crate::registry::Registry;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConnectionState {
+    // Not contacted yet
+    #[default]
+    Unknown,
+    Online,
+    Offline,
+}
+
+impl ConnectionState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConnectionState::Unknown => "unknown",
+            ConnectionState::Online => "online",
+            ConnectionState::Offline => "offline",
+        }
+    }
+}
+
+// What the controller knows about a device from its requests and health checks
+#[derive(Debug, Clone, Default)]
+pub struct Health {
+    pub state: ConnectionState,
+    // Last time the device answered a request
+    pub last_seen: Option<DateTime<Utc>>,
+    // Error of the last failed request, cleared once the device answers again
+    pub last_error: Option<String>,
+    // Failed requests since the device last answered
+    pub failures: u32,
+    // From the last health check
+    pub firmware_version: Option<String>,
+    pub uptime_ms: Option<u64>,
+    pub sensor: Option<SensorModel>,
+    // Quality the camera was last known to use, from health checks and settings changes
+    pub jpeg_quality: Option<JpegQuality>,
+    // From the device's mDNS service, when it was discovered
+    pub advertisement: Option<Advertisement>,
+}
+
+impl Health {
+    // Note: a device answering with an error is still online
+    pub(crate) fn record<T>(&mut self, result: &Result<T, ClientError>) {
+        match result {
+            Ok(_) | Err(ClientError::Device { .. }) => {
+                self.state = ConnectionState::Online;
+                self.last_seen = Some(Utc::now());
+                self.last_error = None;
+                self.failures = 0;
+            }
+            Err(err) => {
+                self.state = ConnectionState::Offline;
+                self.last_error = Some(err.to_string());
+                self.failures += 1;
+            }
+        }
+    }
+
+    pub(crate) fn record_status(&mut self, status: &DeviceStatus) {
+        self.firmware_version = Some(status.firmware_version.clone());
+        self.uptime_ms = Some(status.uptime_ms);
+        self.sensor = status
+            .camera
+            .as_ref()
+            .map(|camera| SensorModel::from_pid(camera.sensor.pid));
+        self.jpeg_quality = status.camera.as_ref().map(|camera| camera.jpeg_quality);
+    }
+}
+
+// Check the health of every device each `interval`, in a background thread
+pub fn monitor(registry: Arc<Registry>, interval: Duration) {
+    thread::spawn(move || loop {
+        for device in registry.devices() {
+            check(&device);
+        }
+        thread::sleep(interval);
+    });
+}
+
+// Refresh the device status, applying its capture settings when it was first reached or restarted
+pub fn check(device: &Device) {
+    let previous = device.health();
+    let status = match device.status() {
+        Ok(status) => status,
+        Err(err) => {
+            if previous.state != ConnectionState::Offline {
+                println!("health: {}: offline: {}", device.name(), err);
+            }
+            return;
+        }
+    };
+
+    if previous.state != ConnectionState::Online {
+        println!(
+            "health: {}: online, firmware {}",
+            device.name(),
+            status.firmware_version
+        );
+    }
+
+    let config = device.config();
+    let found = status
+        .camera
+        .as_ref()
+        .map(|camera| SensorModel::from_pid(camera.sensor.pid));
+    if let (Some(expected), Some(found)) = (config.sensor, found) {
+        if expected != found && previous.sensor != Some(found) {
+            println!(
+                "health: {}: expected sensor {}, found {}",
+                device.name(),
+                expected,
+                found
+            );
+        }
+    }
+
+    // Note: the device forgets its settings when it restarts, which shows as its uptime going back
+    let restarted = previous
+        .uptime_ms
+        .is_none_or(|uptime_ms| status.uptime_ms < uptime_ms);
+    if restarted {
+        if let Err(err) = device.apply_capture_settings(&status) {
+            println!(
+                "health: {}: failed to apply capture settings: {}",
+                device.name(),
+                err
+            );
+            // Note: retried on the next check
+            device.update_health(|health| health.uptime_ms = None);
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use chrono::Utc;
+use protocol::{CameraStatus, DeviceStatus, SensorModel};
+use serde_json::{json, Map, Value};
+
+use crate::archive::Capture;
+use crate::client::Device;
+use crate::door::Classification;
+use crate::health::Health;
+
+// Device config and health, as listed by /devices
+pub fn device(device: &Device) -> Value {
+    let config = device.config();
+    let capture = &config.capture;
+    json!({
+        "name": config.name,
+        "address": device.addr(),
+        "board": config.board.map(|board| board.name()),
+        "sensor": config.sensor.map(|sensor| sensor.to_string()),
+        "capture": {
+            "frame_size": capture.frame_size.map(|frame_size| frame_size.name()),
+            "pixel_format": capture.pixel_format.map(|pixel_format| pixel_format.name()),
+            "jpeg_quality": capture.jpeg_quality.map(|jpeg_quality| jpeg_quality.get()),
+        },
+        "door": device.door().is_some(),
+        "health": health(&device.health(), config.sensor),
+    })
+}
+
+// Note: `sensor_matches` is null until both the expected and the found sensor are known
+fn health(health: &Health, expected_sensor: Option<SensorModel>) -> Value {
+    let sensor_matches = expected_sensor
+        .zip(health.sensor)
+        .map(|(expected, found)| expected == found);
+    json!({
+        "state": health.state.name(),
+        "last_seen": health.last_seen.map(|last_seen| last_seen.to_rfc3339()),
+        "last_error": health.last_error,
+        "failures": health.failures,
+        "firmware_version": health.firmware_version,
+        "uptime_ms": health.uptime_ms,
+        "sensor": health.sensor.map(|sensor| sensor.to_string()),
+        "sensor_matches": sensor_matches,
+        "advertisement": health.advertisement.as_ref().map(|advertisement| json!({
+            "board": advertisement.board.map(|board| board.name()),
+            "sensor": advertisement.sensor.map(|sensor| sensor.to_string()),
+            "firmware_version": advertisement.firmware_version,
+            "protocol_version": advertisement.protocol_version,
+        })),
+    })
+}
+
+// Device status as sent to the app
+pub fn status(status: &DeviceStatus) -> Value {
+    json!({
+        "firmware_version": status.firmware_version,
+        "uptime_ms": status.uptime_ms,
+        "free_heap": status.free_heap,
+        "free_psram": status.free_psram,
+        "reset_reason": status.reset_reason.to_string(),
+        "wifi": status.wifi.as_ref().map(|wifi| json!({
+            "rssi": wifi.rssi,
+            "ip": wifi.ip.to_string(),
+        })),
+        "camera": status.camera.as_ref().map(camera),
+    })
+}
+
+pub fn camera(camera: &CameraStatus) -> Value {
+    let controls: Map<String, Value> = camera
+        .sensor_settings
+        .values()
+        .map(|control_value| {
+            (
+                control_value.control().name().to_string(),
+                control_value.value().into(),
+            )
+        })
+        .collect();
+
+    json!({
+        "sensor": {
+            "pid": camera.sensor.pid,
+            "name": camera.sensor.name,
+            "max_frame_size": camera.sensor.max_frame_size.name(),
+        },
+        "frame_size": camera.frame_size.name(),
+        "width": camera.frame_size.width(),
+        "height": camera.frame_size.height(),
+        "pixel_format": camera.pixel_format.name(),
+        "jpeg_quality": camera.jpeg_quality.get(),
+        "controls": controls,
+    })
+}
+
+pub fn error(message: &str) -> Value {
+    json!({ "error": message })
+}
+
+// Capture metadata, with where to fetch the image
+pub fn capture(capture: &Capture) -> Value {
+    let mut value = serde_json::to_value(capture).expect("capture metadata serializes");
+    value["image_url"] = format!("/captures/{}/image", capture.id).into();
+    value
+}
+
+// Note: `capture_id` is null unless captures are archived
+pub fn door_state(door: &Classification, capture: Option<&Capture>) -> Value {
+    json!({
+        "state": door.state.name(),
+        "confidence": door.confidence,
+        "open_similarity": door.open_similarity,
+        "closed_similarity": door.closed_similarity,
+        "time": Utc::now().to_rfc3339(),
+        "capture_id": capture.map(|capture| &capture.id),
+    })
+}
@@ -0,0 +1,28 @@
+// Central controller, relaying requests from the app to the devices over the TCP protocol
+mod alert;
+mod api;
+mod archive;
+mod client;
+mod config;
+mod discovery;
+mod door;
+mod health;
+mod json;
+mod pipeline;
+mod registry;
+mod schedule;
+
+pub use alert::{Alert, AlertEvent, Alerts, Notifier, NotifyError};
+pub use api::{serve, ApiError};
+pub use archive::{maintain, Archive, Capture, Retention, Trigger};
+pub use client::{device_addr, ClientError, Device, DeviceClient};
+pub use config::{
+    AlertConfig, ArchiveConfig, CaptureSettings, Config, ConfigError, DeviceConfig, DoorConfig,
+    NotifierConfig, ScheduleConfig, SmtpSecurity,
+};
+pub use discovery::{discover, Discovered, Discovery};
+pub use door::{Classification, DoorClassifier, DoorError, DoorState, Roi};
+pub use health::{check, monitor, ConnectionState, Health};
+pub use pipeline::{Observed, Pipeline};
+pub use registry::Registry;
+pub use schedule::{schedule, Cron, QuietHours};
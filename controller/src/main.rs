@@ -1,3 +1,154 @@
-fn main() {
-    println!("Hello, world!");
+use std::error::Error;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Parser;
+use controller::{Alerts, Archive, ArchiveConfig, Config, DeviceConfig, Pipeline, Registry};
+use protocol::PresharedKey;
+
+#[derive(Debug, Parser)]
+#[command(about = "Central controller serving the HTTP API for the app")]
+struct Args {
+    #[arg(
+        long,
+        default_value = "127.0.0.1:8000",
+        help = "Address to serve the HTTP API on, eg. 0.0.0.0:8000 for the app to reach it (with an API token)"
+    )]
+    listen: String,
+    #[arg(
+        long,
+        env = "API_TOKEN",
+        hide_env_values = true,
+        help = "Token every API request has to send as `Authorization: Bearer <token>`, overrides the config's"
+    )]
+    api_token: Option<String>,
+    #[arg(
+        long,
+        env = "CONTROLLER_CONFIG",
+        required_unless_present_any = ["device", "discover"],
+        help = "Config file listing the devices"
+    )]
+    config: Option<PathBuf>,
+    #[arg(
+        long,
+        env = "BOARD_IP",
+        help = "Address of a single device to serve as \"default\" instead of a config, the port defaults to 8080"
+    )]
+    device: Option<String>,
+    #[arg(
+        long,
+        env = "DEVICE_KEY",
+        hide_env_values = true,
+        help = "Key to authenticate with the devices, as hex, overrides the config's"
+    )]
+    key: Option<PresharedKey>,
+    #[arg(
+        long,
+        default_value_t = 10_000,
+        help = "Time allowed for connecting to a device and for each request"
+    )]
+    timeout_ms: u64,
+    #[arg(
+        long,
+        default_value_t = 30,
+        help = "Seconds between device health checks"
+    )]
+    health_interval_s: u64,
+    #[arg(
+        long,
+        help = "Browse for devices over mDNS, adding them to the configured ones"
+    )]
+    discover: bool,
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Store every capture in this directory, overrides the path of the config's [archive]"
+    )]
+    archive: Option<PathBuf>,
+}
+
+// Retention is checked this often even when no captures are taken
+const ARCHIVE_REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+fn main() -> ExitCode {
+    match run(Args::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    let timeout = Duration::from_millis(args.timeout_ms);
+
+    let mut config = match (&args.config, &args.device) {
+        (Some(path), _) => Config::load(path)?,
+        (None, Some(addr)) => Config {
+            devices: vec![DeviceConfig::new("default", addr)],
+            ..Config::default()
+        },
+        (None, None) => Config::default(),
+    };
+    if let Some(key) = args.key {
+        config.key = Some(key);
+    }
+    if let Some(api_token) = args.api_token {
+        config.api_token = Some(api_token);
+    }
+    let api_token = config.api_token.filter(|api_token| !api_token.is_empty());
+    if let Some(path) = args.archive {
+        let retention = config
+            .archive
+            .map(|archive| archive.retention)
+            .unwrap_or_default();
+        config.archive = Some(ArchiveConfig { path, retention });
+    }
+
+    if config.key.is_none() && config.devices.iter().any(|device| device.key.is_none()) {
+        println!("controller: warning: no key set, devices without one can not be reached");
+    }
+    if api_token.is_none() && !is_loopback(&args.listen) {
+        println!(
+            "controller: warning: no API token set, anyone reaching {} can capture, change and reset the devices",
+            args.listen
+        );
+    }
+    let registry = Arc::new(Registry::new(config.devices, config.key, timeout)?);
+    let archive = match config.archive {
+        Some(archive) => {
+            let archive = Arc::new(Archive::open(&archive.path, archive.retention)?);
+            controller::maintain(archive.clone(), ARCHIVE_REFRESH_INTERVAL);
+            Some(archive)
+        }
+        None => None,
+    };
+    let alerts = match config.alerts {
+        Some(alerts) => Some(Alerts::new(alerts, &config.notifiers)?),
+        None => None,
+    };
+    let pipeline = Arc::new(Pipeline::new(archive, alerts));
+
+    if args.discover {
+        controller::discover(registry.clone())?;
+    }
+    controller::monitor(
+        registry.clone(),
+        Duration::from_secs(args.health_interval_s),
+    );
+    controller::schedule(&registry, pipeline.clone());
+    controller::serve(&args.listen, registry, pipeline, api_token)?;
+    Ok(())
+}
+
+// Whether the API is only reachable from this host
+fn is_loopback(listen: &str) -> bool {
+    match listen.parse::<SocketAddr>() {
+        Ok(addr) => addr.ip().is_loopback(),
+        Err(_) => listen.starts_with("localhost:"),
+    }
 }
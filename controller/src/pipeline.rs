@@ -0,0 +1,76 @@
+// What happens to each capture the controller takes, from the API or on a schedule: the door is classified, the
+// capture archived and the door state passed on to the alerts
+use std::sync::Arc;
+
+use chrono::Utc;
+use protocol::ImageInfo;
+
+use crate::alert::Alerts;
+use crate::archive::{Archive, Capture, Trigger};
+use crate::client::{ClientError, Device};
+use crate::door::{Classification, DoorError};
+
+pub struct Pipeline {
+    archive: Option<Arc<Archive>>,
+    alerts: Option<Alerts>,
+}
+
+// A capture after the pipeline
+pub struct Observed {
+    pub info: ImageInfo,
+    pub data: Vec<u8>,
+    // Only for devices with a door configured
+    pub door: Option<Result<Classification, DoorError>>,
+    // Only when captures are archived
+    pub capture: Option<Capture>,
+}
+
+impl Pipeline {
+    pub fn new(archive: Option<Arc<Archive>>, alerts: Option<Alerts>) -> Self {
+        Pipeline { archive, alerts }
+    }
+
+    pub fn archive(&self) -> Option<&Archive> {
+        self.archive.as_deref()
+    }
+
+    // Note: failing to classify or archive the capture does not fail it, the error is only logged
+    pub fn capture(&self, device: &Device, trigger: Trigger) -> Result<Observed, ClientError> {
+        let (info, data) = device.capture()?;
+
+        let door = device.door().map(|door| door.classify(&info, &data));
+        if let Some(Err(err)) = &door {
+            println!(
+                "pipeline: error: failed to classify capture of {}: {}",
+                device.name(),
+                err
+            );
+        }
+
+        let capture = self.archive.as_ref().and_then(|archive| {
+            let door = door.as_ref().and_then(|door| door.as_ref().ok());
+            let jpeg_quality = device.health().jpeg_quality;
+            archive
+                .store(device.name(), &info, jpeg_quality, trigger, door, &data)
+                .map_err(|err| {
+                    println!(
+                        "pipeline: error: failed to archive capture of {}: {}",
+                        device.name(),
+                        err
+                    )
+                })
+                .ok()
+        });
+
+        if let (Some(alerts), Some(Ok(door))) = (&self.alerts, &door) {
+            alerts.record(device.name(), door.state, Utc::now(), capture.as_ref());
+        }
+
+        Ok(Observed {
+            info,
+            data,
+            door,
+            capture,
+        })
+    }
+}
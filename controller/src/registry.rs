@@ -0,0 +1,169 @@
+// Devices known to the controller, configured and found over mDNS
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard};
+use std::time::Duration;
+
+use protocol::PresharedKey;
+
+use crate::client::Device;
+use crate::config::{ConfigError, DeviceConfig};
+use crate::discovery::Discovered;
+use crate::door::DoorClassifier;
+
+// The named devices, configured ones first and then discovered ones in the order they were found
+pub struct Registry {
+    devices: RwLock<Vec<Arc<Device>>>,
+    // For the devices without a key of their own
+    key: Option<PresharedKey>,
+    timeout: Duration,
+}
+
+impl Registry {
+    // `key` authenticates the devices configured without one and the discovered ones, `timeout` applies to
+    // connecting to each device and to each request
+    pub fn new(
+        configs: Vec<DeviceConfig>,
+        key: Option<PresharedKey>,
+        timeout: Duration,
+    ) -> Result<Self, ConfigError> {
+        let mut devices: Vec<Arc<Device>> = Vec::with_capacity(configs.len());
+        for mut config in configs {
+            validate_name(&config.name)?;
+            if devices.iter().any(|device| device.name() == config.name) {
+                return Err(ConfigError::Invalid(format!(
+                    "device {} is configured twice",
+                    config.name
+                )));
+            }
+            config.key = config.key.or_else(|| key.clone());
+            let door = config.door.as_ref().map(DoorClassifier::load).transpose()?;
+            devices.push(Arc::new(Device::new(config, timeout).with_door(door)));
+        }
+        Ok(Registry {
+            devices: RwLock::new(devices),
+            key,
+            timeout,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Device>> {
+        self.read()
+            .iter()
+            .find(|device| device.name() == name)
+            .cloned()
+    }
+
+    pub fn devices(&self) -> Vec<Arc<Device>> {
+        self.read().clone()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.read().is_empty()
+    }
+
+    // Add a device found over mDNS, or update the address of the known device with the same name
+    pub fn discovered(&self, discovered: &Discovered) {
+        let Some(name) = discovered.device_name() else {
+            println!(
+                "registry: ignoring device with unusable name {:?}",
+                discovered.name
+            );
+            return;
+        };
+        let addr = &discovered.addr;
+
+        // Note: the name is looked up and the device added under one lock, so a device found twice at once is
+        // only added once. Moving a device waits for its current request, so it is done after unlocking.
+        let mut devices = self.devices.write().unwrap_or_else(PoisonError::into_inner);
+        let known = devices.iter().find(|device| device.name() == name).cloned();
+        let device = match known {
+            Some(device) => {
+                drop(devices);
+                if device.addr().as_deref() != Some(addr.as_str()) {
+                    println!("registry: {} is at {}", name, addr);
+                    device.set_addr(addr);
+                }
+                device
+            }
+            None => {
+                println!("registry: discovered {} at {}", name, addr);
+                let config = DeviceConfig {
+                    key: self.key.clone(),
+                    board: discovered.advertisement.board,
+                    ..DeviceConfig::new(&name, addr)
+                };
+                let device = Arc::new(Device::new(config, self.timeout));
+                devices.push(device.clone());
+                drop(devices);
+                device
+            }
+        };
+        device
+            .update_health(|health| health.advertisement = Some(discovered.advertisement.clone()));
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Vec<Arc<Device>>> {
+        self.devices.read().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// Names are used as is in URLs, so they are kept to lowercase letters, digits, '-' and '_'
+pub(crate) fn validate_name(name: &str) -> Result<(), ConfigError> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+    match valid {
+        true => Ok(()),
+        false => Err(ConfigError::Invalid(format!(
+            "invalid device name {:?}, use lowercase letters, digits, '-' and '_'",
+            name
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::Advertisement;
+
+    use super::*;
+
+    fn discovered(name: &str, addr: &str) -> Discovered {
+        Discovered {
+            name: name.to_string(),
+            addr: addr.to_string(),
+            advertisement: Advertisement::default(),
+        }
+    }
+
+    #[test]
+    fn discovered_device_is_added_once() {
+        let registry = Arc::new(Registry::new(Vec::new(), None, Duration::from_secs(1)).unwrap());
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let registry = registry.clone();
+                std::thread::spawn(move || {
+                    registry.discovered(&discovered("Side Gate", "192.168.1.40:8080"))
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let devices = registry.devices();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].name(), "side-gate");
+        assert_eq!(devices[0].addr().as_deref(), Some("192.168.1.40:8080"));
+    }
+
+    #[test]
+    fn known_device_moves() {
+        let config = DeviceConfig::new("garage", "192.168.1.40:8080");
+        let registry = Registry::new(vec![config], None, Duration::from_secs(1)).unwrap();
+        registry.discovered(&discovered("garage", "192.168.1.41:8080"));
+
+        let devices = registry.devices();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].addr().as_deref(), Some("192.168.1.41:8080"));
+    }
+}
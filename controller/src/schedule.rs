@@ -0,0 +1,232 @@
+// Captures taken on a schedule, each going through the pipeline like any other (door state, archive, alerts)
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+
+use chrono::{DateTime, Local, NaiveTime};
+use protocol::ParseError;
+use serde::Deserialize;
+
+use crate::archive::Trigger;
+use crate::client::Device;
+use crate::pipeline::Pipeline;
+use crate::registry::Registry;
+
+// Cron expression in local time, with 5 fields (minute hour day month weekday, eg. "*/5 * * * *"), or with seconds
+// first and optionally years last
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Cron(cron::Schedule);
+
+impl Cron {
+    pub fn next_after(&self, time: &DateTime<Local>) -> Option<DateTime<Local>> {
+        self.0.after(time).next()
+    }
+}
+
+impl FromStr for Cron {
+    type Err = cron::error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expression = match s.split_whitespace().count() {
+            5 => format!("0 {}", s),
+            _ => s.to_string(),
+        };
+        cron::Schedule::from_str(&expression).map(Cron)
+    }
+}
+
+impl TryFrom<String> for Cron {
+    type Error = cron::error::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for Cron {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0.source())
+    }
+}
+
+// Local times of day, eg. "22:00-06:00", spanning midnight when the end is before the start
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct QuietHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl QuietHours {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        match self.start <= self.end {
+            true => self.start <= time && time < self.end,
+            false => time >= self.start || time < self.end,
+        }
+    }
+}
+
+impl FromStr for QuietHours {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |time: &str| {
+            NaiveTime::parse_from_str(time.trim(), "%H:%M")
+                .map_err(|_| ParseError::new("quiet hours, use eg. 22:00-06:00"))
+        };
+        let (start, end) = s
+            .split_once('-')
+            .ok_or(ParseError::new("quiet hours, use eg. 22:00-06:00"))?;
+        Ok(QuietHours {
+            start: parse(start)?,
+            end: parse(end)?,
+        })
+    }
+}
+
+impl TryFrom<String> for QuietHours {
+    type Error = ParseError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+// Take the scheduled captures of the configured devices, in a background thread per device
+//
+// Note: a capture that is due while the previous one is still running (eg. waiting for the device's timeout) is
+// skipped, the schedule continues from when it finishes
+pub fn schedule(registry: &Registry, pipeline: Arc<Pipeline>) {
+    for device in registry.devices() {
+        let Some(schedule) = device.config().schedule.clone() else {
+            continue;
+        };
+        println!(
+            "schedule: capturing {} at \"{}\"{}",
+            device.name(),
+            schedule.cron,
+            match schedule.quiet_hours {
+                Some(quiet_hours) => format!(
+                    ", quiet from {} to {}",
+                    quiet_hours.start.format("%H:%M"),
+                    quiet_hours.end.format("%H:%M")
+                ),
+                None => String::new(),
+            }
+        );
+
+        let pipeline = pipeline.clone();
+        thread::spawn(move || loop {
+            let now = Local::now();
+            let Some(next) = schedule.cron.next_after(&now) else {
+                println!("schedule: {} has no more captures scheduled", device.name());
+                return;
+            };
+            thread::sleep((next - now).to_std().unwrap_or_default());
+
+            let quiet = schedule
+                .quiet_hours
+                .is_some_and(|quiet_hours| quiet_hours.contains(next.time()));
+            if !quiet {
+                capture(&device, &pipeline);
+            }
+        });
+    }
+}
+
+fn capture(device: &Device, pipeline: &Pipeline) {
+    match pipeline.capture(device, Trigger::Schedule) {
+        Ok(observed) => match observed.door {
+            Some(Ok(door)) => println!(
+                "schedule: {} door {} (confidence {:.2})",
+                device.name(),
+                door.state,
+                door.confidence
+            ),
+            Some(Err(_)) | None => println!("schedule: captured {}", device.name()),
+        },
+        Err(err) => println!("schedule: {}: capture failed: {}", device.name(), err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn time(time: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(time, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn quiet_hours_wrap_past_midnight() {
+        let quiet_hours: QuietHours = "22:00-06:00".parse().unwrap();
+        for quiet in ["22:00", "23:59", "00:00", "03:00", "05:59"] {
+            assert!(quiet_hours.contains(time(quiet)), "{}", quiet);
+        }
+        for awake in ["06:00", "12:00", "21:59"] {
+            assert!(!quiet_hours.contains(time(awake)), "{}", awake);
+        }
+    }
+
+    #[test]
+    fn quiet_hours_within_a_day() {
+        let quiet_hours: QuietHours = " 09:00 - 17:30 ".parse().unwrap();
+        assert_eq!(quiet_hours.start, time("09:00"));
+        assert_eq!(quiet_hours.end, time("17:30"));
+        assert!(quiet_hours.contains(time("09:00")));
+        assert!(quiet_hours.contains(time("17:29")));
+        assert!(!quiet_hours.contains(time("17:30")));
+        assert!(!quiet_hours.contains(time("08:59")));
+        assert!(!quiet_hours.contains(time("23:00")));
+    }
+
+    #[test]
+    fn invalid_quiet_hours() {
+        for invalid in [
+            "",
+            "22:00",
+            "22:00-",
+            "24:00-06:00",
+            "10pm-6am",
+            "22:00-06:00-07:00",
+        ] {
+            assert!(invalid.parse::<QuietHours>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn cron_with_five_fields_starts_on_the_minute() {
+        let cron: Cron = "*/5 * * * *".parse().unwrap();
+        let now = Local.with_ymd_and_hms(2026, 10, 18, 8, 2, 30).unwrap();
+        let next = cron.next_after(&now).unwrap();
+        assert_eq!(next, Local.with_ymd_and_hms(2026, 10, 18, 8, 5, 0).unwrap());
+        let after = cron.next_after(&next).unwrap();
+        assert_eq!(
+            after,
+            Local.with_ymd_and_hms(2026, 10, 18, 8, 10, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn cron_with_seconds_and_years() {
+        let cron: Cron = "30 0 12 * * * 2026".parse().unwrap();
+        let now = Local.with_ymd_and_hms(2026, 12, 31, 13, 0, 0).unwrap();
+        assert_eq!(cron.next_after(&now), None);
+        let now = Local.with_ymd_and_hms(2026, 10, 18, 8, 0, 0).unwrap();
+        assert_eq!(
+            cron.next_after(&now),
+            Some(Local.with_ymd_and_hms(2026, 10, 18, 12, 0, 30).unwrap())
+        );
+    }
+
+    #[test]
+    fn invalid_cron() {
+        for invalid in ["", "* * *", "61 * * * *", "every minute"] {
+            assert!(invalid.parse::<Cron>().is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use protocol::{ControlValue, FrameSize, JpegQuality, PixelFormat, SensorSettings};
+
+use crate::DeviceError;
+
+// Access to a camera driver, implemented on top of esp32-camera by the firmware and by
+// `MockCamera` on the host
+//
+// Note: frames obtained with `frame_get` belong to the driver and must be handed back with
+// `frame_return`, use `FrameBuffer` instead of calling these directly
+pub trait CameraBackend {
+    type Frame: RawFrame;
+
+    // Start the driver, detecting the sensor and allocating frame buffers for `config`
+    fn init(&mut self, config: &CameraConfig) -> Result<(), DeviceError>;
+
+    // Stop the driver and free its frame buffers
+    fn deinit(&mut self);
+
+    // Take the next frame from the driver, None if no frame buffer could be obtained
+    fn frame_get(&self) -> Option<Self::Frame>;
+
+    // Give a frame back to the driver so its buffer can be reused
+    fn frame_return(&self, frame: Self::Frame);
+
+    // Identity of the sensor the driver detected
+    fn sensor_id(&self) -> Result<SensorId, DeviceError>;
+
+    // Settings the sensor is currently using
+    fn sensor_settings(&self) -> Result<SensorSettings, DeviceError>;
+
+    // Change a setting on the running sensor
+    fn sensor_set(&mut self, setting: SensorSetting) -> Result<(), DeviceError>;
+}
+
+// A frame as handed out by the driver (eg. a `camera_fb_t` pointer)
+pub trait RawFrame {
+    fn data(&self) -> &[u8];
+    fn width(&self) -> u32;
+    fn height(&self) -> u32;
+    // None for formats the protocol has no value for
+    fn pixel_format(&self) -> Option<PixelFormat>;
+    // Time the frame was captured, µs since boot
+    fn timestamp_us(&self) -> u64;
+}
+
+// Settings the driver is initialized with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CameraConfig {
+    pub pixel_format: PixelFormat,
+    pub frame_size: FrameSize,
+    pub jpeg_quality: JpegQuality,
+}
+
+// What the driver reports about the sensor it detected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SensorId {
+    pub pid: u16,
+    // None if the driver has no name for the sensor
+    pub name: Option<String>,
+    // None if the driver does not know the sensor's limits
+    pub max_frame_size: Option<FrameSize>,
+}
+
+// A setting that can be changed without reinitializing the driver
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SensorSetting {
+    PixelFormat(PixelFormat),
+    FrameSize(FrameSize),
+    JpegQuality(JpegQuality),
+    Control(ControlValue),
+}
+
+impl SensorSetting {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SensorSetting::PixelFormat(_) => "pixel format",
+            SensorSetting::FrameSize(_) => "frame size",
+            SensorSetting::JpegQuality(_) => "jpeg quality",
+            SensorSetting::Control(control_value) => control_value.control().name(),
+        }
+    }
+}
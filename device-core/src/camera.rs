@@ -0,0 +1,226 @@
+use protocol::{
+    CameraStatus, ControlValue, FrameSize, ImageInfo, JpegQuality, PixelFormat, SensorInfo,
+    SensorModel, SensorSettings,
+};
+
+use crate::{CameraBackend, CameraConfig, CameraSettings, DeviceError, FrameBuffer, SensorSetting};
+
+// Camera driver along with the settings requested by clients
+pub struct CameraSensor<B: CameraBackend> {
+    backend: B,
+    config: CameraConfig,
+    sensor_settings: SensorSettings,
+    // Controls set by requests or the stored settings, latest last
+    controls: Vec<ControlValue>,
+    sensor_info: SensorInfo,
+}
+
+impl<B: CameraBackend> CameraSensor<B> {
+    pub fn new(backend: B, settings: &CameraSettings) -> Result<Self, DeviceError> {
+        let mut camera_sensor = CameraSensor {
+            backend,
+            config: CameraConfig {
+                pixel_format: settings.pixel_format.unwrap_or_default(),
+                frame_size: settings.frame_size.unwrap_or_default(),
+                jpeg_quality: settings.jpeg_quality.unwrap_or_default(),
+            },
+            sensor_settings: SensorSettings::default(),
+            controls: Vec::new(),
+            // Note: filled in once the driver has detected the sensor
+            sensor_info: SensorInfo {
+                pid: 0,
+                name: String::new(),
+                max_frame_size: FrameSize::default(),
+            },
+        };
+
+        camera_sensor.backend.init(&camera_sensor.config)?;
+        camera_sensor.sensor_info = camera_sensor.detect_sensor()?;
+        camera_sensor.sensor_settings = camera_sensor.backend.sensor_settings()?;
+        println!("camera: detected sensor: {:?}", camera_sensor.sensor_info);
+
+        // The requested frame size may be larger than the detected sensor supports
+        let max_frame_size = camera_sensor.sensor_info.max_frame_size;
+        if !camera_sensor
+            .sensor_info
+            .supports(camera_sensor.config.frame_size)
+        {
+            println!("camera: frame size limited to {:?}", max_frame_size);
+            camera_sensor.set_frame_size(max_frame_size)?;
+        }
+
+        // Note: a control the sensor does not implement (eg. after swapping it) is skipped
+        for control_value in &settings.controls {
+            if let Err(err) = camera_sensor.set_control(*control_value) {
+                println!("camera: skipping {}: {}", control_value, err);
+            }
+        }
+
+        Ok(camera_sensor)
+    }
+
+    // The driver the camera runs on, eg. for inspecting a mock
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn set_pixel_format(&mut self, pixel_format: PixelFormat) -> Result<(), DeviceError> {
+        if pixel_format == self.config.pixel_format {
+            return Ok(());
+        }
+
+        // Note: the driver sizes its frame buffers (and enables its JPEG mode) for the pixel format
+        // it was initialized with, so only raw formats of the same size can be switched on the
+        // sensor directly, everything else needs the driver reinitialized
+        if pixel_format.bytes_per_pixel().is_some()
+            && pixel_format.bytes_per_pixel() == self.config.pixel_format.bytes_per_pixel()
+        {
+            self.backend
+                .sensor_set(SensorSetting::PixelFormat(pixel_format))?;
+            self.config.pixel_format = pixel_format;
+        } else {
+            self.reinit(pixel_format)?;
+        }
+
+        println!("set: pixel format: {:#?}", pixel_format);
+        Ok(())
+    }
+
+    // Restart the camera driver with a different pixel format, falling back to the previous one
+    // if the driver fails to start (eg. not enough memory for the frame buffers)
+    fn reinit(&mut self, pixel_format: PixelFormat) -> Result<(), DeviceError> {
+        self.backend.deinit();
+
+        let previous = self.config.pixel_format;
+        self.config.pixel_format = pixel_format;
+        if let Err(err) = self.backend.init(&self.config) {
+            self.config.pixel_format = previous;
+            match self.backend.init(&self.config) {
+                Ok(()) => self.restore_controls(),
+                Err(restore_err) => {
+                    println!("error: failed to restore pixel format: {}", restore_err)
+                }
+            }
+            return Err(err);
+        }
+
+        self.restore_controls();
+        Ok(())
+    }
+
+    // Re-apply controls after the driver reset the sensor to its defaults
+    fn restore_controls(&mut self) {
+        let current = match self.backend.sensor_settings() {
+            Ok(current) => current,
+            Err(err) => {
+                println!("error: failed to restore sensor controls: {}", err);
+                return;
+            }
+        };
+
+        for control_value in self.sensor_settings.values() {
+            if current.get(control_value.control()) == control_value.value() {
+                continue;
+            }
+            if let Err(err) = self
+                .backend
+                .sensor_set(SensorSetting::Control(control_value))
+            {
+                println!("error: failed to restore sensor control: {}", err);
+            }
+        }
+    }
+
+    pub fn set_frame_size(&mut self, framesize: FrameSize) -> Result<(), DeviceError> {
+        // Note: the driver does not check this itself and would produce corrupt frames
+        if !self.sensor_info.supports(framesize) {
+            return Err(DeviceError::UnsupportedOption(format!(
+                "frame size {:?} exceeds the {} maximum of {:?}",
+                framesize, self.sensor_info.name, self.sensor_info.max_frame_size
+            )));
+        }
+
+        self.backend
+            .sensor_set(SensorSetting::FrameSize(framesize))?;
+        self.config.frame_size = framesize;
+
+        println!("set: frame size: {:#?}", framesize);
+        Ok(())
+    }
+
+    pub fn set_control(&mut self, control_value: ControlValue) -> Result<(), DeviceError> {
+        self.backend
+            .sensor_set(SensorSetting::Control(control_value))?;
+        self.sensor_settings.set(control_value);
+        self.controls
+            .retain(|set| set.control() != control_value.control());
+        self.controls.push(control_value);
+
+        println!("set: sensor control: {}", control_value);
+        Ok(())
+    }
+
+    pub fn set_jpeg_quality(&mut self, jpeg_quality: JpegQuality) -> Result<(), DeviceError> {
+        self.backend
+            .sensor_set(SensorSetting::JpegQuality(jpeg_quality))?;
+        self.config.jpeg_quality = jpeg_quality;
+
+        println!("set: jpeg quality: {}", jpeg_quality);
+        Ok(())
+    }
+
+    // Capture image using camera module
+    // Note: the frame buffer is handed back to the driver once the returned guard is dropped
+    pub fn capture_image(&self) -> Result<(ImageInfo, FrameBuffer<'_, B>), DeviceError> {
+        // Get the frame buffer from the camera driver
+        let fb = FrameBuffer::get(&self.backend).ok_or(DeviceError::FrameBufferUnavailable)?;
+        let info = ImageInfo {
+            width: fb.width() as u16,
+            height: fb.height() as u16,
+            // Note: reports the format of the buffer itself, which is what clients need to decode it
+            pixel_format: fb.pixel_format().unwrap_or(self.config.pixel_format),
+            timestamp_us: fb.timestamp_us(),
+        };
+
+        Ok((info, fb))
+    }
+
+    // Identify the sensor found by the driver by its product id
+    fn detect_sensor(&self) -> Result<SensorInfo, DeviceError> {
+        let id = self.backend.sensor_id()?;
+        let model = SensorModel::from_pid(id.pid);
+
+        // Note: prefer the known limits, falling back to the driver and then to the frame size the
+        // sensor was just initialized with
+        let max_frame_size = model
+            .max_frame_size()
+            .or(id.max_frame_size)
+            .unwrap_or(self.config.frame_size);
+
+        Ok(SensorInfo {
+            pid: id.pid,
+            name: id.name.unwrap_or_else(|| model.to_string()),
+            max_frame_size,
+        })
+    }
+
+    // Settings to initialize the camera with to get it back to how it is now
+    pub fn settings(&self) -> CameraSettings {
+        CameraSettings {
+            pixel_format: Some(self.config.pixel_format),
+            frame_size: Some(self.config.frame_size),
+            jpeg_quality: Some(self.config.jpeg_quality),
+            controls: self.controls.clone(),
+        }
+    }
+
+    pub fn status(&self) -> CameraStatus {
+        CameraStatus {
+            sensor: self.sensor_info.clone(),
+            frame_size: self.config.frame_size,
+            pixel_format: self.config.pixel_format,
+            jpeg_quality: self.config.jpeg_quality,
+            sensor_settings: self.sensor_settings,
+        }
+    }
+}
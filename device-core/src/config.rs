@@ -0,0 +1,337 @@
+// Settings the device keeps across restarts, and where it keeps them
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use protocol::{
+    ControlValue, DecodeError, DeviceSettings, FrameSize, JpegQuality, PixelFormat, Reader,
+    WifiConfig, Writer,
+};
+
+use crate::{DeviceError, KnownNetworks};
+
+// Camera settings the device boots with, unset ones are left to the driver's defaults
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CameraSettings {
+    pub pixel_format: Option<PixelFormat>,
+    pub frame_size: Option<FrameSize>,
+    pub jpeg_quality: Option<JpegQuality>,
+    // Sensor controls set over the protocol, applied over the sensor's defaults in this order
+    pub controls: Vec<ControlValue>,
+}
+
+// Everything the device stores: its settings, camera settings and networks
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceConfig {
+    pub settings: DeviceSettings,
+    pub camera: CameraSettings,
+    pub networks: KnownNetworks,
+    pub wifi: WifiConfig,
+}
+
+impl DeviceConfig {
+    // Version of the layout written by `encode`
+    pub const VERSION: u8 = 1;
+
+    // Layout: version (1) | `DeviceSettings::encode` length (1) | `DeviceSettings::encode` |
+    // pixel format (1 + 4) | frame size (1 + 4) | jpeg quality (1 + 1), each after a byte telling
+    // whether it is set | control count (1) | controls (5 each, see ControlValue) |
+    // `KnownNetworks::encode` length (2) | `KnownNetworks::encode` | `WifiConfig::encode`
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.u8(Self::VERSION);
+
+        let settings = self.settings.encode();
+        writer.u8(settings.len() as u8);
+        writer.bytes(&settings);
+
+        let camera = &self.camera;
+        writer.bool(camera.pixel_format.is_some());
+        if let Some(pixel_format) = camera.pixel_format {
+            writer.u32(pixel_format.into());
+        }
+        writer.bool(camera.frame_size.is_some());
+        if let Some(frame_size) = camera.frame_size {
+            writer.u32(frame_size.into());
+        }
+        writer.bool(camera.jpeg_quality.is_some());
+        if let Some(jpeg_quality) = camera.jpeg_quality {
+            writer.u8(jpeg_quality.get());
+        }
+        writer.u8(camera.controls.len() as u8);
+        for control_value in &camera.controls {
+            writer.bytes(&control_value.encode());
+        }
+
+        let networks = self.networks.encode();
+        writer.u16(networks.len() as u16);
+        writer.bytes(&networks);
+        writer.bytes(&self.wifi.encode());
+        writer.finish()
+    }
+
+    // Decode a config written by this or an earlier firmware
+    //
+    // Note: when the layout changes VERSION is bumped, and the layouts of earlier versions keep
+    // being decoded here and upgraded to the current one, so a firmware update keeps the config
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let Some((&version, bytes)) = bytes.split_first() else {
+            return Err(DecodeError::Truncated {
+                expected: 1,
+                actual: 0,
+            });
+        };
+        match version {
+            1 => Self::decode_v1(bytes),
+            // Note: written by a newer firmware, which may have changed what the fields mean
+            version => Err(DecodeError::UnsupportedVersion(version)),
+        }
+    }
+
+    fn decode_v1(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(bytes);
+
+        let settings_len = reader.u8()? as usize;
+        let settings = DeviceSettings::decode(reader.bytes(settings_len)?)?;
+
+        let pixel_format = match reader.bool()? {
+            true => Some(reader.u32()?.try_into()?),
+            false => None,
+        };
+        let frame_size = match reader.bool()? {
+            true => Some(reader.u32()?.try_into()?),
+            false => None,
+        };
+        let jpeg_quality = match reader.bool()? {
+            true => Some((reader.u8()? as u32).try_into()?),
+            false => None,
+        };
+        let mut controls = Vec::new();
+        for _ in 0..reader.u8()? {
+            controls.push(ControlValue::decode(reader.bytes(ControlValue::LEN)?)?);
+        }
+
+        let networks_len = reader.u16()? as usize;
+        let networks = KnownNetworks::decode(reader.bytes(networks_len)?)?;
+        let wifi = WifiConfig::decode(reader.rest())?;
+        reader.finish()?;
+
+        Ok(DeviceConfig {
+            settings,
+            camera: CameraSettings {
+                pixel_format,
+                frame_size,
+                jpeg_quality,
+                controls,
+            },
+            networks,
+            wifi,
+        })
+    }
+
+    // Config of a firmware from before the config existed, which only stored the networks and the
+    // wifi config (as `KnownNetworks::encode` and `WifiConfig::encode`), parts that can not be
+    // decoded are left to their defaults
+    pub fn from_wifi_store(networks: Option<&[u8]>, wifi: Option<&[u8]>) -> Self {
+        DeviceConfig {
+            networks: networks
+                .and_then(|bytes| KnownNetworks::decode(bytes).ok())
+                .unwrap_or_default(),
+            wifi: wifi
+                .and_then(|bytes| WifiConfig::decode(bytes).ok())
+                .unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+}
+
+// Where the config is kept between restarts (eg. a NVS blob on the device)
+pub trait ConfigStorage {
+    // The config as last written, None when none was written yet
+    fn read(&self) -> Result<Option<Vec<u8>>, DeviceError>;
+
+    fn write(&self, bytes: &[u8]) -> Result<(), DeviceError>;
+
+    // Remove the stored config, so the device boots with the defaults
+    fn erase(&self) -> Result<(), DeviceError>;
+}
+
+// The config the device booted with, changes are stored right away but most only apply from the
+// next boot (eg. networks and the port)
+pub struct ConfigStore<S: ConfigStorage> {
+    storage: S,
+    config: Mutex<DeviceConfig>,
+}
+
+impl<S: ConfigStorage> ConfigStore<S> {
+    // Note: a config that can not be read is replaced by the defaults, so the device still boots
+    // and can be provisioned again
+    pub fn open(storage: S) -> Self {
+        let config = match storage.read() {
+            Ok(Some(bytes)) => match DeviceConfig::decode(&bytes) {
+                Ok(config) => config,
+                Err(DecodeError::UnsupportedVersion(version)) => {
+                    println!(
+                        "config: using defaults, stored config is version {} (newer than this firmware)",
+                        version
+                    );
+                    DeviceConfig::default()
+                }
+                Err(err) => {
+                    println!("config: using defaults, stored config is invalid: {}", err);
+                    DeviceConfig::default()
+                }
+            },
+            Ok(None) => DeviceConfig::default(),
+            Err(err) => {
+                println!(
+                    "config: using defaults, failed to read stored config: {}",
+                    err
+                );
+                DeviceConfig::default()
+            }
+        };
+        ConfigStore {
+            storage,
+            config: Mutex::new(config),
+        }
+    }
+
+    pub fn get(&self) -> DeviceConfig {
+        self.lock().clone()
+    }
+
+    // Change the config and store it, the change is dropped if it can not be stored
+    pub fn update(&self, change: impl FnOnce(&mut DeviceConfig)) -> Result<(), DeviceError> {
+        let mut config = self.lock();
+        let mut changed = config.clone();
+        change(&mut changed);
+        if changed != *config {
+            self.storage.write(&changed.encode())?;
+            *config = changed;
+        }
+        Ok(())
+    }
+
+    pub fn factory_reset(&self) -> Result<(), DeviceError> {
+        let mut config = self.lock();
+        self.storage.erase()?;
+        *config = DeviceConfig::default();
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, DeviceConfig> {
+        self.config.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::{AccessPointMode, SensorControl, WifiCredentials};
+
+    use super::*;
+
+    fn config() -> DeviceConfig {
+        let mut networks = KnownNetworks::default();
+        networks.add(WifiCredentials::new("office", "").unwrap());
+        networks.add(WifiCredentials::new("home", "password").unwrap());
+        DeviceConfig {
+            settings: DeviceSettings::new("garage", 8081, false).unwrap(),
+            camera: CameraSettings {
+                pixel_format: Some(PixelFormat::RGB565),
+                frame_size: Some(FrameSize::VGA),
+                jpeg_quality: Some(JpegQuality::try_from(20u32).unwrap()),
+                controls: vec![
+                    SensorControl::Brightness.value(-2).unwrap(),
+                    SensorControl::Exposure.value(1200).unwrap(),
+                ],
+            },
+            networks,
+            wifi: WifiConfig::new(true, AccessPointMode::Always, 6, "garage-cam", "password")
+                .unwrap(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let config = config();
+        assert_eq!(DeviceConfig::decode(&config.encode()), Ok(config));
+    }
+
+    #[test]
+    fn default_round_trip() {
+        let config = DeviceConfig::default();
+        assert_eq!(DeviceConfig::decode(&config.encode()), Ok(config));
+    }
+
+    #[test]
+    fn encode_starts_with_version() {
+        assert_eq!(config().encode()[0], DeviceConfig::VERSION);
+    }
+
+    #[test]
+    fn unknown_version_is_rejected() {
+        let mut bytes = config().encode();
+        bytes[0] = DeviceConfig::VERSION + 1;
+        assert_eq!(
+            DeviceConfig::decode(&bytes),
+            Err(DecodeError::UnsupportedVersion(DeviceConfig::VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn truncated_is_rejected() {
+        let bytes = config().encode();
+        for len in 0..bytes.len() {
+            assert!(
+                DeviceConfig::decode(&bytes[..len]).is_err(),
+                "decoded {} of {} bytes",
+                len,
+                bytes.len()
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_setting_is_rejected() {
+        let mut config = config();
+        config.camera.frame_size = None;
+        config.camera.pixel_format = None;
+        config.camera.jpeg_quality = None;
+        let mut bytes = config.encode();
+        // Note: the first control's value follows the version, the settings, the three unset
+        // camera settings and the control count
+        let value = 1 + 1 + config.settings.encode().len() + 3 + 1 + 1;
+        bytes[value..value + 4].copy_from_slice(&100i32.to_be_bytes());
+        assert!(DeviceConfig::decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn migrates_wifi_store() {
+        let expected = config();
+        let migrated = DeviceConfig::from_wifi_store(
+            Some(&expected.networks.encode()),
+            Some(&expected.wifi.encode()),
+        );
+        assert_eq!(migrated.networks, expected.networks);
+        assert_eq!(migrated.wifi, expected.wifi);
+        assert_eq!(migrated.settings, DeviceSettings::default());
+        assert_eq!(migrated.camera, CameraSettings::default());
+    }
+
+    #[test]
+    fn migrates_partial_wifi_store() {
+        let expected = config();
+        let migrated = DeviceConfig::from_wifi_store(Some(&expected.networks.encode()), None);
+        assert_eq!(migrated.networks, expected.networks);
+        assert_eq!(migrated.wifi, WifiConfig::default());
+
+        // Note: a record that can not be decoded is left to its default, the other one is kept
+        let migrated = DeviceConfig::from_wifi_store(Some(&[9, 1]), Some(&expected.wifi.encode()));
+        assert_eq!(migrated.networks, KnownNetworks::default());
+        assert_eq!(migrated.wifi, expected.wifi);
+
+        assert_eq!(
+            DeviceConfig::from_wifi_store(None, None),
+            DeviceConfig::default()
+        );
+    }
+}
@@ -0,0 +1,87 @@
+use std::fmt;
+
+use protocol::{DecodeError, ErrorCode, OutgoingPacket};
+
+// Errors raised while handling a request, reported back to the client instead of panicking
+#[derive(Debug, Clone)]
+pub enum DeviceError {
+    // The camera driver failed to initialize, with its esp_err_t code and name
+    CameraInit { code: i32, name: &'static str },
+    // The driver did not hand out a frame buffer
+    FrameBufferUnavailable,
+    // The driver has no sensor (eg. it is not initialized)
+    SensorUnavailable,
+    // Sensor does not implement the setter or it returned a non-zero status
+    SensorSet { setting: &'static str, status: i32 },
+    // Request could not be decoded
+    BadPacket(DecodeError),
+    // Request asks for something the device cannot do
+    UnsupportedOption(String),
+    // A setting could not be stored (eg. the flash is worn out or full)
+    Storage(String),
+    // Client did not complete the handshake (eg. it sent a packet first or does not know the key)
+    Unauthorized(String),
+}
+
+impl DeviceError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            DeviceError::CameraInit { .. } => ErrorCode::CameraInit,
+            DeviceError::FrameBufferUnavailable => ErrorCode::FrameBufferUnavailable,
+            DeviceError::SensorUnavailable | DeviceError::SensorSet { .. } => ErrorCode::SensorSet,
+            DeviceError::BadPacket(_) => ErrorCode::BadPacket,
+            DeviceError::UnsupportedOption(_) => ErrorCode::UnsupportedOption,
+            DeviceError::Storage(_) => ErrorCode::Storage,
+            DeviceError::Unauthorized(_) => ErrorCode::Unauthorized,
+        }
+    }
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceError::CameraInit { code, name } => {
+                write!(f, "camera: init failed: {} ({:#x})", name, code)
+            }
+            DeviceError::FrameBufferUnavailable => {
+                f.write_str("camera: failed to get frame buffer")
+            }
+            DeviceError::SensorUnavailable => f.write_str("camera: failed to get sensor"),
+            DeviceError::SensorSet { setting, status } => {
+                write!(f, "camera: failed to set {} (status {})", setting, status)
+            }
+            DeviceError::BadPacket(err) => write!(f, "{}", err),
+            DeviceError::UnsupportedOption(option) => write!(f, "unsupported option: {}", option),
+            DeviceError::Storage(reason) => write!(f, "storage: {}", reason),
+            DeviceError::Unauthorized(reason) => write!(f, "unauthorized: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for DeviceError {}
+
+// Out of range option values are valid packets asking for an unsupported option
+impl From<DecodeError> for DeviceError {
+    fn from(err: DecodeError) -> Self {
+        match err {
+            DecodeError::InvalidFrameSize(_)
+            | DecodeError::InvalidPixelFormat(_)
+            | DecodeError::InvalidJpegQuality(_)
+            | DecodeError::InvalidSensorControl(_)
+            | DecodeError::InvalidControlValue { .. }
+            | DecodeError::InvalidWifiCredentials
+            | DecodeError::InvalidWifiConfig
+            | DecodeError::InvalidDeviceSettings => DeviceError::UnsupportedOption(err.to_string()),
+            _ => DeviceError::BadPacket(err),
+        }
+    }
+}
+
+impl From<&DeviceError> for OutgoingPacket {
+    fn from(err: &DeviceError) -> Self {
+        OutgoingPacket::Error {
+            code: err.code(),
+            message: err.to_string(),
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::fmt;
+use std::mem::ManuallyDrop;
+use std::ops::Deref;
+
+use protocol::PixelFormat;
+
+use crate::{CameraBackend, RawFrame};
+
+// Frame borrowed from the camera driver, returned to it when dropped
+//
+// Note: the driver reuses the buffer as soon as it is returned, borrowing the backend keeps the
+// image data from outliving the frame (or the driver being reinitialized while it is held)
+pub struct FrameBuffer<'a, B: CameraBackend> {
+    backend: &'a B,
+    frame: ManuallyDrop<B::Frame>,
+}
+
+impl<'a, B: CameraBackend> FrameBuffer<'a, B> {
+    // Take the next frame from the driver, None if no frame buffer could be obtained
+    pub fn get(backend: &'a B) -> Option<Self> {
+        let frame = backend.frame_get()?;
+        Some(FrameBuffer {
+            backend,
+            frame: ManuallyDrop::new(frame),
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.frame.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.frame.height()
+    }
+
+    pub fn pixel_format(&self) -> Option<PixelFormat> {
+        self.frame.pixel_format()
+    }
+
+    pub fn timestamp_us(&self) -> u64 {
+        self.frame.timestamp_us()
+    }
+}
+
+impl<B: CameraBackend> Deref for FrameBuffer<'_, B> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.frame.data()
+    }
+}
+
+impl<B: CameraBackend> Drop for FrameBuffer<'_, B> {
+    fn drop(&mut self) {
+        // Safety: the frame is not used again after being taken
+        let frame = unsafe { ManuallyDrop::take(&mut self.frame) };
+        self.backend.frame_return(frame);
+    }
+}
+
+impl<B: CameraBackend> fmt::Debug for FrameBuffer<'_, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FrameBuffer")
+            .field("len", &self.len())
+            .field("width", &self.width())
+            .field("height", &self.height())
+            .field("pixel_format", &self.pixel_format())
+            .field("timestamp_us", &self.timestamp_us())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::{FrameSize, JpegQuality};
+
+    use super::*;
+    use crate::{CameraConfig, MockCamera};
+
+    fn backend() -> MockCamera {
+        let mut backend = MockCamera::default();
+        backend
+            .init(&CameraConfig {
+                pixel_format: PixelFormat::GRAYSCALE,
+                frame_size: FrameSize::QQVGA,
+                jpeg_quality: JpegQuality::default(),
+            })
+            .unwrap();
+        backend
+    }
+
+    #[test]
+    fn frame_is_returned_once_on_drop() {
+        let backend = backend();
+        let frame = FrameBuffer::get(&backend).unwrap();
+        assert_eq!(backend.frames_out(), 1);
+        assert_eq!((frame.width(), frame.height()), (160, 120));
+        assert_eq!(frame.pixel_format(), Some(PixelFormat::GRAYSCALE));
+        assert_eq!(frame.len(), 160 * 120);
+
+        drop(frame);
+        assert_eq!(backend.frames_out(), 0);
+    }
+
+    #[test]
+    fn frames_are_returned_independently() {
+        let backend = backend();
+        let first = FrameBuffer::get(&backend).unwrap();
+        let second = FrameBuffer::get(&backend).unwrap();
+        assert_eq!(backend.frames_out(), 2);
+
+        drop(second);
+        assert_eq!(backend.frames_out(), 1);
+        drop(first);
+        assert_eq!(backend.frames_out(), 0);
+    }
+
+    #[test]
+    fn no_frame_when_capture_fails() {
+        let backend = backend();
+        backend.set_fail_capture(true);
+        assert!(FrameBuffer::get(&backend).is_none());
+        assert_eq!(backend.frames_out(), 0);
+    }
+}
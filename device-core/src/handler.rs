@@ -0,0 +1,377 @@
+use protocol::{ImageInfo, IncomingPacket, OutgoingPacket};
+
+use crate::{CameraBackend, CameraSensor, DeviceError, FrameBuffer, System};
+
+// Camera as seen by the request handler, a failed init is reported to each request
+pub type Camera<B> = Result<CameraSensor<B>, DeviceError>;
+
+// Response to a request, captures are sent straight from the camera's frame buffer
+pub enum Response<'a, B: CameraBackend> {
+    Packet(OutgoingPacket),
+    Capture {
+        info: ImageInfo,
+        frame_buffer: FrameBuffer<'a, B>,
+    },
+}
+
+impl<B: CameraBackend> Response<'_, B> {
+    // Write the response answering the request with `request_id`
+    pub fn write_to<W: std::io::Write>(
+        self,
+        writer: &mut W,
+        request_id: u32,
+    ) -> std::io::Result<()> {
+        match self {
+            Response::Packet(packet) => packet.into_frame(request_id).write_to(writer),
+            Response::Capture { info, frame_buffer } => {
+                OutgoingPacket::write_capture_to(writer, request_id, &info, &frame_buffer)
+            }
+        }
+    }
+}
+
+impl<B: CameraBackend> From<OutgoingPacket> for Response<'_, B> {
+    fn from(packet: OutgoingPacket) -> Self {
+        Response::Packet(packet)
+    }
+}
+
+// TODO: encapsulate instruction handlers
+// Note: `IncomingPacket::Restart` is only acknowledged (as are the packets changing the config once
+// it is stored), restarting is up to the caller once the response has been sent
+pub fn handle_packet<'a, B: CameraBackend>(
+    packet: IncomingPacket,
+    camera: &'a mut Camera<B>,
+    system: &impl System,
+) -> Result<Response<'a, B>, DeviceError> {
+    let camera_sensor = camera.as_mut().map_err(|err| err.clone());
+
+    let response = match packet {
+        IncomingPacket::Capture => {
+            let (info, frame_buffer) = camera_sensor?.capture_image()?;
+            return Ok(Response::Capture { info, frame_buffer });
+        }
+        IncomingPacket::SetFrameSize(frame_size) => {
+            let camera_sensor = camera_sensor?;
+            camera_sensor.set_frame_size(frame_size)?;
+            persist_camera(camera_sensor, system);
+            OutgoingPacket::SetFrameSize(true)
+        }
+        IncomingPacket::SetPixelFormat(pixel_format) => {
+            let camera_sensor = camera_sensor?;
+            camera_sensor.set_pixel_format(pixel_format)?;
+            persist_camera(camera_sensor, system);
+            OutgoingPacket::SetPixelFormat(true)
+        }
+        IncomingPacket::SetJpegQuality(jpeg_quality) => {
+            let camera_sensor = camera_sensor?;
+            camera_sensor.set_jpeg_quality(jpeg_quality)?;
+            persist_camera(camera_sensor, system);
+            OutgoingPacket::SetJpegQuality(true)
+        }
+        IncomingPacket::SetControl(control_value) => {
+            let camera_sensor = camera_sensor?;
+            camera_sensor.set_control(control_value)?;
+            persist_camera(camera_sensor, system);
+            OutgoingPacket::SetControl(true)
+        }
+        IncomingPacket::GetStatus => {
+            // Note: the status is still useful when the camera failed to initialize
+            let camera_status = camera_sensor
+                .ok()
+                .map(|camera_sensor| camera_sensor.status());
+            OutgoingPacket::Status(system.device_status(camera_status))
+        }
+        IncomingPacket::SetWifi(credentials) => {
+            system
+                .config()
+                .update(|config| config.networks.add(credentials))?;
+            OutgoingPacket::SetWifi(true)
+        }
+        IncomingPacket::SetWifiConfig(wifi) => {
+            system.config().update(|config| config.wifi = wifi)?;
+            OutgoingPacket::SetWifiConfig(true)
+        }
+        IncomingPacket::SetDeviceSettings(settings) => {
+            system
+                .config()
+                .update(|config| config.settings = settings)?;
+            OutgoingPacket::SetDeviceSettings(true)
+        }
+        IncomingPacket::FactoryReset => {
+            system.config().factory_reset()?;
+            OutgoingPacket::FactoryReset(true)
+        }
+        IncomingPacket::Restart => OutgoingPacket::Restart(true),
+        IncomingPacket::Ping => OutgoingPacket::Ping,
+    };
+
+    Ok(response.into())
+}
+
+// Store the camera settings a request changed, for devices set to keep them
+// Note: the request still succeeds if they can not be stored, the camera uses them until it restarts
+fn persist_camera<B: CameraBackend>(camera_sensor: &CameraSensor<B>, system: &impl System) {
+    let config = system.config();
+    if !config.get().settings.persist_camera() {
+        return;
+    }
+    if let Err(err) = config.update(|config| config.camera = camera_sensor.settings()) {
+        println!("config: failed to store camera settings: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::TcpStream;
+    use std::sync::Mutex;
+
+    use protocol::{
+        CameraStatus, DeviceStatus, Frame, FrameSize, JpegQuality, PixelFormat, ResetReason,
+        SensorModel,
+    };
+
+    use super::*;
+    use crate::{
+        CameraConfig, CameraSettings, ConfigStorage, ConfigStore, MockCamera, SensorSetting,
+    };
+
+    // Config kept in memory
+    #[derive(Default)]
+    struct MemoryStorage(Mutex<Option<Vec<u8>>>);
+
+    impl ConfigStorage for MemoryStorage {
+        fn read(&self) -> Result<Option<Vec<u8>>, DeviceError> {
+            Ok(self.0.lock().unwrap().clone())
+        }
+
+        fn write(&self, bytes: &[u8]) -> Result<(), DeviceError> {
+            *self.0.lock().unwrap() = Some(bytes.to_vec());
+            Ok(())
+        }
+
+        fn erase(&self) -> Result<(), DeviceError> {
+            *self.0.lock().unwrap() = None;
+            Ok(())
+        }
+    }
+
+    struct TestSystem {
+        config: ConfigStore<MemoryStorage>,
+    }
+
+    impl TestSystem {
+        fn new() -> Self {
+            TestSystem {
+                config: ConfigStore::open(MemoryStorage::default()),
+            }
+        }
+    }
+
+    impl System for TestSystem {
+        type Stream = TcpStream;
+        type Storage = MemoryStorage;
+
+        fn device_status(&self, camera: Option<CameraStatus>) -> DeviceStatus {
+            DeviceStatus {
+                firmware_version: "test".into(),
+                uptime_ms: 0,
+                free_heap: 0,
+                free_psram: 0,
+                reset_reason: ResetReason::PowerOn,
+                wifi: None,
+                camera,
+            }
+        }
+
+        fn open_stream(&self, stream: TcpStream) -> io::Result<TcpStream> {
+            Ok(stream)
+        }
+
+        fn config(&self) -> &ConfigStore<MemoryStorage> {
+            &self.config
+        }
+
+        fn restart(&self) {}
+    }
+
+    fn camera(backend: MockCamera) -> Camera<MockCamera> {
+        CameraSensor::new(backend, &CameraSettings::default())
+    }
+
+    fn backend(camera: &Camera<MockCamera>) -> &MockCamera {
+        camera.as_ref().unwrap().backend()
+    }
+
+    // Handle a request answered with a packet
+    fn request(
+        packet: IncomingPacket,
+        camera: &mut Camera<MockCamera>,
+        system: &TestSystem,
+    ) -> Result<OutgoingPacket, DeviceError> {
+        match handle_packet(packet, camera, system)? {
+            Response::Packet(packet) => Ok(packet),
+            Response::Capture { .. } => panic!("unexpected capture"),
+        }
+    }
+
+    #[test]
+    fn capture_sends_frame_and_returns_buffer() {
+        let system = TestSystem::new();
+        let mut camera = camera(MockCamera::new(SensorModel::OV2640));
+        request(
+            IncomingPacket::SetFrameSize(FrameSize::QQVGA),
+            &mut camera,
+            &system,
+        )
+        .unwrap();
+
+        let mut sent = Vec::new();
+        match handle_packet(IncomingPacket::Capture, &mut camera, &system).unwrap() {
+            response @ Response::Capture { .. } => response.write_to(&mut sent, 7).unwrap(),
+            Response::Packet(packet) => panic!("expected a capture, got {:?}", packet),
+        }
+        assert_eq!(backend(&camera).frames_out(), 0);
+
+        let frame = Frame::read_from(&mut sent.as_slice()).unwrap();
+        assert_eq!(frame.request_id, 7);
+        match OutgoingPacket::from_frame(&frame).unwrap() {
+            OutgoingPacket::Capture { info, data } => {
+                assert_eq!((info.width, info.height), (160, 120));
+                assert_eq!(info.pixel_format, PixelFormat::JPEG);
+                assert!(data.starts_with(&[0xff, 0xd8]));
+            }
+            packet => panic!("expected a capture, got {:?}", packet),
+        }
+    }
+
+    #[test]
+    fn set_frame_size_applies_setting() {
+        let system = TestSystem::new();
+        let mut camera = camera(MockCamera::new(SensorModel::OV2640));
+
+        let response = request(
+            IncomingPacket::SetFrameSize(FrameSize::VGA),
+            &mut camera,
+            &system,
+        );
+        assert!(matches!(response, Ok(OutgoingPacket::SetFrameSize(true))));
+        assert_eq!(
+            backend(&camera).applied(),
+            [SensorSetting::FrameSize(FrameSize::VGA)]
+        );
+        assert_eq!(
+            system.config().get().camera.frame_size,
+            Some(FrameSize::VGA)
+        );
+    }
+
+    #[test]
+    fn set_frame_size_beyond_sensor_is_rejected() {
+        let system = TestSystem::new();
+        let mut camera = camera(MockCamera::new(SensorModel::OV2640));
+
+        let response = request(
+            IncomingPacket::SetFrameSize(FrameSize::QSXGA),
+            &mut camera,
+            &system,
+        );
+        assert!(matches!(response, Err(DeviceError::UnsupportedOption(_))));
+        assert!(backend(&camera).applied().is_empty());
+    }
+
+    #[test]
+    fn set_pixel_format_reinitializes_driver() {
+        let system = TestSystem::new();
+        let mut camera = camera(MockCamera::new(SensorModel::OV2640));
+
+        let response = request(
+            IncomingPacket::SetPixelFormat(PixelFormat::RGB565),
+            &mut camera,
+            &system,
+        );
+        assert!(matches!(response, Ok(OutgoingPacket::SetPixelFormat(true))));
+        assert_eq!(backend(&camera).init_count(), 2);
+        assert_eq!(
+            backend(&camera).config().map(|config| config.pixel_format),
+            Some(PixelFormat::RGB565)
+        );
+
+        // Note: formats of the same size are switched on the sensor without reinitializing
+        let response = request(
+            IncomingPacket::SetPixelFormat(PixelFormat::YUV422),
+            &mut camera,
+            &system,
+        );
+        assert!(matches!(response, Ok(OutgoingPacket::SetPixelFormat(true))));
+        assert_eq!(backend(&camera).init_count(), 2);
+        assert_eq!(
+            backend(&camera).applied(),
+            [SensorSetting::PixelFormat(PixelFormat::YUV422)]
+        );
+    }
+
+    #[test]
+    fn set_jpeg_quality_applies_setting() {
+        let system = TestSystem::new();
+        let mut camera = camera(MockCamera::new(SensorModel::OV2640));
+        let jpeg_quality = JpegQuality::try_from(20u32).unwrap();
+
+        let response = request(
+            IncomingPacket::SetJpegQuality(jpeg_quality),
+            &mut camera,
+            &system,
+        );
+        assert!(matches!(response, Ok(OutgoingPacket::SetJpegQuality(true))));
+        assert_eq!(
+            backend(&camera).applied(),
+            [SensorSetting::JpegQuality(jpeg_quality)]
+        );
+        assert_eq!(
+            backend(&camera).config(),
+            Some(CameraConfig {
+                jpeg_quality,
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn failed_init_is_reported_to_each_request() {
+        let system = TestSystem::new();
+        let mut backend = MockCamera::new(SensorModel::OV2640);
+        backend.set_fail_init(true);
+        let mut camera = camera(backend);
+
+        assert!(matches!(
+            handle_packet(IncomingPacket::Capture, &mut camera, &system),
+            Err(DeviceError::CameraInit { .. })
+        ));
+        let response = request(
+            IncomingPacket::SetFrameSize(FrameSize::VGA),
+            &mut camera,
+            &system,
+        );
+        assert!(matches!(response, Err(DeviceError::CameraInit { .. })));
+
+        // Note: the status is still answered, without the camera
+        match request(IncomingPacket::GetStatus, &mut camera, &system) {
+            Ok(OutgoingPacket::Status(status)) => assert!(status.camera.is_none()),
+            response => panic!("expected a status, got {:?}", response),
+        }
+    }
+
+    #[test]
+    fn failed_capture_is_reported() {
+        let system = TestSystem::new();
+        let mut camera = camera(MockCamera::new(SensorModel::OV2640));
+        backend(&camera).set_fail_capture(true);
+
+        assert!(matches!(
+            handle_packet(IncomingPacket::Capture, &mut camera, &system),
+            Err(DeviceError::FrameBufferUnavailable)
+        ));
+        assert_eq!(backend(&camera).frames_out(), 0);
+    }
+}
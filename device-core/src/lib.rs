@@ -0,0 +1,25 @@
+// Camera handling, request dispatch and the TCP server of the device firmware, which do not
+// depend on esp-idf so they build and run on the host
+mod backend;
+mod camera;
+mod config;
+mod error;
+mod framebuffer;
+mod handler;
+#[cfg(any(test, feature = "mock"))]
+mod mock;
+mod server;
+mod system;
+mod wifi;
+
+pub use backend::{CameraBackend, CameraConfig, RawFrame, SensorId, SensorSetting};
+pub use camera::CameraSensor;
+pub use config::{CameraSettings, ConfigStorage, ConfigStore, DeviceConfig};
+pub use error::DeviceError;
+pub use framebuffer::FrameBuffer;
+pub use handler::{handle_packet, Camera, Response};
+#[cfg(any(test, feature = "mock"))]
+pub use mock::{colour_bars, FrameSource, MockCamera, MockFrame};
+pub use server::{serve, SharedCamera};
+pub use system::System;
+pub use wifi::{ConnectionManager, KnownNetworks, WifiAction, WifiEvent, WifiState, WifiTiming};
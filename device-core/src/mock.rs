@@ -0,0 +1,289 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::time::Instant;
+
+use jpeg_encoder::{ColorType, Encoder};
+use protocol::{FrameSize, JpegQuality, PixelFormat, SensorModel, SensorSettings};
+
+use crate::{CameraBackend, CameraConfig, DeviceError, RawFrame, SensorId, SensorSetting};
+
+// esp_err_t returned by esp_camera_init when no sensor answers on the SCCB bus
+const ESP_ERR_CAMERA_NOT_DETECTED: i32 = 0x20001;
+
+// Colour bars (white, yellow, cyan, green, magenta, red, blue, black) as RGB
+const COLOUR_BARS: [[u8; 3]; 8] = [
+    [255, 255, 255],
+    [255, 255, 0],
+    [0, 255, 255],
+    [0, 255, 0],
+    [255, 0, 255],
+    [255, 0, 0],
+    [0, 0, 255],
+    [0, 0, 0],
+];
+
+// Produces the RGB888 pixels of frame number `index` at `width` x `height`, None if no frame is
+// available (reported as a failed capture)
+pub type FrameSource = Box<dyn Fn(u32, u32, u32) -> Option<Vec<u8>> + Send + Sync>;
+
+// Camera driver that runs on the host, recording the settings it was given
+//
+// Frames come from a `FrameSource`, by default colour bars that shift by a few pixels each frame so
+// consecutive captures can be told apart. They are converted to the configured pixel format (and
+// JPEG quality) like the sensor would.
+pub struct MockCamera {
+    model: SensorModel,
+    source: FrameSource,
+    // None while the driver is not initialized
+    config: Option<CameraConfig>,
+    settings: SensorSettings,
+    applied: Vec<SensorSetting>,
+    init_count: usize,
+    fail_init: bool,
+    fail_capture: AtomicBool,
+    frame_count: AtomicU32,
+    frames_out: AtomicUsize,
+    started: Instant,
+}
+
+impl MockCamera {
+    pub fn new(model: SensorModel) -> Self {
+        Self::with_source(
+            model,
+            Box::new(|width, height, index| Some(colour_bars(width, height, index))),
+        )
+    }
+
+    pub fn with_source(model: SensorModel, source: FrameSource) -> Self {
+        MockCamera {
+            model,
+            source,
+            config: None,
+            settings: SensorSettings::default(),
+            applied: Vec::new(),
+            init_count: 0,
+            fail_init: false,
+            fail_capture: AtomicBool::new(false),
+            frame_count: AtomicU32::new(0),
+            frames_out: AtomicUsize::new(0),
+            started: Instant::now(),
+        }
+    }
+
+    // Make `init` fail as if no sensor was connected
+    pub fn set_fail_init(&mut self, fail: bool) {
+        self.fail_init = fail;
+    }
+
+    // Make `frame_get` fail as if the driver ran out of frame buffers
+    pub fn set_fail_capture(&self, fail: bool) {
+        self.fail_capture.store(fail, Ordering::SeqCst);
+    }
+
+    // Config of the last successful `init`, None while not initialized
+    pub fn config(&self) -> Option<CameraConfig> {
+        self.config
+    }
+
+    // Every setting passed to `sensor_set`, in order
+    pub fn applied(&self) -> &[SensorSetting] {
+        &self.applied
+    }
+
+    // Number of successful `init` calls
+    pub fn init_count(&self) -> usize {
+        self.init_count
+    }
+
+    // Frames taken with `frame_get` that have not been returned yet
+    pub fn frames_out(&self) -> usize {
+        self.frames_out.load(Ordering::SeqCst)
+    }
+
+    fn running_config(&self) -> Result<CameraConfig, DeviceError> {
+        self.config.ok_or(DeviceError::SensorUnavailable)
+    }
+}
+
+impl Default for MockCamera {
+    fn default() -> Self {
+        MockCamera::new(SensorModel::OV2640)
+    }
+}
+
+impl CameraBackend for MockCamera {
+    type Frame = MockFrame;
+
+    fn init(&mut self, config: &CameraConfig) -> Result<(), DeviceError> {
+        if self.fail_init {
+            return Err(DeviceError::CameraInit {
+                code: ESP_ERR_CAMERA_NOT_DETECTED,
+                name: "ESP_ERR_CAMERA_NOT_DETECTED",
+            });
+        }
+
+        // Note: like the real driver, the sensor starts over from its defaults
+        self.config = Some(*config);
+        self.settings = SensorSettings::default();
+        self.init_count += 1;
+        Ok(())
+    }
+
+    fn deinit(&mut self) {
+        self.config = None;
+    }
+
+    fn frame_get(&self) -> Option<MockFrame> {
+        let config = self.config?;
+        if self.fail_capture.load(Ordering::SeqCst) {
+            return None;
+        }
+
+        let frame_index = self.frame_count.fetch_add(1, Ordering::SeqCst);
+        let (width, height) = config.frame_size.dimensions();
+        let rgb = (self.source)(width, height, frame_index)?;
+        let data = match config.pixel_format {
+            PixelFormat::RGB565 => rgb565(&rgb),
+            PixelFormat::YUV422 => yuv422(&rgb),
+            PixelFormat::GRAYSCALE => rgb.chunks_exact(3).map(luma).collect(),
+            PixelFormat::JPEG => jpeg(&rgb, config.frame_size, config.jpeg_quality)?,
+        };
+
+        self.frames_out.fetch_add(1, Ordering::SeqCst);
+        Some(MockFrame {
+            data,
+            width,
+            height,
+            pixel_format: config.pixel_format,
+            timestamp_us: self.started.elapsed().as_micros() as u64,
+        })
+    }
+
+    fn frame_return(&self, _frame: MockFrame) {
+        self.frames_out.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    fn sensor_id(&self) -> Result<SensorId, DeviceError> {
+        self.running_config()?;
+        Ok(SensorId {
+            pid: self.model.pid(),
+            name: None,
+            max_frame_size: self.model.max_frame_size(),
+        })
+    }
+
+    fn sensor_settings(&self) -> Result<SensorSettings, DeviceError> {
+        self.running_config()?;
+        Ok(self.settings)
+    }
+
+    fn sensor_set(&mut self, setting: SensorSetting) -> Result<(), DeviceError> {
+        let mut config = self.running_config()?;
+        match setting {
+            SensorSetting::PixelFormat(pixel_format) => config.pixel_format = pixel_format,
+            SensorSetting::FrameSize(frame_size) => config.frame_size = frame_size,
+            SensorSetting::JpegQuality(jpeg_quality) => config.jpeg_quality = jpeg_quality,
+            SensorSetting::Control(control_value) => self.settings.set(control_value),
+        }
+
+        self.config = Some(config);
+        self.applied.push(setting);
+        Ok(())
+    }
+}
+
+// Frame produced by `MockCamera`
+#[derive(Debug)]
+pub struct MockFrame {
+    data: Vec<u8>,
+    width: u32,
+    height: u32,
+    pixel_format: PixelFormat,
+    timestamp_us: u64,
+}
+
+impl RawFrame for MockFrame {
+    fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn width(&self) -> u32 {
+        self.width
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn pixel_format(&self) -> Option<PixelFormat> {
+        Some(self.pixel_format)
+    }
+
+    fn timestamp_us(&self) -> u64 {
+        self.timestamp_us
+    }
+}
+
+// RGB888 pixels of vertical colour bars, shifted right by 4 pixels per frame
+pub fn colour_bars(width: u32, height: u32, frame_index: u32) -> Vec<u8> {
+    let bar_width = width.div_ceil(COLOUR_BARS.len() as u32);
+    let shift = frame_index.wrapping_mul(4) % width;
+
+    let row: Vec<u8> = (0..width)
+        .flat_map(|x| COLOUR_BARS[(((x + width - shift) % width) / bar_width) as usize])
+        .collect();
+    row.repeat(height as usize)
+}
+
+// Big-endian like the esp32-camera driver produces it
+fn rgb565(rgb: &[u8]) -> Vec<u8> {
+    rgb.chunks_exact(3)
+        .flat_map(|pixel| {
+            let value = (u16::from(pixel[0]) >> 3) << 11
+                | (u16::from(pixel[1]) >> 2) << 5
+                | u16::from(pixel[2]) >> 3;
+            value.to_be_bytes()
+        })
+        .collect()
+}
+
+// YUYV, each pair of pixels shares its chroma
+fn yuv422(rgb: &[u8]) -> Vec<u8> {
+    rgb.chunks_exact(6)
+        .flat_map(|pair| {
+            let (first, second) = pair.split_at(3);
+            let (u, v) = chroma(first);
+            [luma(first), u, luma(second), v]
+        })
+        .collect()
+}
+
+// BT.601
+fn luma(pixel: &[u8]) -> u8 {
+    let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(f32::from);
+    (0.299 * r + 0.587 * g + 0.114 * b).round() as u8
+}
+
+fn chroma(pixel: &[u8]) -> (u8, u8) {
+    let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(f32::from);
+    let u = 128.0 - 0.168736 * r - 0.331264 * g + 0.5 * b;
+    let v = 128.0 + 0.5 * r - 0.418688 * g - 0.081312 * b;
+    (
+        u.round().clamp(0.0, 255.0) as u8,
+        v.round().clamp(0.0, 255.0) as u8,
+    )
+}
+
+// Note: the sensor's quality goes from 0 (best) to 63, the encoder's from 100 (best) to 1
+fn jpeg(rgb: &[u8], frame_size: FrameSize, jpeg_quality: JpegQuality) -> Option<Vec<u8>> {
+    let quality = 100 - u32::from(jpeg_quality) * 99 / u32::from(JpegQuality::MAX);
+    let mut data = Vec::new();
+    Encoder::new(&mut data, quality as u8)
+        .encode(
+            rgb,
+            frame_size.width() as u16,
+            frame_size.height() as u16,
+            ColorType::Rgb,
+        )
+        .ok()?;
+    Some(data)
+}
@@ -0,0 +1,324 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use protocol::{
+    DecodeError, ErrorCode, Frame, Handshake, HandshakeRequest, HandshakeResponse, IncomingPacket,
+    OutgoingPacket, PresharedKey, RecordCipher, Role, SecureStream, NONCE_LEN,
+};
+
+use crate::{handle_packet, Camera, CameraBackend, DeviceError, Response, System};
+
+// Clients that do not complete the handshake within this long of connecting are dropped, however
+// slowly they send, so connections that never authenticate can not hold on to the session slots
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+// Sessions are closed after this long without a request, clients can send `Ping` to stay connected
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+// Each session runs on its own thread, limited to keep RAM usage predictable
+const MAX_SESSIONS: usize = 3;
+
+// Camera shared by all sessions, requests are serialized by the lock
+pub type SharedCamera<B> = Arc<Mutex<Camera<B>>>;
+
+// Accept connections and serve each one on its own thread, to clients that prove they know `key`
+pub fn serve<B, S>(
+    listener: TcpListener,
+    camera: SharedCamera<B>,
+    system: Arc<S>,
+    key: PresharedKey,
+) -> io::Result<()>
+where
+    B: CameraBackend + Send + 'static,
+    S: System + Send + Sync + 'static,
+{
+    let sessions = Arc::new(AtomicUsize::new(0));
+    let key = Arc::new(key);
+
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                println!("tcp: error {:#?}", e);
+                continue;
+            }
+        };
+
+        if sessions.fetch_add(1, Ordering::SeqCst) >= MAX_SESSIONS {
+            sessions.fetch_sub(1, Ordering::SeqCst);
+            println!("tcp: too many sessions, rejecting connection");
+            let busy = OutgoingPacket::Error {
+                code: ErrorCode::Busy,
+                message: format!("device busy: {} sessions open", MAX_SESSIONS),
+            };
+            send_response::<B>(&mut stream, 0, busy.into());
+            continue;
+        }
+
+        let camera = camera.clone();
+        let system = system.clone();
+        let key = key.clone();
+        let session_count = sessions.clone();
+        let spawned = thread::Builder::new()
+            .stack_size(S::SESSION_STACK_SIZE)
+            .spawn(move || {
+                handle_session(stream, &camera, &*system, &key);
+                session_count.fetch_sub(1, Ordering::SeqCst);
+            });
+
+        if let Err(err) = spawned {
+            sessions.fetch_sub(1, Ordering::SeqCst);
+            println!("tcp: error spawning session: {:#?}", err);
+        }
+    }
+
+    Ok(())
+}
+
+// Answer requests from one client until it closes the connection or goes idle
+fn handle_session<B: CameraBackend, S: System>(
+    mut stream: TcpStream,
+    camera: &SharedCamera<B>,
+    system: &S,
+    key: &PresharedKey,
+) {
+    let peer_addr = stream
+        .peer_addr()
+        .map(|addr| addr.to_string())
+        .unwrap_or_else(|_| "unknown".into());
+    println!("tcp: session opened by {}", peer_addr);
+
+    if let Err(err) = configure_stream(&stream) {
+        println!("tcp: error configuring session: {:#?}", err);
+    }
+    let mut handshake_stream = HandshakeStream {
+        stream: &mut stream,
+        deadline: Instant::now() + HANDSHAKE_TIMEOUT,
+    };
+    let Some(ciphers) = authenticate(&mut handshake_stream, key, &peer_addr) else {
+        println!("tcp: session with {} closed", peer_addr);
+        return;
+    };
+    if let Err(err) = stream.set_read_timeout(Some(IDLE_TIMEOUT)) {
+        println!("tcp: error configuring session: {:#?}", err);
+    }
+    let mut stream = match system.open_stream(stream) {
+        Ok(stream) => SecureStream::new(stream, ciphers),
+        Err(err) => {
+            println!("tcp: error opening session: {:#?}", err);
+            return;
+        }
+    };
+
+    loop {
+        let frame = match Frame::read_from(&mut stream) {
+            Ok(frame) => frame,
+            Err(err) => {
+                match err.kind() {
+                    io::ErrorKind::UnexpectedEof => {}
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                        println!("tcp: session with {} idle", peer_addr)
+                    }
+                    _ => println!("tcp: error reading frame: {:#?}", err),
+                }
+                // Note: an invalid frame has no trustworthy request id to echo, so 0 is used,
+                // and the stream position is unknown so the session can not continue
+                if let Some(err) = err
+                    .get_ref()
+                    .and_then(|err| err.downcast_ref::<DecodeError>())
+                {
+                    let bad_packet = OutgoingPacket::from(&DeviceError::BadPacket(*err));
+                    send_response::<B>(&mut stream, 0, bad_packet.into());
+                }
+                break;
+            }
+        };
+
+        let packet = IncomingPacket::from_frame(&frame);
+        println!("tcp: packet: {:#?}", packet);
+
+        // Note: changes to the wifi, the device settings and a factory reset are applied by
+        // restarting
+        let restart_requested = matches!(
+            packet,
+            Ok(IncomingPacket::Restart
+                | IncomingPacket::SetWifi(_)
+                | IncomingPacket::SetWifiConfig(_)
+                | IncomingPacket::SetDeviceSettings(_)
+                | IncomingPacket::FactoryReset)
+        );
+        // Note: a panic in another session must not take the camera down with it
+        let mut camera_guard = camera.lock().unwrap_or_else(PoisonError::into_inner);
+        let response = packet
+            .map_err(DeviceError::from)
+            .and_then(|packet| handle_packet(packet, &mut camera_guard, system))
+            .unwrap_or_else(|err| {
+                println!("error: {}", err);
+                OutgoingPacket::from(&err).into()
+            });
+        let restart_requested = restart_requested
+            && !matches!(response, Response::Packet(OutgoingPacket::Error { .. }));
+        // Note: the camera stays locked until the response (and with it any frame buffer) is sent
+        let sent = send_response(&mut stream, frame.request_id, response);
+        drop(camera_guard);
+        if !sent {
+            break;
+        }
+
+        // Note: the restart happens once the response has been sent
+        if restart_requested {
+            println!("device: restarting"); // When in doubt.. restart your way out
+            system.restart();
+            break;
+        }
+    }
+
+    println!("tcp: session with {} closed", peer_addr);
+}
+
+// Run the handshake the client has to complete before any of its packets are handled, see
+// `Handshake`
+//
+// Note: a rejected client is told why before the session ends
+fn authenticate<T: io::Read + io::Write>(
+    stream: &mut T,
+    key: &PresharedKey,
+    peer_addr: &str,
+) -> Option<(RecordCipher, RecordCipher)> {
+    let mut device_nonce = [0; NONCE_LEN];
+    if let Err(err) = getrandom::getrandom(&mut device_nonce) {
+        println!("tcp: error generating nonce: {}", err);
+        return None;
+    }
+
+    let (request_id, controller_nonce) = match read_handshake(stream)? {
+        (request_id, HandshakeRequest::Hello { nonce }) => (request_id, nonce),
+        (request_id, _) => return reject(stream, request_id, "expected hello"),
+    };
+    let handshake = Handshake::new(key, &controller_nonce, &device_nonce);
+    let challenge = HandshakeResponse::Challenge {
+        nonce: device_nonce,
+        proof: handshake.proof(Role::Device),
+    };
+    write_handshake(stream, request_id, challenge)?;
+
+    let (request_id, proof) = match read_handshake(stream)? {
+        (request_id, HandshakeRequest::Proof { proof }) => (request_id, proof),
+        (request_id, _) => return reject(stream, request_id, "expected proof"),
+    };
+    if handshake.verify(Role::Controller, &proof).is_err() {
+        return reject(stream, request_id, "wrong key");
+    }
+    write_handshake(stream, request_id, HandshakeResponse::Accepted)?;
+
+    println!("tcp: {} authenticated", peer_addr);
+    Some(handshake.ciphers(Role::Device))
+}
+
+// Read the next handshake message, a packet sent before the handshake is done is rejected without
+// being decoded
+fn read_handshake<T: io::Read + io::Write>(stream: &mut T) -> Option<(u32, HandshakeRequest)> {
+    let frame = match Frame::read_from_limited(stream, HandshakeRequest::MAX_PAYLOAD_LEN) {
+        Ok(frame) => frame,
+        Err(err) => {
+            match err.kind() {
+                io::ErrorKind::UnexpectedEof => {}
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                    println!("tcp: handshake timed out")
+                }
+                _ => println!("tcp: error reading handshake: {:#?}", err),
+            }
+            // Note: as in a session, an invalid frame is answered with request id 0
+            if let Some(err) = err
+                .get_ref()
+                .and_then(|err| err.downcast_ref::<DecodeError>())
+            {
+                let bad_packet = OutgoingPacket::from(&DeviceError::BadPacket(*err));
+                let _ = bad_packet.to_frame(0).write_to(stream);
+            }
+            return None;
+        }
+    };
+
+    match HandshakeRequest::from_frame(&frame) {
+        Ok(request) => Some((frame.request_id, request)),
+        Err(_) => reject(stream, frame.request_id, "authenticate first"),
+    }
+}
+
+fn write_handshake<T: io::Write>(
+    stream: &mut T,
+    request_id: u32,
+    response: HandshakeResponse,
+) -> Option<()> {
+    match response.to_frame(request_id).write_to(stream) {
+        Ok(()) => Some(()),
+        Err(err) => {
+            println!("tcp: error writing handshake: {:#?}", err);
+            None
+        }
+    }
+}
+
+// The stream of a client that is not authenticated yet, reads fail once `deadline` has passed
+//
+// Note: a read timeout alone limits each read, a client sending a byte at a time would never be
+// timed out
+struct HandshakeStream<'a> {
+    stream: &'a mut TcpStream,
+    deadline: Instant,
+}
+
+impl Read for HandshakeStream<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(remaining))?;
+        self.stream.read(buf)
+    }
+}
+
+impl Write for HandshakeStream<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+// Tell the client why it was not authenticated, always returns None to end the session
+fn reject<T: io::Write, R>(stream: &mut T, request_id: u32, reason: &str) -> Option<R> {
+    println!("tcp: unauthorized: {}", reason);
+    let unauthorized = OutgoingPacket::from(&DeviceError::Unauthorized(reason.into()));
+    let _ = unauthorized.to_frame(request_id).write_to(stream);
+    None
+}
+
+// Returns false when the client can no longer be written to
+fn send_response<B: CameraBackend>(
+    stream: &mut impl io::Write,
+    request_id: u32,
+    response: Response<'_, B>,
+) -> bool {
+    match response.write_to(stream, request_id) {
+        Ok(()) => true,
+        Err(err) => {
+            println!("tcp: error writing response: {:#?}", err);
+            false
+        }
+    }
+}
+
+// Note: the read timeout is set by `HandshakeStream` during the handshake, and to IDLE_TIMEOUT
+// once the client is authenticated
+fn configure_stream(stream: &TcpStream) -> io::Result<()> {
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    stream.set_nodelay(true)
+}
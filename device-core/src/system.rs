@@ -0,0 +1,32 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+use protocol::{CameraStatus, DeviceStatus};
+
+use crate::{ConfigStorage, ConfigStore};
+
+// Device level services needed to answer requests besides the camera
+pub trait System {
+    // Stream sessions are served over, usually the TcpStream itself
+    type Stream: Read + Write;
+
+    // Where the config changed by requests (eg. `IncomingPacket::SetWifi`) is stored
+    type Storage: ConfigStorage;
+
+    // Stack size of session threads, kept small on the device to leave RAM for frame buffers
+    const SESSION_STACK_SIZE: usize = 8 * 1024;
+
+    // Snapshot of the device state for `IncomingPacket::GetStatus`
+    fn device_status(&self, camera: Option<CameraStatus>) -> DeviceStatus;
+
+    // Prepare an accepted connection for a session (eg. TCP keepalive), the server has already set
+    // its timeouts
+    fn open_stream(&self, stream: TcpStream) -> std::io::Result<Self::Stream>;
+
+    // Config the device booted with, as changed by requests since
+    fn config(&self) -> &ConfigStore<Self::Storage>;
+
+    // Restart the device once a `IncomingPacket::Restart` has been answered, the session ends if
+    // this returns
+    fn restart(&self);
+}
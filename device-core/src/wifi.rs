@@ -0,0 +1,618 @@
+// Station connection logic of the firmware, kept apart from the esp-idf driver so it runs on the
+// host: the driver feeds in its events and the time, and carries out the returned actions
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use protocol::{DecodeError, WifiCredentials};
+
+// Networks the device joins, most preferred first
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KnownNetworks(Vec<WifiCredentials>);
+
+impl KnownNetworks {
+    // Note: each network costs a full scan and connect timeout before the next is tried
+    pub const MAX_LEN: usize = 5;
+
+    // Add a network ahead of the known ones, replacing one with the same SSID and dropping the
+    // least preferred past MAX_LEN
+    pub fn add(&mut self, credentials: WifiCredentials) {
+        self.0.retain(|known| known.ssid() != credentials.ssid());
+        self.0.insert(0, credentials);
+        self.0.truncate(Self::MAX_LEN);
+    }
+
+    pub fn get(&self, index: usize) -> Option<&WifiCredentials> {
+        self.0.get(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &WifiCredentials> {
+        self.0.iter()
+    }
+
+    // Layout: for each network, length (1) | `WifiCredentials::encode`
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for credentials in &self.0 {
+            let encoded = credentials.encode();
+            bytes.push(encoded.len() as u8);
+            bytes.extend_from_slice(&encoded);
+        }
+        bytes
+    }
+
+    pub fn decode(mut bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut networks = KnownNetworks::default();
+        while let Some((&len, rest)) = bytes.split_first() {
+            if rest.len() < len as usize {
+                return Err(DecodeError::InvalidWifiCredentials);
+            }
+            let (encoded, rest) = rest.split_at(len as usize);
+            networks.0.push(WifiCredentials::decode(encoded)?);
+            bytes = rest;
+        }
+        networks.0.truncate(Self::MAX_LEN);
+        Ok(networks)
+    }
+}
+
+// How long the steps of joining a network may take and how long to wait between rounds of
+// trying every known network
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WifiTiming {
+    // From connecting to being associated with the access point
+    pub connect_timeout: Duration,
+    // From being associated to getting a DHCP lease
+    pub dhcp_timeout: Duration,
+    // Wait after the first round failed, doubled after each further failed round up to max_backoff
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for WifiTiming {
+    fn default() -> Self {
+        WifiTiming {
+            connect_timeout: Duration::from_secs(15),
+            dhcp_timeout: Duration::from_secs(20),
+            initial_backoff: Duration::from_secs(2),
+            max_backoff: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+// What the station is doing, reported to the rest of the firmware on each change
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WifiState {
+    // No network has been provisioned
+    Unprovisioned,
+    Connecting { ssid: String },
+    WaitingForIp { ssid: String },
+    Connected { ssid: String, ip: Ipv4Addr },
+    // Every known network failed, they are tried again after `delay`
+    Backoff { delay: Duration },
+}
+
+impl fmt::Display for WifiState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WifiState::Unprovisioned => f.write_str("no network provisioned"),
+            WifiState::Connecting { ssid } => write!(f, "connecting to {}", ssid),
+            WifiState::WaitingForIp { ssid } => write!(f, "waiting for an address on {}", ssid),
+            WifiState::Connected { ssid, ip } => write!(f, "connected to {} as {}", ssid, ip),
+            WifiState::Backoff { delay } => {
+                write!(f, "no network reachable, retrying in {}s", delay.as_secs())
+            }
+        }
+    }
+}
+
+// Station events of the driver
+//
+// Note: the esp-idf driver reports the disconnects it causes itself too (eg. when switching
+// networks), they are drained before the next network is tried and never reach the manager,
+// which would take them for the new network failing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WifiEvent {
+    // Associated with the access point
+    Associated,
+    // Association failed or the link to the access point dropped
+    Disconnected,
+    GotIp(Ipv4Addr),
+    // The DHCP lease expired without being renewed
+    LostIp,
+}
+
+// Driver operations the manager asks for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WifiAction {
+    // Leave the current network, if any, and connect to this one
+    Connect(WifiCredentials),
+    // Leave the current network and stay idle
+    Disconnect,
+}
+
+// Tries the known networks in order until one gives an address, keeps to it while the link holds,
+// and starts over from the most preferred network when it drops
+//
+// Once every network failed the manager waits before the next round, twice as long after each
+// failed round, so a device whose access point is gone does not keep the radio busy
+pub struct ConnectionManager {
+    networks: KnownNetworks,
+    timing: WifiTiming,
+    state: WifiState,
+    // Network of the current attempt
+    network: usize,
+    // Rounds failed in a row, reset once connected
+    failed_rounds: u32,
+    // When the current step times out, or the backoff ends
+    deadline: Option<Instant>,
+}
+
+impl ConnectionManager {
+    pub fn new(networks: KnownNetworks, timing: WifiTiming) -> Self {
+        ConnectionManager {
+            networks,
+            timing,
+            state: WifiState::Unprovisioned,
+            network: 0,
+            failed_rounds: 0,
+            deadline: None,
+        }
+    }
+
+    pub fn state(&self) -> &WifiState {
+        &self.state
+    }
+
+    // Rounds of trying every known network that failed in a row, 0 once connected
+    pub fn failed_rounds(&self) -> u32 {
+        self.failed_rounds
+    }
+
+    // When `poll` has to be called next if no event comes in first
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    // Connect to the most preferred network, None when there is none
+    pub fn start(&mut self, now: Instant) -> Option<WifiAction> {
+        self.failed_rounds = 0;
+        self.connect(0, now)
+    }
+
+    pub fn handle(&mut self, event: WifiEvent, now: Instant) -> Option<WifiAction> {
+        match (&self.state, event) {
+            (WifiState::Connecting { ssid }, WifiEvent::Associated) => {
+                self.state = WifiState::WaitingForIp { ssid: ssid.clone() };
+                self.deadline = Some(now + self.timing.dhcp_timeout);
+                None
+            }
+            (
+                WifiState::Connecting { ssid } | WifiState::WaitingForIp { ssid },
+                WifiEvent::GotIp(ip),
+            ) => {
+                self.state = WifiState::Connected {
+                    ssid: ssid.clone(),
+                    ip,
+                };
+                self.failed_rounds = 0;
+                self.deadline = None;
+                None
+            }
+            (WifiState::Connected { ssid, ip }, WifiEvent::GotIp(new_ip)) if *ip != new_ip => {
+                self.state = WifiState::Connected {
+                    ssid: ssid.clone(),
+                    ip: new_ip,
+                };
+                None
+            }
+            (
+                WifiState::Connecting { .. } | WifiState::WaitingForIp { .. },
+                WifiEvent::Disconnected,
+            ) => self.next_network(now),
+            // Note: a dropped link or lease is not counted as a failed round, the most preferred
+            // network is joined again right away
+            (WifiState::Connected { .. }, WifiEvent::Disconnected | WifiEvent::LostIp) => {
+                self.start(now)
+            }
+            // Late events of an attempt that was given up
+            _ => None,
+        }
+    }
+
+    // Act on a deadline that passed
+    pub fn poll(&mut self, now: Instant) -> Option<WifiAction> {
+        if self.deadline.is_none_or(|deadline| now < deadline) {
+            return None;
+        }
+        match self.state {
+            WifiState::Connecting { .. } | WifiState::WaitingForIp { .. } => self.next_network(now),
+            WifiState::Backoff { .. } => self.connect(0, now),
+            WifiState::Unprovisioned | WifiState::Connected { .. } => None,
+        }
+    }
+
+    fn next_network(&mut self, now: Instant) -> Option<WifiAction> {
+        if self.network + 1 < self.networks.len() {
+            return self.connect(self.network + 1, now);
+        }
+
+        self.failed_rounds += 1;
+        let delay = self
+            .timing
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(self.failed_rounds - 1))
+            .min(self.timing.max_backoff);
+        self.state = WifiState::Backoff { delay };
+        self.deadline = Some(now + delay);
+        Some(WifiAction::Disconnect)
+    }
+
+    fn connect(&mut self, network: usize, now: Instant) -> Option<WifiAction> {
+        let Some(credentials) = self.networks.get(network) else {
+            self.state = WifiState::Unprovisioned;
+            self.deadline = None;
+            return None;
+        };
+        self.network = network;
+        self.state = WifiState::Connecting {
+            ssid: credentials.ssid().to_string(),
+        };
+        self.deadline = Some(now + self.timing.connect_timeout);
+        Some(WifiAction::Connect(credentials.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMING: WifiTiming = WifiTiming {
+        connect_timeout: Duration::from_secs(15),
+        dhcp_timeout: Duration::from_secs(20),
+        initial_backoff: Duration::from_secs(2),
+        max_backoff: Duration::from_secs(10),
+    };
+    const IP: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 20);
+
+    fn credentials(ssid: &str) -> WifiCredentials {
+        WifiCredentials::new(ssid, "password").unwrap()
+    }
+
+    // Networks added least preferred first, so they are tried in the given order
+    fn networks(ssids: &[&str]) -> KnownNetworks {
+        let mut networks = KnownNetworks::default();
+        for ssid in ssids.iter().rev() {
+            networks.add(credentials(ssid));
+        }
+        networks
+    }
+
+    fn connect(ssid: &str) -> Option<WifiAction> {
+        Some(WifiAction::Connect(credentials(ssid)))
+    }
+
+    fn connecting(ssid: &str) -> WifiState {
+        WifiState::Connecting { ssid: ssid.into() }
+    }
+
+    #[test]
+    fn connects_to_preferred_network() {
+        let now = Instant::now();
+        let mut manager = ConnectionManager::new(networks(&["home", "office"]), TIMING);
+
+        assert_eq!(manager.start(now), connect("home"));
+        assert_eq!(*manager.state(), connecting("home"));
+        assert_eq!(manager.deadline(), Some(now + TIMING.connect_timeout));
+
+        assert_eq!(manager.handle(WifiEvent::Associated, now), None);
+        assert_eq!(
+            *manager.state(),
+            WifiState::WaitingForIp {
+                ssid: "home".into()
+            }
+        );
+        assert_eq!(manager.deadline(), Some(now + TIMING.dhcp_timeout));
+
+        assert_eq!(manager.handle(WifiEvent::GotIp(IP), now), None);
+        assert_eq!(
+            *manager.state(),
+            WifiState::Connected {
+                ssid: "home".into(),
+                ip: IP
+            }
+        );
+        assert_eq!(manager.deadline(), None);
+    }
+
+    #[test]
+    fn without_networks_stays_unprovisioned() {
+        let mut manager = ConnectionManager::new(KnownNetworks::default(), TIMING);
+
+        assert_eq!(manager.start(Instant::now()), None);
+        assert_eq!(*manager.state(), WifiState::Unprovisioned);
+        assert_eq!(manager.deadline(), None);
+    }
+
+    #[test]
+    fn connect_timeout_moves_to_next_network() {
+        let now = Instant::now();
+        let mut manager = ConnectionManager::new(networks(&["home", "office"]), TIMING);
+        manager.start(now);
+
+        // Note: nothing happens before the deadline
+        assert_eq!(manager.poll(now + TIMING.connect_timeout / 2), None);
+        assert_eq!(*manager.state(), connecting("home"));
+
+        let timeout = now + TIMING.connect_timeout;
+        assert_eq!(manager.poll(timeout), connect("office"));
+        assert_eq!(*manager.state(), connecting("office"));
+        assert_eq!(manager.deadline(), Some(timeout + TIMING.connect_timeout));
+    }
+
+    #[test]
+    fn dhcp_timeout_moves_to_next_network() {
+        let now = Instant::now();
+        let mut manager = ConnectionManager::new(networks(&["home", "office"]), TIMING);
+        manager.start(now);
+        manager.handle(WifiEvent::Associated, now);
+
+        assert_eq!(manager.poll(now + TIMING.dhcp_timeout), connect("office"));
+        assert_eq!(*manager.state(), connecting("office"));
+    }
+
+    #[test]
+    fn failed_association_moves_to_next_network() {
+        let now = Instant::now();
+        let mut manager = ConnectionManager::new(networks(&["home", "office"]), TIMING);
+        manager.start(now);
+
+        assert_eq!(
+            manager.handle(WifiEvent::Disconnected, now),
+            connect("office")
+        );
+    }
+
+    #[test]
+    fn failed_rounds_back_off_up_to_max() {
+        let mut now = Instant::now();
+        let mut manager = ConnectionManager::new(networks(&["home", "office"]), TIMING);
+        manager.start(now);
+
+        for delay in [2, 4, 8, 10, 10] {
+            let delay = Duration::from_secs(delay);
+            manager.handle(WifiEvent::Disconnected, now);
+            assert_eq!(
+                manager.handle(WifiEvent::Disconnected, now),
+                Some(WifiAction::Disconnect)
+            );
+            assert_eq!(*manager.state(), WifiState::Backoff { delay });
+            assert_eq!(manager.deadline(), Some(now + delay));
+
+            now += delay;
+            assert_eq!(manager.poll(now), connect("home"));
+        }
+    }
+
+    #[test]
+    fn failed_rounds_are_counted_until_connected() {
+        let now = Instant::now();
+        let mut manager = ConnectionManager::new(networks(&["home", "office"]), TIMING);
+        manager.start(now);
+        assert_eq!(manager.failed_rounds(), 0);
+
+        for round in 1..=3 {
+            manager.handle(WifiEvent::Disconnected, now);
+            assert_eq!(manager.failed_rounds(), round - 1);
+            manager.handle(WifiEvent::Disconnected, now);
+            assert_eq!(manager.failed_rounds(), round);
+            manager.poll(now + TIMING.max_backoff);
+        }
+
+        manager.handle(WifiEvent::GotIp(IP), now);
+        assert_eq!(manager.failed_rounds(), 0);
+    }
+
+    #[test]
+    fn connecting_resets_backoff() {
+        let now = Instant::now();
+        let mut manager = ConnectionManager::new(networks(&["home"]), TIMING);
+        manager.start(now);
+        manager.handle(WifiEvent::Disconnected, now);
+        manager.poll(now + TIMING.initial_backoff);
+
+        manager.handle(WifiEvent::GotIp(IP), now);
+        manager.handle(WifiEvent::Disconnected, now);
+        manager.handle(WifiEvent::Disconnected, now);
+        assert_eq!(
+            *manager.state(),
+            WifiState::Backoff {
+                delay: TIMING.initial_backoff
+            }
+        );
+    }
+
+    #[test]
+    fn lost_connection_starts_over_from_preferred_network() {
+        for event in [WifiEvent::Disconnected, WifiEvent::LostIp] {
+            let now = Instant::now();
+            let mut manager = ConnectionManager::new(networks(&["home", "office"]), TIMING);
+            manager.start(now);
+            // Note: a failed round before connecting to the second network
+            manager.handle(WifiEvent::Disconnected, now);
+            manager.handle(WifiEvent::Disconnected, now);
+            manager.poll(now + TIMING.initial_backoff);
+            manager.handle(WifiEvent::Disconnected, now);
+            manager.handle(WifiEvent::GotIp(IP), now);
+            assert_eq!(
+                *manager.state(),
+                WifiState::Connected {
+                    ssid: "office".into(),
+                    ip: IP
+                }
+            );
+
+            assert_eq!(manager.handle(event, now), connect("home"));
+            assert_eq!(*manager.state(), connecting("home"));
+
+            // Note: the dropped connection did not count as a failed round
+            manager.handle(WifiEvent::Disconnected, now);
+            manager.handle(WifiEvent::Disconnected, now);
+            assert_eq!(
+                *manager.state(),
+                WifiState::Backoff {
+                    delay: TIMING.initial_backoff
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn late_events_are_ignored() {
+        let now = Instant::now();
+        let mut manager = ConnectionManager::new(networks(&["home"]), TIMING);
+        manager.start(now);
+        manager.handle(WifiEvent::GotIp(IP), now);
+        let connected = manager.state().clone();
+
+        assert_eq!(manager.handle(WifiEvent::Associated, now), None);
+        assert_eq!(manager.handle(WifiEvent::GotIp(IP), now), None);
+        assert_eq!(*manager.state(), connected);
+        assert_eq!(manager.poll(now + TIMING.max_backoff), None);
+
+        manager.handle(WifiEvent::Disconnected, now);
+        manager.handle(WifiEvent::Disconnected, now);
+        let backoff = manager.state().clone();
+        for event in [
+            WifiEvent::Associated,
+            WifiEvent::Disconnected,
+            WifiEvent::GotIp(IP),
+            WifiEvent::LostIp,
+        ] {
+            assert_eq!(manager.handle(event, now), None);
+            assert_eq!(*manager.state(), backoff);
+        }
+    }
+
+    // Events as the driver passes them on: its own disconnects when switching networks are left
+    // out, and the lease of a dropped link only expires once the station is reconnecting
+    #[test]
+    fn driver_event_sequence() {
+        let mut now = Instant::now();
+        let mut manager = ConnectionManager::new(networks(&["home", "office"]), TIMING);
+        assert_eq!(manager.start(now), connect("home"));
+
+        // Note: home is out of reach, the attempt times out and the driver leaves it for office
+        now += TIMING.connect_timeout;
+        assert_eq!(manager.poll(now), connect("office"));
+        assert_eq!(
+            manager.handle(WifiEvent::Disconnected, now),
+            Some(WifiAction::Disconnect)
+        );
+        now += TIMING.initial_backoff;
+        assert_eq!(manager.poll(now), connect("home"));
+
+        assert_eq!(manager.handle(WifiEvent::Associated, now), None);
+        assert_eq!(manager.handle(WifiEvent::GotIp(IP), now), None);
+        assert_eq!(manager.failed_rounds(), 0);
+
+        assert_eq!(
+            manager.handle(WifiEvent::Disconnected, now),
+            connect("home")
+        );
+        assert_eq!(manager.handle(WifiEvent::LostIp, now), None);
+        assert_eq!(*manager.state(), connecting("home"));
+        assert_eq!(manager.handle(WifiEvent::Associated, now), None);
+        assert_eq!(manager.handle(WifiEvent::GotIp(IP), now), None);
+        assert_eq!(
+            *manager.state(),
+            WifiState::Connected {
+                ssid: "home".into(),
+                ip: IP
+            }
+        );
+    }
+
+    #[test]
+    fn new_address_updates_connection() {
+        let now = Instant::now();
+        let mut manager = ConnectionManager::new(networks(&["home"]), TIMING);
+        manager.start(now);
+        manager.handle(WifiEvent::GotIp(IP), now);
+
+        let new_ip = Ipv4Addr::new(192, 168, 1, 21);
+        assert_eq!(manager.handle(WifiEvent::GotIp(new_ip), now), None);
+        assert_eq!(
+            *manager.state(),
+            WifiState::Connected {
+                ssid: "home".into(),
+                ip: new_ip
+            }
+        );
+    }
+
+    #[test]
+    fn known_networks_prefer_last_added() {
+        let mut networks = networks(&["home", "office"]);
+        networks.add(credentials("cafe"));
+        let ssids: Vec<_> = networks.iter().map(|network| network.ssid()).collect();
+        assert_eq!(ssids, ["cafe", "home", "office"]);
+
+        // Note: a known SSID is moved to the front with its new password
+        networks.add(WifiCredentials::new("office", "new password").unwrap());
+        let ssids: Vec<_> = networks.iter().map(|network| network.ssid()).collect();
+        assert_eq!(ssids, ["office", "cafe", "home"]);
+        assert_eq!(networks.get(0).unwrap().password(), "new password");
+    }
+
+    #[test]
+    fn known_networks_drop_least_preferred() {
+        let mut networks = KnownNetworks::default();
+        for index in 0..KnownNetworks::MAX_LEN + 2 {
+            networks.add(credentials(&format!("network-{}", index)));
+        }
+        assert_eq!(networks.len(), KnownNetworks::MAX_LEN);
+        assert_eq!(networks.get(0).unwrap().ssid(), "network-6");
+        assert_eq!(
+            networks.get(KnownNetworks::MAX_LEN - 1).unwrap().ssid(),
+            "network-2"
+        );
+    }
+
+    #[test]
+    fn known_networks_round_trip() {
+        let networks = networks(&["home", "office"]);
+        assert_eq!(KnownNetworks::decode(&networks.encode()), Ok(networks));
+        assert_eq!(KnownNetworks::decode(&[]), Ok(KnownNetworks::default()));
+    }
+
+    #[test]
+    fn known_networks_decode_limits_length() {
+        let mut bytes = Vec::new();
+        for index in 0..KnownNetworks::MAX_LEN + 1 {
+            let encoded = credentials(&format!("network-{}", index)).encode();
+            bytes.push(encoded.len() as u8);
+            bytes.extend_from_slice(&encoded);
+        }
+        let networks = KnownNetworks::decode(&bytes).unwrap();
+        assert_eq!(networks.len(), KnownNetworks::MAX_LEN);
+        assert_eq!(networks.get(0).unwrap().ssid(), "network-0");
+    }
+
+    #[test]
+    fn known_networks_reject_truncated() {
+        let encoded = networks(&["home"]).encode();
+        assert_eq!(
+            KnownNetworks::decode(&encoded[..encoded.len() - 1]),
+            Err(DecodeError::InvalidWifiCredentials)
+        );
+    }
+}
@@ -1,9 +1,57 @@
+use std::fs;
+
+use protocol::PresharedKey;
+
 fn main() -> anyhow::Result<()> {
     // TODO: use args instead of env ?
     dotenv_build::output(dotenv_build::Config::default()).unwrap();
+    check_device_key()?;
 
     embuild::build::CfgArgs::output_propagated("ESP_IDF")?;
     embuild::build::LinkArgs::output_propagated("ESP_IDF")?;
 
     Ok(())
 }
+
+// The firmware parses DEVICE_KEY when it boots, so a key it can not parse is rejected here rather
+// than building a firmware that panics at every boot, as is the placeholder key of .env.sample
+fn check_device_key() -> anyhow::Result<()> {
+    println!("cargo:rerun-if-env-changed=DEVICE_KEY");
+    // Note: as with `env!`, the key in .env takes precedence over the environment
+    let key = fs::read_to_string(".env")
+        .ok()
+        .and_then(|env| {
+            env.lines()
+                .find_map(|line| line.trim().strip_prefix("DEVICE_KEY=").map(env_value))
+        })
+        .or_else(|| std::env::var("DEVICE_KEY").ok());
+    let Some(key) = key else {
+        anyhow::bail!("DEVICE_KEY is not set, add it to device/.env (eg. `openssl rand -hex 32`)");
+    };
+    if let Err(err) = key.parse::<PresharedKey>() {
+        anyhow::bail!("DEVICE_KEY is not valid: {}", err);
+    }
+    // Note: a placeholder like the zeros of .env.sample would let anyone control the camera
+    let mut digits = key.trim().chars().map(|digit| digit.to_ascii_lowercase());
+    let first = digits.next();
+    if digits.all(|digit| Some(digit) == first) {
+        anyhow::bail!(
+            "DEVICE_KEY is a placeholder, generate a key of your own (eg. `openssl rand -hex 32`)"
+        );
+    }
+    Ok(())
+}
+
+// Value of a .env line, without quotes and trailing comment
+fn env_value(value: &str) -> String {
+    let value = value.trim();
+    match value.strip_prefix('"') {
+        Some(quoted) => quoted.split('"').next().unwrap_or_default().to_string(),
+        None => value
+            .split('#')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_string(),
+    }
+}
@@ -3,6 +3,8 @@ use std::os::raw::c_int;
 mod aithinker;
 mod freenove;
 
+use protocol::BoardModel;
+
 use aithinker::AITHINKER_DVP_PINS;
 use freenove::FREENOVE_DVP_PINS;
 
@@ -69,6 +71,15 @@ pub enum Board {
 }
 
 impl Board {
+    // Model advertised to the controller, None for custom pin assignments
+    pub fn model(&self) -> Option<BoardModel> {
+        match self {
+            Board::Freenove => Some(BoardModel::Freenove),
+            Board::AIThinker => Some(BoardModel::AIThinker),
+            Board::Custom(_) => None,
+        }
+    }
+
     pub fn dvp_pins(self) -> DvpPins {
         match self {
             Board::Freenove => FREENOVE_DVP_PINS,
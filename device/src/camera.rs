@@ -1,22 +1,30 @@
-use base64::Engine;
 use core::convert::From;
+use std::ffi::CStr;
 use std::os::raw::c_int;
 
+use device_core::{CameraBackend, CameraConfig, DeviceError, SensorId, SensorSetting};
 use esp_idf_sys::esp_camera::{
-    esp_camera_fb_get, esp_camera_fb_return, esp_camera_init, esp_camera_sensor_get, sensor_t, camera_config_t, camera_config_t__bindgen_ty_1, camera_config_t__bindgen_ty_2,
+    esp_camera_deinit, esp_camera_fb_get, esp_camera_fb_return, esp_camera_init, esp_camera_sensor_get, esp_camera_sensor_get_info, gainceiling_t, sensor_t, camera_config_t, camera_config_t__bindgen_ty_1, camera_config_t__bindgen_ty_2,
+    ESP_ERR_CAMERA_FAILED_TO_SET_FRAME_SIZE, ESP_ERR_CAMERA_FAILED_TO_SET_OUT_FORMAT, ESP_ERR_CAMERA_NOT_DETECTED, ESP_ERR_CAMERA_NOT_SUPPORTED,
     ledc_channel_t_LEDC_CHANNEL_0, ledc_channel_t_LEDC_CHANNEL_1, ledc_channel_t_LEDC_CHANNEL_2, ledc_channel_t_LEDC_CHANNEL_3, ledc_channel_t_LEDC_CHANNEL_4, ledc_channel_t_LEDC_CHANNEL_5,
     ledc_channel_t_LEDC_CHANNEL_6, ledc_channel_t_LEDC_CHANNEL_7, ledc_timer_t_LEDC_TIMER_0, ledc_timer_t_LEDC_TIMER_1, ledc_timer_t_LEDC_TIMER_2, ledc_timer_t_LEDC_TIMER_3
 };
+use esp_idf_sys::{esp_err_t, esp_err_to_name};
 
 use crate::boards::DvpPins;
 
+mod controls;
+mod frame;
 mod framesize;
 mod pixelformat;
 
-pub use framesize::FrameSize;
-pub use pixelformat::PixelFormat;
+pub use protocol::{ControlValue, FrameSize, PixelFormat, SensorControl, SensorSettings};
 
-const DEFAULT_JPEG_QUALITY: c_int = 12;
+use controls::settings_from_status;
+use frame::EspFrame;
+
+use framesize::{from_framesize_t, to_framesize_t};
+use pixelformat::to_pixformat_t;
 
 #[derive(Debug)]
 pub enum LedcChannel {
@@ -66,31 +74,78 @@ impl From<LedcTimer> for u32 {
     }
 }
 
-pub struct SensorInfo {
-    pid: u16,
-    name: &'static str,
-    max_frame_size: FrameSize,
+// The esp32-camera driver, which keeps its state globally
+#[derive(Debug)]
+pub struct EspCamera {
+    dvp_pins: DvpPins,
 }
 
-// representation of camera sensor for direct use with esp32-camera library via transform
-pub struct CameraSensor {
-    pixel_format: PixelFormat,
-    frame_size: FrameSize,
-    // jpeg_quality: JpegQuality,
-    dvp_pins: DvpPins,
+impl EspCamera {
+    pub fn new(dvp_pins: DvpPins) -> Self {
+        EspCamera { dvp_pins }
+    }
+
+    fn get_sensor(&self) -> Result<*mut sensor_t, DeviceError> {
+        let sensor = unsafe { esp_camera_sensor_get() };
+        if sensor.is_null() {
+            return Err(DeviceError::SensorUnavailable);
+        }
+        Ok(sensor)
+    }
+
+    // Call one of the sensor's setter functions
+    // Note: a missing function pointer means the sensor driver does not implement the setting
+    fn set_sensor<T>(
+        &self,
+        setting: &'static str,
+        setter: impl FnOnce(&sensor_t) -> Option<unsafe extern "C" fn(*mut sensor_t, T) -> c_int>,
+        value: T,
+    ) -> Result<(), DeviceError> {
+        let sensor = self.get_sensor()?;
+        let set = setter(unsafe { &*sensor }).ok_or(DeviceError::SensorSet { setting, status: -1 })?;
+
+        let status = unsafe { set(sensor, value) };
+        if status != 0 {
+            return Err(DeviceError::SensorSet { setting, status });
+        }
+        Ok(())
+    }
+
+    fn set_control(&self, control_value: ControlValue) -> Result<(), DeviceError> {
+        let setting = control_value.control().name();
+        let value = control_value.value() as c_int;
+
+        match control_value.control() {
+            SensorControl::Brightness => self.set_sensor(setting, |sensor| sensor.set_brightness, value),
+            SensorControl::Contrast => self.set_sensor(setting, |sensor| sensor.set_contrast, value),
+            SensorControl::Saturation => self.set_sensor(setting, |sensor| sensor.set_saturation, value),
+            SensorControl::Sharpness => self.set_sensor(setting, |sensor| sensor.set_sharpness, value),
+            SensorControl::SpecialEffect => self.set_sensor(setting, |sensor| sensor.set_special_effect, value),
+            SensorControl::AutoWhiteBalance => self.set_sensor(setting, |sensor| sensor.set_whitebal, value),
+            SensorControl::AwbGain => self.set_sensor(setting, |sensor| sensor.set_awb_gain, value),
+            SensorControl::WhiteBalanceMode => self.set_sensor(setting, |sensor| sensor.set_wb_mode, value),
+            SensorControl::AutoExposure => self.set_sensor(setting, |sensor| sensor.set_exposure_ctrl, value),
+            SensorControl::AutoExposureDsp => self.set_sensor(setting, |sensor| sensor.set_aec2, value),
+            SensorControl::AeLevel => self.set_sensor(setting, |sensor| sensor.set_ae_level, value),
+            SensorControl::Exposure => self.set_sensor(setting, |sensor| sensor.set_aec_value, value),
+            SensorControl::AutoGain => self.set_sensor(setting, |sensor| sensor.set_gain_ctrl, value),
+            SensorControl::Gain => self.set_sensor(setting, |sensor| sensor.set_agc_gain, value),
+            SensorControl::GainCeiling => {
+                self.set_sensor(setting, |sensor| sensor.set_gainceiling, value as gainceiling_t)
+            }
+            SensorControl::LensCorrection => self.set_sensor(setting, |sensor| sensor.set_lenc, value),
+            SensorControl::HMirror => self.set_sensor(setting, |sensor| sensor.set_hmirror, value),
+            SensorControl::VFlip => self.set_sensor(setting, |sensor| sensor.set_vflip, value),
+        }
+    }
 }
 
-impl CameraSensor {
-    pub fn new(
-        pixel_format: Option<PixelFormat>,
-        frame_size: Option<FrameSize>,
-        // jpeg_quality: Option<JpegQuality>,
-        dvp_pins: DvpPins,
-    ) -> anyhow::Result<Self> {
-        let pixel_format = pixel_format.unwrap_or_default();
-        let frame_size = frame_size.unwrap_or_default();
-
-        let pins = dvp_pins;
+impl CameraBackend for EspCamera {
+    type Frame = EspFrame;
+
+    // Initialize the camera driver with the given config
+    fn init(&mut self, config: &CameraConfig) -> Result<(), DeviceError> {
+        let pins = self.dvp_pins;
         let result = unsafe {
             // TODO: remove the need for this
             esp_camera_init(&camera_config_t {
@@ -117,96 +172,85 @@ impl CameraSensor {
                 xclk_freq_hz: 20_000_000,
                 ledc_timer: LedcTimer::Timer0.into(),
                 ledc_channel: LedcChannel::Channel0.into(),
-                pixel_format: pixel_format.clone().into(),
-                frame_size: frame_size.clone().into(),
-                jpeg_quality: DEFAULT_JPEG_QUALITY, // TODO: make configurable
+                pixel_format: to_pixformat_t(config.pixel_format),
+                frame_size: to_framesize_t(config.frame_size),
+                jpeg_quality: config.jpeg_quality.get() as c_int,
                 fb_count: 1,
                 ..Default::default()
             })
         };
 
-        // let result = unsafe { esp_camera_init(&sensor.into()) };
         match result {
-            0 => Ok(CameraSensor {
-                pixel_format,
-                frame_size,
-                // jpeg_quality: jpeg_quality.unwrap_or_default(),
-                dvp_pins,
+            0 => Ok(()),
+            code => Err(DeviceError::CameraInit {
+                code,
+                name: esp_err_name(code),
             }),
-            // TODO: return error
-            _ => panic!("error: failed to init camera"),
         }
     }
 
-    fn get_sensor(&self) -> *mut sensor_t {
-        unsafe { esp_camera_sensor_get() }
+    fn deinit(&mut self) {
+        unsafe { esp_camera_deinit() };
     }
 
-    // pub fn set_pixel_format(&mut self, pixel_format: PixelFormat) {
-    //     self.pixel_format = pixel_format;
-    //     let mut sensor = unsafe { esp_camera_sensor_get() };
-    //     let result = unsafe { (*sensor).set_pixformat(sensor, pixel_format.clone().into()) };
-    //     // if result != 0 {}
-    // }
-
-    pub fn set_frame_size(&self, framesize: FrameSize) -> Result<(), ()> {
-        let sensor = self.get_sensor();
-        if let Some(set_framesize) = unsafe { (*sensor).set_framesize } {
-            let result = unsafe { set_framesize(sensor, framesize.clone().into()) };
-            if result == 0 {
-                println!("set: frame size: {:#?}", framesize);
-                return Ok(());
-            } else {
-                println!("error: set frame size: failed to set framesize");
-                return Err(());
-            }
-        } else {
-            println!("error: set frame size: c-interop: failed to deference function");
-            return Err(());
-        }
+    fn frame_get(&self) -> Option<EspFrame> {
+        EspFrame::new(unsafe { esp_camera_fb_get() })
     }
 
-    // pub fn set_jpeg_quality(&mut self, jpeg_quality: JpegQuality) {
-    //     // min: 0, max: 63
-    //     self.jpeg_quality = jpeg_quality;
-    // }
-
-    // Capture image using camera module
-    pub fn capture_image(&self, debug: bool) -> anyhow::Result<&'static [u8]> {
-        // TODO: figure out how to use esp wrapper macros
-        // Get the frame buffer from the camera driver
-        let fb = unsafe { esp_camera_fb_get() };
-        if fb.is_null() {
-            // TODO: return error
-            eprintln!("error: failed to get camera buffer")
-        }
+    fn frame_return(&self, frame: EspFrame) {
+        unsafe { esp_camera_fb_return(frame.as_ptr()) };
+    }
 
-        let img_data = unsafe { std::slice::from_raw_parts((*fb).buf, (*fb).len as usize) };
+    fn sensor_id(&self) -> Result<SensorId, DeviceError> {
+        let sensor = self.get_sensor()?;
+        let pid = unsafe { (*sensor).id.PID };
+        let info = unsafe { esp_camera_sensor_get_info(&mut (*sensor).id) };
 
-        if debug == true {
-            // Print the base64 encoded image to console for debugging purposes
-            let base64_img = base64::engine::general_purpose::STANDARD.encode(img_data);
-            println!("----------------------------------------------");
-            println!("Image size: {} KB", img_data.len() / 1024);
-            println!("----------------------------------------------");
-            println!("{}", base64_img);
-        }
+        let (name, max_frame_size) = match info.is_null() {
+            true => (None, None),
+            false => unsafe {
+                let name = CStr::from_ptr((*info).name).to_string_lossy().into_owned();
+                (Some(name), from_framesize_t((*info).max_size))
+            },
+        };
+
+        Ok(SensorId {
+            pid,
+            name,
+            max_frame_size,
+        })
+    }
 
-        // Return the frame buffer to the camera driver
-        unsafe { esp_camera_fb_return(fb) };
+    fn sensor_settings(&self) -> Result<SensorSettings, DeviceError> {
+        let sensor = self.get_sensor()?;
+        Ok(settings_from_status(unsafe { &(*sensor).status }))
+    }
 
-        Ok(img_data)
+    fn sensor_set(&mut self, setting: SensorSetting) -> Result<(), DeviceError> {
+        let name = setting.name();
+        match setting {
+            SensorSetting::PixelFormat(pixel_format) => {
+                self.set_sensor(name, |sensor| sensor.set_pixformat, to_pixformat_t(pixel_format))
+            }
+            SensorSetting::FrameSize(frame_size) => {
+                self.set_sensor(name, |sensor| sensor.set_framesize, to_framesize_t(frame_size))
+            }
+            SensorSetting::JpegQuality(jpeg_quality) => {
+                self.set_sensor(name, |sensor| sensor.set_quality, jpeg_quality.get() as c_int)
+            }
+            SensorSetting::Control(control_value) => self.set_control(control_value),
+        }
     }
+}
 
-    // pub fn sensor_info(self) -> SensorInfo {
-    //     let sensor = self.get();
-    //     let pid = unsafe { (*sensor).id };
-    //     let name = unsafe { (*sensor).name };
-    //     let max_frame_size = unsafe { (*sensor).max_size };
-    //     SensorInfo {
-    //         pid,
-    //         name,
-    //         max_frame_size,
-    //     }
-    // }
+// esp_err_t -> readable name, esp_err_to_name does not know about the camera component codes
+fn esp_err_name(err: esp_err_t) -> &'static str {
+    match err as u32 {
+        ESP_ERR_CAMERA_NOT_DETECTED => "ESP_ERR_CAMERA_NOT_DETECTED",
+        ESP_ERR_CAMERA_FAILED_TO_SET_FRAME_SIZE => "ESP_ERR_CAMERA_FAILED_TO_SET_FRAME_SIZE",
+        ESP_ERR_CAMERA_FAILED_TO_SET_OUT_FORMAT => "ESP_ERR_CAMERA_FAILED_TO_SET_OUT_FORMAT",
+        ESP_ERR_CAMERA_NOT_SUPPORTED => "ESP_ERR_CAMERA_NOT_SUPPORTED",
+        // Note: esp_err_to_name returns a static string for any code
+        _ => unsafe { CStr::from_ptr(esp_err_to_name(err)) }.to_str().unwrap_or("UNKNOWN"),
+    }
 }
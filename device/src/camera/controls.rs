@@ -0,0 +1,35 @@
+use esp_idf_sys::esp_camera::camera_status_t;
+
+use super::{SensorControl, SensorSettings};
+
+// lib binding -> rust struct, values the sensor reports out of range are left at their default
+pub fn settings_from_status(status: &camera_status_t) -> SensorSettings {
+    let values = [
+        (SensorControl::Brightness, status.brightness as i32),
+        (SensorControl::Contrast, status.contrast as i32),
+        (SensorControl::Saturation, status.saturation as i32),
+        (SensorControl::Sharpness, status.sharpness as i32),
+        (SensorControl::SpecialEffect, status.special_effect as i32),
+        (SensorControl::AutoWhiteBalance, status.awb as i32),
+        (SensorControl::AwbGain, status.awb_gain as i32),
+        (SensorControl::WhiteBalanceMode, status.wb_mode as i32),
+        (SensorControl::AutoExposure, status.aec as i32),
+        (SensorControl::AutoExposureDsp, status.aec2 as i32),
+        (SensorControl::AeLevel, status.ae_level as i32),
+        (SensorControl::Exposure, status.aec_value as i32),
+        (SensorControl::AutoGain, status.agc as i32),
+        (SensorControl::Gain, status.agc_gain as i32),
+        (SensorControl::GainCeiling, status.gainceiling as i32),
+        (SensorControl::LensCorrection, status.lenc as i32),
+        (SensorControl::HMirror, status.hmirror as i32),
+        (SensorControl::VFlip, status.vflip as i32),
+    ];
+
+    let mut settings = SensorSettings::default();
+    for (control, value) in values {
+        if let Some(control_value) = control.value(value) {
+            settings.set(control_value);
+        }
+    }
+    settings
+}
@@ -0,0 +1,49 @@
+use std::ptr::NonNull;
+
+use device_core::RawFrame;
+use esp_idf_sys::esp_camera::camera_fb_t;
+
+use super::pixelformat::from_pixformat_t;
+use super::PixelFormat;
+
+// Frame buffer handed out by esp_camera_fb_get
+pub struct EspFrame(NonNull<camera_fb_t>);
+
+impl EspFrame {
+    // None for the null pointer returned when no frame buffer is available
+    pub fn new(fb: *mut camera_fb_t) -> Option<Self> {
+        NonNull::new(fb).map(EspFrame)
+    }
+
+    pub fn as_ptr(&self) -> *mut camera_fb_t {
+        self.0.as_ptr()
+    }
+
+    fn fb(&self) -> &camera_fb_t {
+        // Safety: the driver keeps the frame buffer valid until it is returned, which consumes self
+        unsafe { self.0.as_ref() }
+    }
+}
+
+impl RawFrame for EspFrame {
+    fn data(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.fb().buf, self.fb().len as usize) }
+    }
+
+    fn width(&self) -> u32 {
+        self.fb().width as u32
+    }
+
+    fn height(&self) -> u32 {
+        self.fb().height as u32
+    }
+
+    fn pixel_format(&self) -> Option<PixelFormat> {
+        from_pixformat_t(self.fb().format)
+    }
+
+    fn timestamp_us(&self) -> u64 {
+        let timestamp = self.fb().timestamp;
+        timestamp.tv_sec as u64 * 1_000_000 + timestamp.tv_usec as u64
+    }
+}
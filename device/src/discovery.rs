@@ -0,0 +1,42 @@
+use esp_idf_svc::mdns::EspMdns;
+use esp_idf_sys::{esp_mac_type_t_ESP_MAC_WIFI_STA, esp_read_mac, EspError};
+use protocol::{Advertisement, SERVICE_TYPE};
+
+// Name the device advertises itself under, `DEVICE_NAME` or "espcam-" and the end of its MAC
+// address
+pub fn device_name() -> String {
+    if let Some(name) = option_env!("DEVICE_NAME") {
+        return name.to_string();
+    }
+
+    let mut mac = [0u8; 6];
+    unsafe { esp_read_mac(mac.as_mut_ptr(), esp_mac_type_t_ESP_MAC_WIFI_STA) };
+    format!("espcam-{:02x}{:02x}{:02x}", mac[3], mac[4], mac[5])
+}
+
+// Advertise the device over mDNS so the controller can find it, the service stays up while EspMdns
+// lives
+pub fn advertise(
+    name: &str,
+    port: u16,
+    advertisement: &Advertisement,
+) -> Result<EspMdns, EspError> {
+    // eg. "_espcam._tcp" -> ("_espcam", "_tcp")
+    let (service, proto) = SERVICE_TYPE
+        .split_once('.')
+        .expect("service type is _service._proto");
+
+    let mut mdns = EspMdns::take()?;
+    mdns.set_hostname(name)?;
+    mdns.set_instance_name(name)?;
+
+    let txt = advertisement.txt_records();
+    let txt: Vec<(&str, &str)> = txt
+        .iter()
+        .map(|(key, value)| (*key, value.as_str()))
+        .collect();
+    mdns.add_service(Some(name), service, proto, port, &txt)?;
+
+    println!("mdns: advertising {}.{}", name, SERVICE_TYPE);
+    Ok(mdns)
+}
@@ -1,19 +1,25 @@
-use std::io::Write;
 use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
 
-use esp_idf_hal::{peripherals::Peripherals, reset::restart};
+use device_core::{CameraSensor, ConfigStore};
+use esp_idf_hal::peripherals::Peripherals;
 use esp_idf_svc::eventloop::EspSystemEventLoop;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
 use esp_idf_sys::{self as _};
 
 mod boards;
 mod camera;
-mod packet;
+mod discovery;
+mod storage;
+mod system;
 mod wifi;
 
 use boards::Board;
-use camera::CameraSensor;
-use packet::IncomingPacket;
-use wifi::init_wifi;
+use camera::EspCamera;
+use protocol::{Advertisement, PresharedKey, SensorModel};
+use storage::NvsStorage;
+use system::{EspSystem, FIRMWARE_VERSION};
+use wifi::start_wifi;
 
 fn main() -> anyhow::Result<()> {
     esp_idf_sys::link_patches();
@@ -22,56 +28,65 @@ fn main() -> anyhow::Result<()> {
     let sysloop = EspSystemEventLoop::take()?;
     let peripherals = Peripherals::take().unwrap();
     let board = Board::from_env();
+    let board_model = board.model();
 
-    // Initialize wifi
-    // TODO: encrypt secrets in binary
-    let wifi_ssid = env!("WIFI_SSID");
-    let wifi_pass = env!("WIFI_PASS");
-    let _wifi = init_wifi(wifi_ssid, wifi_pass, peripherals.modem, sysloop.clone());
+    // Load the config set over the protocol, the defaults until the device is provisioned
+    let nvs = EspDefaultNvsPartition::take()?;
+    let config_store = ConfigStore::open(NvsStorage::new(nvs.clone())?);
+    let config = config_store.get();
+    let name = match config.settings.name().is_empty() {
+        true => discovery::device_name(),
+        false => config.settings.name().to_string(),
+    };
+    let port = config.settings.port();
 
-    // TODO: let Board handle camera instantiation
-    // Initialize the camera with default config
-    let camera_sensor = CameraSensor::new(None, None, board.dvp_pins()).unwrap();
+    // Initialize wifi with the stored networks and config
+    // Note: the connection is kept up in the background, requests are served once it is
+    start_wifi(
+        config.networks,
+        &config.wifi,
+        &name,
+        peripherals.modem,
+        sysloop.clone(),
+        nvs,
+        |state| println!("wifi: {}", state),
+    )?;
 
-    // Listen to TCP for instruction packets
-    let listener = TcpListener::bind("0.0.0.0:8080")?;
-    for stream in listener.incoming() {
-        match stream {
-            Ok(mut stream) => {
-                println!("tcp: received packet from {}", stream.peer_addr().unwrap());
-                let packet = IncomingPacket::try_from(&mut stream);
-                println!("tcp: packet: {:#?}", packet);
+    // Only controllers that prove they know this key are served
+    // Note: build.rs fails the build on a key that does not parse
+    let key: PresharedKey = env!("DEVICE_KEY")
+        .parse()
+        .expect("DEVICE_KEY is checked by build.rs");
 
-                // TODO: encapsulate instruction handlers
-                match packet {
-                    Ok(IncomingPacket::Capture) => {
-                        let image = camera_sensor.capture_image(true).unwrap();
-                        stream.write_all(image);
-                        stream.flush();
-                    }
-                    Ok(IncomingPacket::SetFrameSize(frame_size)) => {
-                        let result = camera_sensor.set_frame_size(frame_size);
-                        match result {
-                            Ok(_) => stream.write(vec![1; 1].as_slice()),
-                            Err(_) => stream.write(vec![1; 0].as_slice()),
-                        };
-                        stream.flush();
-                    }
-                    Ok(IncomingPacket::SetPixelFormat(pixel_format)) => {
-                        println!("set: pixel format: {:#?}", pixel_format);
-                    }
-                    Ok(IncomingPacket::Restart) => {
-                        println!("device: restarting"); // When in doubt.. restart your way out
-                        restart();
-                    }
-                    Err(err) => println!("error parsing packet: {:#?}", err),
-                }
-            }
-            Err(e) => {
-                println!("tcp: error {:#?}", e)
-            }
-        }
+    // TODO: let Board handle camera instantiation
+    // Initialize the camera with the stored settings, the driver's defaults for unset ones
+    // Note: a failed init is reported to each request instead of rebooting the device
+    let camera_sensor = CameraSensor::new(EspCamera::new(board.dvp_pins()), &config.camera);
+    if let Err(err) = &camera_sensor {
+        println!("error: {}", err);
     }
 
+    // Advertise the device so the controller finds it without reading its address off the serial
+    // console
+    // Note: the device still serves requests if this fails, it just has to be addressed directly
+    let sensor = camera_sensor
+        .as_ref()
+        .ok()
+        .map(|camera_sensor| SensorModel::from_pid(camera_sensor.status().sensor.pid));
+    let advertisement = Advertisement::new(board_model, sensor, FIRMWARE_VERSION);
+    let _mdns = discovery::advertise(&name, port, &advertisement)
+        .map_err(|err| println!("mdns: error: {}", err))
+        .ok();
+
+    // Listen to TCP for instruction packets
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    println!("device: listening on port {}", port);
+    device_core::serve(
+        listener,
+        Arc::new(Mutex::new(camera_sensor)),
+        Arc::new(EspSystem::new(config_store)),
+        key,
+    )?;
+
     Ok(())
 }
@@ -0,0 +1,106 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use device_core::{ConfigStorage, DeviceConfig, DeviceError};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_sys::EspError;
+
+// NVS namespace and key the config is stored under, as `DeviceConfig::encode`
+const NAMESPACE: &str = "device";
+const CONFIG_KEY: &str = "config";
+// Room for the largest config: settings, camera settings with every control set, five networks and
+// the wifi config take well below this
+const MAX_CONFIG_LEN: usize = 1024;
+
+// Where firmwares from before the config stored the networks and the wifi config, read once to
+// migrate them
+const LEGACY_NAMESPACE: &str = "wifi";
+const LEGACY_NETWORKS_KEY: &str = "networks";
+const LEGACY_CONFIG_KEY: &str = "config";
+
+// Device config kept in NVS, so it survives restarts and reflashing the firmware
+pub struct NvsStorage {
+    nvs: Mutex<EspNvs<NvsDefault>>,
+    legacy_nvs: Mutex<EspNvs<NvsDefault>>,
+}
+
+impl NvsStorage {
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Self, EspError> {
+        Ok(NvsStorage {
+            nvs: Mutex::new(EspNvs::new(partition.clone(), NAMESPACE, true)?),
+            legacy_nvs: Mutex::new(EspNvs::new(partition, LEGACY_NAMESPACE, true)?),
+        })
+    }
+
+    // Config made of what an earlier firmware stored, None when it stored nothing
+    //
+    // Note: the legacy keys are only removed once the config is first written, until then they are
+    // migrated on each boot
+    fn read_legacy(&self) -> Result<Option<Vec<u8>>, EspError> {
+        let nvs = lock(&self.legacy_nvs);
+        let mut networks_buf = [0; MAX_CONFIG_LEN];
+        let mut config_buf = [0; MAX_CONFIG_LEN];
+        let networks = nvs.get_raw(LEGACY_NETWORKS_KEY, &mut networks_buf)?;
+        let config = nvs.get_raw(LEGACY_CONFIG_KEY, &mut config_buf)?;
+        if networks.is_none() && config.is_none() {
+            return Ok(None);
+        }
+        println!("config: migrating the wifi settings of an earlier firmware");
+        Ok(Some(
+            DeviceConfig::from_wifi_store(networks, config).encode(),
+        ))
+    }
+
+    fn remove_legacy(&self) -> Result<(), EspError> {
+        let mut nvs = lock(&self.legacy_nvs);
+        nvs.remove(LEGACY_NETWORKS_KEY)?;
+        nvs.remove(LEGACY_CONFIG_KEY)?;
+        Ok(())
+    }
+}
+
+impl ConfigStorage for NvsStorage {
+    fn read(&self) -> Result<Option<Vec<u8>>, DeviceError> {
+        let mut buf = [0; MAX_CONFIG_LEN];
+        let stored = lock(&self.nvs)
+            .get_raw(CONFIG_KEY, &mut buf)
+            .map_err(|err| storage_error("read config", err))?
+            .map(|bytes| bytes.to_vec());
+        match stored {
+            Some(bytes) => Ok(Some(bytes)),
+            None => self
+                .read_legacy()
+                .map_err(|err| storage_error("read wifi settings", err)),
+        }
+    }
+
+    fn write(&self, bytes: &[u8]) -> Result<(), DeviceError> {
+        lock(&self.nvs)
+            .set_raw(CONFIG_KEY, bytes)
+            .map_err(|err| storage_error("store config", err))?;
+        // Note: the config now holds what the legacy keys did, a failure only means they are read
+        // again
+        if let Err(err) = self.remove_legacy() {
+            println!(
+                "config: failed to remove the wifi settings of an earlier firmware: {}",
+                err
+            );
+        }
+        Ok(())
+    }
+
+    fn erase(&self) -> Result<(), DeviceError> {
+        lock(&self.nvs)
+            .remove(CONFIG_KEY)
+            .map_err(|err| storage_error("erase config", err))?;
+        self.remove_legacy()
+            .map_err(|err| storage_error("erase wifi settings", err))
+    }
+}
+
+fn lock(nvs: &Mutex<EspNvs<NvsDefault>>) -> MutexGuard<'_, EspNvs<NvsDefault>> {
+    nvs.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn storage_error(action: &str, err: EspError) -> DeviceError {
+    DeviceError::Storage(format!("failed to {}: {}", action, err))
+}
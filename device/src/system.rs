@@ -0,0 +1,112 @@
+use std::ffi::{c_int, c_void, CStr};
+use std::io;
+use std::mem::MaybeUninit;
+use std::net::{Ipv4Addr, TcpStream};
+use std::os::fd::AsRawFd;
+
+use device_core::{ConfigStore, System};
+use esp_idf_hal::reset::restart;
+use esp_idf_sys::{
+    esp_get_free_heap_size, esp_netif_get_handle_from_ifkey, esp_netif_get_ip_info,
+    esp_netif_ip_info_t, esp_reset_reason, esp_timer_get_time, esp_wifi_sta_get_ap_info,
+    heap_caps_get_free_size, lwip_setsockopt, socklen_t, wifi_ap_record_t, IPPROTO_TCP,
+    MALLOC_CAP_SPIRAM, SOL_SOCKET, SO_KEEPALIVE, TCP_KEEPCNT, TCP_KEEPIDLE, TCP_KEEPINTVL,
+};
+use protocol::{CameraStatus, DeviceStatus, ResetReason, WifiStatus};
+
+use crate::storage::NvsStorage;
+
+pub const FIRMWARE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Key of the default station interface created by esp-idf
+const STA_IFKEY: &CStr = c"WIFI_STA_DEF";
+
+// TCP keepalive probes detect clients that disappeared without closing the connection
+const KEEPALIVE_IDLE_SECS: c_int = 15;
+const KEEPALIVE_INTERVAL_SECS: c_int = 5;
+const KEEPALIVE_COUNT: c_int = 3;
+
+// System services of esp-idf
+pub struct EspSystem {
+    config: ConfigStore<NvsStorage>,
+}
+
+impl EspSystem {
+    pub fn new(config: ConfigStore<NvsStorage>) -> Self {
+        EspSystem { config }
+    }
+}
+
+impl System for EspSystem {
+    type Stream = TcpStream;
+    type Storage = NvsStorage;
+
+    fn device_status(&self, camera: Option<CameraStatus>) -> DeviceStatus {
+        DeviceStatus {
+            firmware_version: FIRMWARE_VERSION.into(),
+            uptime_ms: (unsafe { esp_timer_get_time() } / 1000) as u64,
+            free_heap: unsafe { esp_get_free_heap_size() },
+            free_psram: unsafe { heap_caps_get_free_size(MALLOC_CAP_SPIRAM) } as u32,
+            reset_reason: ResetReason::from(unsafe { esp_reset_reason() } as u8),
+            wifi: wifi_status(),
+            camera,
+        }
+    }
+
+    fn open_stream(&self, stream: TcpStream) -> io::Result<TcpStream> {
+        let fd = stream.as_raw_fd();
+        set_socket_option(fd, SOL_SOCKET, SO_KEEPALIVE, 1)?;
+        set_socket_option(fd, IPPROTO_TCP, TCP_KEEPIDLE, KEEPALIVE_IDLE_SECS)?;
+        set_socket_option(fd, IPPROTO_TCP, TCP_KEEPINTVL, KEEPALIVE_INTERVAL_SECS)?;
+        set_socket_option(fd, IPPROTO_TCP, TCP_KEEPCNT, KEEPALIVE_COUNT)?;
+        Ok(stream)
+    }
+
+    fn config(&self) -> &ConfigStore<NvsStorage> {
+        &self.config
+    }
+
+    fn restart(&self) {
+        restart();
+    }
+}
+
+// Signal strength and address of the station interface, None while not connected
+fn wifi_status() -> Option<WifiStatus> {
+    let mut ap_info = MaybeUninit::<wifi_ap_record_t>::zeroed();
+    if unsafe { esp_wifi_sta_get_ap_info(ap_info.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    let rssi = unsafe { ap_info.assume_init() }.rssi;
+
+    let netif = unsafe { esp_netif_get_handle_from_ifkey(STA_IFKEY.as_ptr()) };
+    if netif.is_null() {
+        return None;
+    }
+    let mut ip_info = MaybeUninit::<esp_netif_ip_info_t>::zeroed();
+    if unsafe { esp_netif_get_ip_info(netif, ip_info.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    // Note: lwip stores addresses in network byte order
+    let ip = Ipv4Addr::from(unsafe { ip_info.assume_init() }.ip.addr.to_ne_bytes());
+
+    Some(WifiStatus { rssi, ip })
+}
+
+// std does not expose TCP keepalive options, so they are set on the lwip socket directly
+fn set_socket_option(fd: c_int, level: u32, option: u32, value: c_int) -> io::Result<()> {
+    let result = unsafe {
+        lwip_setsockopt(
+            fd,
+            level as c_int,
+            option as c_int,
+            &value as *const c_int as *const c_void,
+            std::mem::size_of::<c_int>() as socklen_t,
+        )
+    };
+
+    match result {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
@@ -1,72 +1,277 @@
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use device_core::{ConnectionManager, KnownNetworks, WifiAction, WifiEvent, WifiState, WifiTiming};
+use embedded_svc::wifi::{
+    AccessPointConfiguration, AuthMethod, ClientConfiguration, Configuration,
+};
 use esp_idf_hal::{modem::Modem, peripheral::Peripheral};
-// use esp_idf_svc::netif::EspNetifWait;
 use esp_idf_svc::{
     eventloop::EspSystemEventLoop,
-    wifi::{EspWifi, WifiWait},
+    netif::IpEvent,
+    nvs::EspDefaultNvsPartition,
+    wifi::{EspWifi, WifiEvent as EspWifiEvent},
 };
-use std::time::Duration;
-// use std::net::Ipv4Addr;
-use embedded_svc::wifi::{AccessPointConfiguration, ClientConfiguration, Configuration};
-
-// Initializes ESP32 wifi and connects to the specified network
-pub fn init_wifi(
-    wifi_ssid: &str,
-    wifi_pass: &str,
+use esp_idf_sys::EspError;
+use protocol::{AccessPointMode, WifiConfig};
+
+// Stack of the thread driving the connection, it only handles events and logs
+const WIFI_STACK_SIZE: usize = 8 * 1024;
+// How long switching networks waits for the driver to leave the current one
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+// Rounds of every known network failing after which a device in provisioning mode starts its
+// access point, so it can be given a network that is in reach
+const PROVISIONING_AFTER_ROUNDS: u32 = 3;
+
+// Initializes ESP32 wifi as `config` asks and keeps the station connected to the known networks in
+// a background thread, calling `on_change` whenever the connection state changes
+//
+// Note: a device that knows no network runs its access point whatever the config says, else it
+// could never be provisioned: a controller joining it sets one with
+// `camctl --device 192.168.71.1 set-wifi <ssid>`. In provisioning mode the access point is also
+// started once none of the known networks could be joined for PROVISIONING_AFTER_ROUNDS rounds,
+// it then stays up until the device restarts (eg. once given a network)
+pub fn start_wifi(
+    networks: KnownNetworks,
+    config: &WifiConfig,
+    name: &str,
     modem: impl Peripheral<P = Modem> + 'static,
     sysloop: EspSystemEventLoop,
-) -> anyhow::Result<Box<EspWifi<'static>>> {
-    let mut wifi = Box::new(EspWifi::new(modem, sysloop.clone(), None)?);
+    nvs: EspDefaultNvsPartition,
+    on_change: impl Fn(&WifiState) + Send + 'static,
+) -> anyhow::Result<()> {
+    let mut wifi = EspWifi::new(modem, sysloop.clone(), Some(nvs))?;
     println!("wifi: created");
 
-    let ap_infos = wifi.scan()?;
+    let station = config.station() && !networks.is_empty();
+    let access_point = match config.access_point() {
+        AccessPointMode::Always => Some(access_point_configuration(config, name)),
+        AccessPointMode::Provisioning | AccessPointMode::Off => networks
+            .is_empty()
+            .then(|| access_point_configuration(config, name)),
+    };
+    if config.access_point() == AccessPointMode::Off && networks.is_empty() {
+        println!("wifi: no network known, running the access point to be provisioned");
+    }
+    wifi.set_configuration(&configuration(
+        station.then(ClientConfiguration::default),
+        access_point.as_ref(),
+    ))?;
+    wifi.start()?;
 
-    let mut channel = None;
-    let target = ap_infos.into_iter().find(|ap| ap.ssid == wifi_ssid);
-    if let Some(target) = target {
-        println!("wifi: ssid matched");
-        channel = Some(target.channel)
+    if let Some(access_point) = &access_point {
+        log_access_point(access_point);
+    }
+    if !station {
+        if networks.is_empty() {
+            on_change(&WifiState::Unprovisioned);
+        }
+        // Note: there is no connection to manage, the driver is kept running for as long as the
+        // device runs
+        Box::leak(Box::new(wifi));
+        return Ok(());
     }
 
-    wifi.set_configuration(&Configuration::Mixed(
-        ClientConfiguration {
-            ssid: wifi_ssid.into(),
-            password: wifi_pass.into(),
-            channel,
-            ..Default::default()
-        },
-        AccessPointConfiguration {
-            ssid: "aptest".into(),
-            channel: channel.unwrap_or(1),
-            ..Default::default()
-        },
-    ))?;
+    let (sender, events) = mpsc::channel();
+    let station_sender = sender.clone();
+    let station_subscription = sysloop.subscribe(move |event: &EspWifiEvent| {
+        let event = match event {
+            EspWifiEvent::StaConnected => WifiEvent::Associated,
+            EspWifiEvent::StaDisconnected => WifiEvent::Disconnected,
+            _ => return,
+        };
+        let _ = station_sender.send(event);
+    })?;
+    let ip_subscription = sysloop.subscribe(move |event: &IpEvent| {
+        let event = match event {
+            IpEvent::DhcpIpAssigned(assignment) => WifiEvent::GotIp(assignment.ip_settings.ip),
+            IpEvent::DhcpIpDeassigned(_) => WifiEvent::LostIp,
+            _ => return,
+        };
+        let _ = sender.send(event);
+    })?;
 
-    wifi.start()?;
+    let fallback = (config.access_point() == AccessPointMode::Provisioning)
+        .then(|| access_point_configuration(config, name))
+        .filter(|_| access_point.is_none());
+    let manager = ConnectionManager::new(networks, WifiTiming::default());
+    thread::Builder::new()
+        .name("wifi".into())
+        .stack_size(WIFI_STACK_SIZE)
+        .spawn(move || {
+            let _subscriptions = (station_subscription, ip_subscription);
+            run(wifi, manager, events, access_point, fallback, on_change);
+        })?;
+
+    Ok(())
+}
 
-    if !WifiWait::new(&sysloop)?
-        .wait_with_timeout(Duration::from_secs(20), || wifi.is_started().unwrap())
-    {
-        println!("wifi: device failed to start");
+// Access point named after the device unless the config names it, using WPA2 when it has a password
+fn access_point_configuration(config: &WifiConfig, name: &str) -> AccessPointConfiguration {
+    let ssid = match config.ssid().is_empty() {
+        true => name,
+        false => config.ssid(),
+    };
+    let auth_method = match config.password().is_empty() {
+        true => AuthMethod::None,
+        false => AuthMethod::WPA2Personal,
+    };
+    AccessPointConfiguration {
+        ssid: ssid.into(),
+        password: config.password().into(),
+        auth_method,
+        channel: config.channel(),
+        ..Default::default()
     }
+}
 
-    println!("wifi: connecting to {}", wifi_ssid);
+fn log_access_point(access_point: &AccessPointConfiguration) {
+    let security = match access_point.auth_method {
+        AuthMethod::None => "open",
+        _ => "WPA2",
+    };
+    println!(
+        "wifi: access point {} ({}) on channel {}",
+        access_point.ssid, security, access_point.channel
+    );
+}
 
-    wifi.connect()?;
+fn configuration(
+    client: Option<ClientConfiguration>,
+    access_point: Option<&AccessPointConfiguration>,
+) -> Configuration {
+    match (client, access_point) {
+        (Some(client), Some(access_point)) => Configuration::Mixed(client, access_point.clone()),
+        (Some(client), None) => Configuration::Client(client),
+        (None, Some(access_point)) => Configuration::AccessPoint(access_point.clone()),
+        (None, None) => Configuration::None,
+    }
+}
+
+// Feed driver events and timeouts to the manager and carry out what it asks for, until the event
+// loop is gone
+//
+// Note: `fallback` is the access point to start when the known networks keep failing
+fn run(
+    mut wifi: EspWifi<'static>,
+    mut manager: ConnectionManager,
+    events: Receiver<WifiEvent>,
+    mut access_point: Option<AccessPointConfiguration>,
+    mut fallback: Option<AccessPointConfiguration>,
+    on_change: impl Fn(&WifiState),
+) {
+    // Whether the station is associated or trying to be, so it has to leave before joining another
+    // network
+    let mut station_busy = false;
+    let mut action = manager.start(Instant::now());
+    on_change(manager.state());
 
-    // TODO: fix
-    // if !EspNetifWait::new::<EspNetif>(wifi.sta_netif(), &sysloop)?.wait_with_timeout(
-    //     Duration::from_secs(20),
-    //     || {
-    //         wifi.is_connected().unwrap()
-    //             && wifi.sta_netif().get_ip_info().unwrap().ip != Ipv4Addr::new(0, 0, 0, 0)
-    //     },
-    // ) {
-    //     bail!("wifi: did not connect or did not receive a DHCP lease");
-    // }
+    loop {
+        let state = manager.state().clone();
+        let executed = action.take().map(|action| {
+            execute(
+                &mut wifi,
+                action,
+                &mut station_busy,
+                &events,
+                access_point.as_ref(),
+            )
+        });
 
-    let ip_info = wifi.sta_netif().get_ip_info()?;
+        // Note: the round that fails last ends with the station leaving its network, so the mode
+        // changes without cutting an attempt short
+        if manager.failed_rounds() >= PROVISIONING_AFTER_ROUNDS {
+            if let Some(fallback) = fallback.take() {
+                println!("wifi: no network reachable, running the access point to be provisioned");
+                let client = ClientConfiguration::default();
+                match wifi.set_configuration(&configuration(Some(client), Some(&fallback))) {
+                    Ok(()) => {
+                        log_access_point(&fallback);
+                        access_point = Some(fallback);
+                    }
+                    Err(err) => println!("wifi: failed to start the access point: {}", err),
+                }
+            }
+        }
+        action = match executed {
+            // Note: a network that can not be tried counts as failed, the manager moves on to the
+            // next one
+            Some(Err(err)) => {
+                println!("wifi: failed to connect: {}", err);
+                manager.handle(WifiEvent::Disconnected, Instant::now())
+            }
+            _ => {
+                let received = match manager.deadline() {
+                    Some(deadline) => {
+                        events.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                    }
+                    None => events.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                let now = Instant::now();
+                match received {
+                    Ok(event) => {
+                        if event == WifiEvent::Disconnected {
+                            station_busy = false;
+                        }
+                        manager.handle(event, now)
+                    }
+                    Err(RecvTimeoutError::Timeout) => manager.poll(now),
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+        };
 
-    println!("wifi: dhcp info: {:?}", ip_info);
+        if *manager.state() != state {
+            on_change(manager.state());
+        }
+    }
+}
+
+fn execute(
+    wifi: &mut EspWifi<'static>,
+    action: WifiAction,
+    station_busy: &mut bool,
+    events: &Receiver<WifiEvent>,
+    access_point: Option<&AccessPointConfiguration>,
+) -> Result<(), EspError> {
+    if *station_busy {
+        wifi.disconnect()?;
+        // Note: the driver reports leaving as a disconnect, which must not be taken for the next
+        // network failing
+        let deadline = Instant::now() + DISCONNECT_TIMEOUT;
+        while let Ok(event) =
+            events.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        {
+            if event == WifiEvent::Disconnected {
+                break;
+            }
+        }
+        *station_busy = false;
+    }
+
+    let WifiAction::Connect(credentials) = action else {
+        return Ok(());
+    };
 
-    Ok(wifi)
+    // Note: the access point follows the station to the channel of its network
+    let channel = wifi
+        .scan()?
+        .into_iter()
+        .find(|ap| ap.ssid == credentials.ssid())
+        .map(|ap| ap.channel);
+    let client = ClientConfiguration {
+        ssid: credentials.ssid().into(),
+        password: credentials.password().into(),
+        channel,
+        ..Default::default()
+    };
+    let access_point = access_point.map(|access_point| AccessPointConfiguration {
+        channel: channel.unwrap_or(access_point.channel),
+        ..access_point.clone()
+    });
+    wifi.set_configuration(&configuration(Some(client), access_point.as_ref()))?;
+    wifi.connect()?;
+    *station_busy = true;
+    Ok(())
 }
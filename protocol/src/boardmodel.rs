@@ -0,0 +1,42 @@
+use core::fmt;
+use core::str::FromStr;
+
+use crate::ParseError;
+
+// ESP32 camera boards the firmware has pin assignments for
+//
+// Note: names match the `BOARD_MODEL` values the device firmware is built with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BoardModel {
+    Freenove,
+    AIThinker,
+}
+
+impl BoardModel {
+    pub const ALL: [BoardModel; 2] = [BoardModel::Freenove, BoardModel::AIThinker];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BoardModel::Freenove => "Freenove",
+            BoardModel::AIThinker => "AIThinker",
+        }
+    }
+}
+
+// Parse a name, eg. "Freenove" or "aithinker"
+impl FromStr for BoardModel {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|board| board.name().eq_ignore_ascii_case(s.trim()))
+            .ok_or(ParseError::new("board model"))
+    }
+}
+
+impl fmt::Display for BoardModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
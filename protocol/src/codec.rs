@@ -0,0 +1,128 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::DecodeError;
+
+// Appends big-endian fields to a payload
+#[derive(Default)]
+pub struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Writer { bytes: Vec::new() }
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(u8::from(value));
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.bytes.extend_from_slice(value);
+    }
+
+    // Length (1) followed by UTF-8, longer strings are truncated to 255 bytes
+    pub fn str(&mut self, value: &str) {
+        let mut len = value.len().min(u8::MAX as usize);
+        while !value.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.u8(len as u8);
+        self.bytes(&value.as_bytes()[..len]);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+// Reads big-endian fields from a payload, failing once it runs out of bytes
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, pos: 0 }
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        let end = self.pos + len;
+        if end > self.bytes.len() {
+            return Err(DecodeError::InvalidPayloadLength {
+                expected: end,
+                actual: self.bytes.len(),
+            });
+        }
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_be_bytes(self.array()?))
+    }
+
+    pub fn bool(&mut self) -> Result<bool, DecodeError> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn str(&mut self) -> Result<String, DecodeError> {
+        let len = self.u8()? as usize;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+
+    // The bytes not read yet, eg. a field that takes up the rest of the payload
+    pub fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.bytes[self.pos..];
+        self.pos = self.bytes.len();
+        rest
+    }
+
+    // Fails when bytes are left over, eg. a payload longer than its packet type allows
+    pub fn finish(self) -> Result<(), DecodeError> {
+        if self.pos != self.bytes.len() {
+            return Err(DecodeError::InvalidPayloadLength {
+                expected: self.pos,
+                actual: self.bytes.len(),
+            });
+        }
+        Ok(())
+    }
+}
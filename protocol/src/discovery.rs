@@ -0,0 +1,145 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::{BoardModel, SensorModel, PROTOCOL_VERSION};
+
+// DNS-SD service the devices advertise, eg. "garage._espcam._tcp.local."
+pub const SERVICE_TYPE: &str = "_espcam._tcp";
+
+// TXT record keys
+pub const TXT_BOARD: &str = "board";
+pub const TXT_SENSOR: &str = "sensor";
+pub const TXT_FIRMWARE: &str = "firmware";
+pub const TXT_PROTOCOL: &str = "protocol";
+
+// What a device tells about itself in the TXT records of its service
+//
+// Note: values that are missing or not understood are left as None, so older and newer devices can still be found
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Advertisement {
+    pub board: Option<BoardModel>,
+    pub sensor: Option<SensorModel>,
+    pub firmware_version: Option<String>,
+    pub protocol_version: Option<u8>,
+}
+
+impl Advertisement {
+    pub fn new(
+        board: Option<BoardModel>,
+        sensor: Option<SensorModel>,
+        firmware_version: &str,
+    ) -> Self {
+        Advertisement {
+            board,
+            sensor,
+            firmware_version: Some(firmware_version.to_string()),
+            protocol_version: Some(PROTOCOL_VERSION),
+        }
+    }
+
+    // Whether the device speaks the protocol version of this build (assumed when it does not say)
+    pub fn is_compatible(&self) -> bool {
+        self.protocol_version
+            .is_none_or(|version| version == PROTOCOL_VERSION)
+    }
+
+    // Sensors are advertised by name, or by product id for sensors without one (eg. "0x9655")
+    pub fn txt_records(&self) -> Vec<(&'static str, String)> {
+        let mut records = Vec::new();
+        if let Some(board) = self.board {
+            records.push((TXT_BOARD, board.name().to_string()));
+        }
+        if let Some(sensor) = self.sensor {
+            let sensor = match sensor.name() {
+                Some(name) => name.to_string(),
+                None => format!("{:#06x}", sensor.pid()),
+            };
+            records.push((TXT_SENSOR, sensor));
+        }
+        if let Some(firmware_version) = &self.firmware_version {
+            records.push((TXT_FIRMWARE, firmware_version.clone()));
+        }
+        if let Some(protocol_version) = self.protocol_version {
+            records.push((TXT_PROTOCOL, protocol_version.to_string()));
+        }
+        records
+    }
+
+    pub fn from_txt<'a>(records: impl IntoIterator<Item = (&'a str, &'a str)>) -> Self {
+        let mut advertisement = Advertisement::default();
+        for (key, value) in records {
+            match key {
+                TXT_BOARD => advertisement.board = value.parse().ok(),
+                TXT_SENSOR => advertisement.sensor = parse_sensor(value),
+                TXT_FIRMWARE => advertisement.firmware_version = Some(value.to_string()),
+                TXT_PROTOCOL => advertisement.protocol_version = value.parse().ok(),
+                _ => {}
+            }
+        }
+        advertisement
+    }
+}
+
+fn parse_sensor(value: &str) -> Option<SensorModel> {
+    match value.strip_prefix("0x") {
+        Some(pid) => u16::from_str_radix(pid, 16).ok().map(SensorModel::from_pid),
+        None => value.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(advertisement: &Advertisement) -> Advertisement {
+        let records = advertisement.txt_records();
+        Advertisement::from_txt(records.iter().map(|(key, value)| (*key, value.as_str())))
+    }
+
+    #[test]
+    fn txt_round_trip() {
+        let advertisement = Advertisement::new(
+            Some(BoardModel::AIThinker),
+            Some(SensorModel::OV2640),
+            "0.3.0",
+        );
+        assert_eq!(round_trip(&advertisement), advertisement);
+        assert!(advertisement.is_compatible());
+    }
+
+    #[test]
+    fn unnamed_sensor_is_advertised_by_pid() {
+        let advertisement = Advertisement::new(None, Some(SensorModel::Unknown(0x9655)), "0.3.0");
+        assert!(advertisement
+            .txt_records()
+            .contains(&(TXT_SENSOR, "0x9655".to_string())));
+        assert_eq!(round_trip(&advertisement), advertisement);
+    }
+
+    #[test]
+    fn missing_records_are_none() {
+        let advertisement = Advertisement::default();
+        assert!(advertisement.txt_records().is_empty());
+        assert_eq!(round_trip(&advertisement), advertisement);
+        assert!(advertisement.is_compatible());
+    }
+
+    #[test]
+    fn unknown_records_and_values_are_ignored() {
+        let advertisement = Advertisement::from_txt([
+            (TXT_BOARD, "breadboard"),
+            (TXT_SENSOR, "0xnope"),
+            (TXT_PROTOCOL, "two"),
+            ("color", "blue"),
+        ]);
+        assert_eq!(advertisement, Advertisement::default());
+    }
+
+    #[test]
+    fn other_protocol_version_is_incompatible() {
+        let advertisement = Advertisement::from_txt([(TXT_PROTOCOL, "1")]);
+        assert_eq!(advertisement.protocol_version, Some(1));
+        assert!(!advertisement.is_compatible());
+    }
+}
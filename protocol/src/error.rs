@@ -0,0 +1,165 @@
+use core::fmt;
+
+use crate::Role;
+
+// Reasons a byte sequence could not be decoded into a frame or packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    // Fewer bytes than the frame requires
+    Truncated { expected: usize, actual: usize },
+    // Frame does not start with the protocol magic bytes
+    InvalidMagic([u8; 2]),
+    // Frame was encoded with a different protocol version
+    UnsupportedVersion(u8),
+    // Frame declares a payload larger than `MAX_PAYLOAD_LEN`
+    PayloadTooLarge(u32),
+    // Frame checksum does not match its contents
+    ChecksumMismatch { expected: u32, actual: u32 },
+    // Message type does not match any known packet
+    InvalidMessageType(u8),
+    // Payload length does not match the packet type
+    InvalidPayloadLength { expected: usize, actual: usize },
+    // Payload value does not map to a known FrameSize
+    InvalidFrameSize(u32),
+    // Payload value does not map to a known PixelFormat
+    InvalidPixelFormat(u32),
+    // Payload value is outside of the JpegQuality range
+    InvalidJpegQuality(u32),
+    // Payload value does not map to a known SensorControl
+    InvalidSensorControl(u8),
+    // Payload value is outside of the range of its SensorControl
+    InvalidControlValue { control: u8, value: i32 },
+    // Payload is not a valid SSID and password, see `WifiCredentials`
+    InvalidWifiCredentials,
+    // Payload is not a valid `WifiConfig`
+    InvalidWifiConfig,
+    // Payload is not a valid `DeviceSettings`
+    InvalidDeviceSettings,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Truncated { expected, actual } => {
+                write!(
+                    f,
+                    "frame: truncated: expected {} bytes, got {}",
+                    expected, actual
+                )
+            }
+            DecodeError::InvalidMagic(magic) => write!(f, "frame: invalid magic {:02x?}", magic),
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "frame: unsupported protocol version {}", version)
+            }
+            DecodeError::PayloadTooLarge(len) => {
+                write!(f, "frame: payload too large ({} bytes)", len)
+            }
+            DecodeError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "frame: checksum mismatch: expected {:#010x}, got {:#010x}",
+                expected, actual
+            ),
+            DecodeError::InvalidMessageType(message_type) => {
+                write!(f, "packet: invalid message type {:#04x}", message_type)
+            }
+            DecodeError::InvalidPayloadLength { expected, actual } => write!(
+                f,
+                "packet: invalid payload length: expected {} bytes, got {}",
+                expected, actual
+            ),
+            DecodeError::InvalidFrameSize(value) => {
+                write!(f, "packet: invalid frame size {}", value)
+            }
+            DecodeError::InvalidPixelFormat(value) => {
+                write!(f, "packet: invalid pixel format {}", value)
+            }
+            DecodeError::InvalidJpegQuality(value) => {
+                write!(f, "packet: invalid jpeg quality {}", value)
+            }
+            DecodeError::InvalidSensorControl(control) => {
+                write!(f, "packet: invalid sensor control {}", control)
+            }
+            DecodeError::InvalidControlValue { control, value } => {
+                write!(
+                    f,
+                    "packet: invalid value {} for sensor control {}",
+                    value, control
+                )
+            }
+            DecodeError::InvalidWifiCredentials => f.write_str("packet: invalid wifi credentials"),
+            DecodeError::InvalidWifiConfig => f.write_str("packet: invalid wifi config"),
+            DecodeError::InvalidDeviceSettings => f.write_str("packet: invalid device settings"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {}
+
+#[cfg(feature = "std")]
+impl From<DecodeError> for std::io::Error {
+    fn from(err: DecodeError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, err)
+    }
+}
+
+// A string could not be parsed into a protocol type, eg. a frame size name given on the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseError {
+    what: &'static str,
+}
+
+impl ParseError {
+    pub fn new(what: &'static str) -> Self {
+        ParseError { what }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid {}", self.what)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {}
+
+// Reasons a session could not be established or continued
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionError {
+    // The side did not prove it knows the key
+    BadProof(Role),
+    // A record did not decrypt, it was tampered with, replayed or reordered
+    BadRecord,
+    // A record declares more data than a sender may put in one
+    RecordTooLarge(u32),
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::BadProof(Role::Controller) => {
+                f.write_str("session: controller does not know the key")
+            }
+            SessionError::BadProof(Role::Device) => {
+                f.write_str("session: device does not know the key")
+            }
+            SessionError::BadRecord => {
+                f.write_str("session: record failed authentication (tampered with or replayed)")
+            }
+            SessionError::RecordTooLarge(len) => {
+                write!(f, "session: record too large ({} bytes)", len)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SessionError {}
+
+#[cfg(feature = "std")]
+impl From<SessionError> for std::io::Error {
+    fn from(err: SessionError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, err)
+    }
+}
@@ -0,0 +1,72 @@
+use core::fmt;
+
+// Category of a failed request, sent to the controller in `OutgoingPacket::Error`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    // Camera driver failed to initialise (eg. sensor not detected, out of memory)
+    CameraInit,
+    // Camera driver did not return a frame buffer
+    FrameBufferUnavailable,
+    // Sensor rejected a setting or the setting is not implemented by the sensor
+    SensorSet,
+    // Request frame or packet could not be decoded
+    BadPacket,
+    // Request is well-formed but asks for something the device does not support
+    UnsupportedOption,
+    // Device is already serving as many connections as it can
+    Busy,
+    // Client did not authenticate with the device's key, see `Handshake`
+    Unauthorized,
+    // Device failed to store a setting (eg. in flash)
+    Storage,
+    // Code sent by a newer device that this version does not know about
+    Unknown(u16),
+}
+
+impl From<ErrorCode> for u16 {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::CameraInit => 1,
+            ErrorCode::FrameBufferUnavailable => 2,
+            ErrorCode::SensorSet => 3,
+            ErrorCode::BadPacket => 4,
+            ErrorCode::UnsupportedOption => 5,
+            ErrorCode::Busy => 6,
+            ErrorCode::Unauthorized => 7,
+            ErrorCode::Storage => 8,
+            ErrorCode::Unknown(value) => value,
+        }
+    }
+}
+
+impl From<u16> for ErrorCode {
+    fn from(value: u16) -> Self {
+        match value {
+            1 => ErrorCode::CameraInit,
+            2 => ErrorCode::FrameBufferUnavailable,
+            3 => ErrorCode::SensorSet,
+            4 => ErrorCode::BadPacket,
+            5 => ErrorCode::UnsupportedOption,
+            6 => ErrorCode::Busy,
+            7 => ErrorCode::Unauthorized,
+            8 => ErrorCode::Storage,
+            value => ErrorCode::Unknown(value),
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorCode::CameraInit => f.write_str("camera init failed"),
+            ErrorCode::FrameBufferUnavailable => f.write_str("frame buffer unavailable"),
+            ErrorCode::SensorSet => f.write_str("sensor setting failed"),
+            ErrorCode::BadPacket => f.write_str("bad packet"),
+            ErrorCode::UnsupportedOption => f.write_str("unsupported option"),
+            ErrorCode::Busy => f.write_str("device busy"),
+            ErrorCode::Unauthorized => f.write_str("unauthorized"),
+            ErrorCode::Storage => f.write_str("storage failed"),
+            ErrorCode::Unknown(value) => write!(f, "unknown error {}", value),
+        }
+    }
+}
@@ -0,0 +1,347 @@
+use alloc::vec::Vec;
+
+use crate::crc::{crc32, Crc32};
+use crate::DecodeError;
+
+// Identifies the start of a frame, "EC" for ESP32 Camera
+pub const MAGIC: [u8; 2] = *b"EC";
+// Bumped whenever the frame layout or the encoding of an existing message changes
+//
+// 2: connections start with a `Handshake`, after which frames are sent in encrypted records
+pub const PROTOCOL_VERSION: u8 = 2;
+// Upper bound on payloads to avoid allocating whatever a corrupt length field asks for
+pub const MAX_PAYLOAD_LEN: u32 = 8 * 1024 * 1024;
+
+// Frame layout (multi-byte fields are big-endian):
+//
+// | magic (2) | version (1) | message type (1) | request id (4) | payload length (4) |
+// | payload (payload length) | CRC-32 of everything before it (4) |
+//
+// Responses echo the request id of the request they answer, which allows several requests to
+// share one connection. They also echo the message type, unless the request failed in which
+// case an error packet is sent instead
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub message_type: u8,
+    pub request_id: u32,
+    pub payload_len: u32,
+}
+
+impl FrameHeader {
+    pub const LEN: usize = 12;
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        let mut buf = [0; Self::LEN];
+        buf[0..2].copy_from_slice(&MAGIC);
+        buf[2] = PROTOCOL_VERSION;
+        buf[3] = self.message_type;
+        buf[4..8].copy_from_slice(&self.request_id.to_be_bytes());
+        buf[8..12].copy_from_slice(&self.payload_len.to_be_bytes());
+        buf
+    }
+
+    // Validates magic, version and payload length so a bad frame is rejected before its payload
+    // is read
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        if bytes.len() < Self::LEN {
+            return Err(DecodeError::Truncated {
+                expected: Self::LEN,
+                actual: bytes.len(),
+            });
+        }
+
+        if bytes[0..2] != MAGIC {
+            return Err(DecodeError::InvalidMagic([bytes[0], bytes[1]]));
+        }
+        if bytes[2] != PROTOCOL_VERSION {
+            return Err(DecodeError::UnsupportedVersion(bytes[2]));
+        }
+
+        let payload_len = u32::from_be_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]);
+        if payload_len > MAX_PAYLOAD_LEN {
+            return Err(DecodeError::PayloadTooLarge(payload_len));
+        }
+
+        Ok(FrameHeader {
+            message_type: bytes[3],
+            request_id: u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+            payload_len,
+        })
+    }
+}
+
+// A single message on the wire, see `FrameHeader` for the layout
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub message_type: u8,
+    pub request_id: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub const CRC_LEN: usize = 4;
+
+    pub fn new(message_type: u8, request_id: u32, payload: Vec<u8>) -> Self {
+        Frame {
+            message_type,
+            request_id,
+            payload,
+        }
+    }
+
+    pub fn header(&self) -> FrameHeader {
+        FrameHeader {
+            message_type: self.message_type,
+            request_id: self.request_id,
+            payload_len: self.payload.len() as u32,
+        }
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        FrameHeader::LEN + self.payload.len() + Self::CRC_LEN
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.encoded_len());
+        bytes.extend_from_slice(&self.header().encode());
+        bytes.extend_from_slice(&self.payload);
+
+        let mut crc = Crc32::new();
+        crc.update(&bytes);
+        bytes.extend_from_slice(&crc.finish().to_be_bytes());
+        bytes
+    }
+
+    // Decodes a single frame from the start of `bytes`, returning it with the number of bytes used
+    pub fn decode(bytes: &[u8]) -> Result<(Self, usize), DecodeError> {
+        let header = FrameHeader::decode(bytes)?;
+        let len = FrameHeader::LEN + header.payload_len as usize + Self::CRC_LEN;
+        if bytes.len() < len {
+            return Err(DecodeError::Truncated {
+                expected: len,
+                actual: bytes.len(),
+            });
+        }
+
+        let (body, crc_bytes) = bytes[..len].split_at(len - Self::CRC_LEN);
+        verify_crc(body, crc_bytes)?;
+
+        let frame = Frame::new(
+            header.message_type,
+            header.request_id,
+            body[FrameHeader::LEN..].to_vec(),
+        );
+        Ok((frame, len))
+    }
+
+    // Try read a single frame from a stream (eg. TcpStream)
+    #[cfg(feature = "std")]
+    pub fn read_from<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        Self::read_from_limited(reader, MAX_PAYLOAD_LEN)
+    }
+
+    // Try read a single frame with a payload of at most `max_payload_len` bytes, eg. from a peer
+    // that is not trusted yet, a larger one is rejected before anything is allocated for it
+    #[cfg(feature = "std")]
+    pub fn read_from_limited<R: std::io::Read>(
+        reader: &mut R,
+        max_payload_len: u32,
+    ) -> std::io::Result<Self> {
+        let mut header_bytes = [0; FrameHeader::LEN];
+        reader.read_exact(&mut header_bytes)?;
+        let header = FrameHeader::decode(&header_bytes)?;
+        if header.payload_len > max_payload_len {
+            return Err(DecodeError::PayloadTooLarge(header.payload_len).into());
+        }
+
+        let mut payload = alloc::vec![0; header.payload_len as usize];
+        reader.read_exact(&mut payload)?;
+
+        let mut crc_bytes = [0; Self::CRC_LEN];
+        reader.read_exact(&mut crc_bytes)?;
+
+        let mut crc = Crc32::new();
+        crc.update(&header_bytes);
+        crc.update(&payload);
+        check_crc(crc.finish(), &crc_bytes)?;
+
+        Ok(Frame::new(header.message_type, header.request_id, payload))
+    }
+
+    // Write the encoded frame to a stream without copying the payload
+    #[cfg(feature = "std")]
+    pub fn write_to<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        Self::write_parts_to(writer, self.message_type, self.request_id, &[&self.payload])
+    }
+
+    // Write a frame whose payload is made up of `parts`, eg. a header followed by borrowed image data
+    #[cfg(feature = "std")]
+    pub fn write_parts_to<W: std::io::Write>(
+        writer: &mut W,
+        message_type: u8,
+        request_id: u32,
+        parts: &[&[u8]],
+    ) -> std::io::Result<()> {
+        let header = FrameHeader {
+            message_type,
+            request_id,
+            payload_len: parts.iter().map(|part| part.len()).sum::<usize>() as u32,
+        }
+        .encode();
+        let mut crc = Crc32::new();
+        crc.update(&header);
+        writer.write_all(&header)?;
+        for part in parts {
+            crc.update(part);
+            writer.write_all(part)?;
+        }
+        writer.write_all(&crc.finish().to_be_bytes())?;
+        writer.flush()
+    }
+}
+
+fn verify_crc(body: &[u8], crc_bytes: &[u8]) -> Result<(), DecodeError> {
+    check_crc(crc32(body), crc_bytes)
+}
+
+fn check_crc(actual: u32, crc_bytes: &[u8]) -> Result<(), DecodeError> {
+    let expected = u32::from_be_bytes([crc_bytes[0], crc_bytes[1], crc_bytes[2], crc_bytes[3]]);
+    if expected != actual {
+        return Err(DecodeError::ChecksumMismatch { expected, actual });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    fn frame() -> Frame {
+        Frame::new(7, 0x0102_0304, vec![1, 2, 3, 4, 5])
+    }
+
+    #[test]
+    fn round_trip() {
+        let frame = frame();
+        let bytes = frame.encode();
+        assert_eq!(bytes.len(), frame.encoded_len());
+        assert_eq!(Frame::decode(&bytes), Ok((frame, bytes.len())));
+    }
+
+    #[test]
+    fn empty_payload_round_trip() {
+        let frame = Frame::new(5, 1, Vec::new());
+        let bytes = frame.encode();
+        assert_eq!(Frame::decode(&bytes), Ok((frame, bytes.len())));
+    }
+
+    // Note: a buffer may hold more than one frame, only the first is decoded
+    #[test]
+    fn decode_stops_after_frame() {
+        let frame = frame();
+        let mut bytes = frame.encode();
+        let len = bytes.len();
+        bytes.extend_from_slice(&Frame::new(1, 2, Vec::new()).encode());
+        assert_eq!(Frame::decode(&bytes), Ok((frame, len)));
+    }
+
+    #[test]
+    fn bad_magic_is_rejected() {
+        let mut bytes = frame().encode();
+        bytes[0] = b'X';
+        assert_eq!(
+            Frame::decode(&bytes),
+            Err(DecodeError::InvalidMagic([b'X', b'C']))
+        );
+    }
+
+    #[test]
+    fn other_version_is_rejected() {
+        let mut bytes = frame().encode();
+        bytes[2] = PROTOCOL_VERSION + 1;
+        assert_eq!(
+            Frame::decode(&bytes),
+            Err(DecodeError::UnsupportedVersion(PROTOCOL_VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn bad_checksum_is_rejected() {
+        let mut bytes = frame().encode();
+        bytes[FrameHeader::LEN] ^= 0xff;
+        assert!(matches!(
+            Frame::decode(&bytes),
+            Err(DecodeError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn truncated_is_rejected() {
+        let bytes = frame().encode();
+        assert_eq!(
+            Frame::decode(&bytes[..FrameHeader::LEN - 1]),
+            Err(DecodeError::Truncated {
+                expected: FrameHeader::LEN,
+                actual: FrameHeader::LEN - 1,
+            })
+        );
+        assert_eq!(
+            Frame::decode(&bytes[..bytes.len() - 1]),
+            Err(DecodeError::Truncated {
+                expected: bytes.len(),
+                actual: bytes.len() - 1,
+            })
+        );
+    }
+
+    #[test]
+    fn oversized_payload_is_rejected() {
+        let header = FrameHeader {
+            message_type: 1,
+            request_id: 1,
+            payload_len: MAX_PAYLOAD_LEN + 1,
+        };
+        assert_eq!(
+            FrameHeader::decode(&header.encode()),
+            Err(DecodeError::PayloadTooLarge(MAX_PAYLOAD_LEN + 1))
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn stream_round_trip() {
+        let frame = frame();
+        let mut bytes = Vec::new();
+        frame.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes, frame.encode());
+        assert_eq!(Frame::read_from(&mut bytes.as_slice()).unwrap(), frame);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn stream_bad_checksum_is_rejected() {
+        let mut bytes = frame().encode();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        let err = Frame::read_from(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn stream_limit_is_checked_before_the_payload() {
+        let bytes = frame().encode();
+        assert_eq!(
+            Frame::read_from_limited(&mut bytes.as_slice(), 5).unwrap(),
+            frame()
+        );
+
+        // Note: only the header is there, a payload would be read (and fail) if it were accepted
+        let err = Frame::read_from_limited(&mut &bytes[..FrameHeader::LEN], 4).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        let err = err.into_inner().unwrap().downcast::<DecodeError>().unwrap();
+        assert_eq!(*err, DecodeError::PayloadTooLarge(5));
+    }
+}
@@ -0,0 +1,341 @@
+use core::cmp::Ordering;
+use core::fmt;
+use core::str::FromStr;
+
+use crate::{DecodeError, ParseError};
+
+pub const DEFAULT_FRAME_SIZE: FrameSize = FrameSize::SVGA;
+
+// ESP32 supported image resolutions encapsulated in a Rust enum with transforms
+//
+// Note: the wire values follow the `framesize_t` numbering of the esp32-camera library, but are fixed
+// here independently of the library version the device is built against
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FrameSize {
+    R96X96 = 0,   // 96 x 96
+    QQVGA = 1,    // Quarter Quarter VGA, 160 x 120, 28.8 kbps
+    QCIF = 2,     // Quarter Common Intermediate Format, 176 x 144, 38.016 kbps
+    HQVGA = 3,    // Half Quarter VGA, 240 x 176
+    R240X240 = 4, // 240 x 240
+    QVGA = 5,     // Quarter VGA, 320 x 240, 115.2 kbps
+    CIF = 6,      // Common Intermediate Format, 352 x 288, 152.064 kbps
+    HVGA = 7,     // Half VGA, 480 x 320
+    VGA = 8,      // Video Graphics Array, 640 x 480, 460.8 kbps
+    SVGA = 9,     // Super VGA, 800 x 600, 720 kbps
+    XGA = 10,     // Extended Graphics Array, 1024 x 768, 1.175296 Mbps
+    HD = 11,      // High Definition, 1280 x 720
+    SXGA = 12,    // Super Extended Graphics Array, 1280 x 1024, 1.96608 Mbps
+    UXGA = 13,    // Ultra Extended Graphics Array, 1600 x 1200, 2.88064 Mbps
+    FHD = 14,     // Full HD, 1920 x 1080 (3MP sensors)
+    PHD = 15,     // Portrait HD, 720 x 1280 (3MP sensors)
+    P3MP = 16,    // Portrait 3MP, 864 x 1536 (3MP sensors)
+    QXGA = 17,    // Quad Extended Graphics Array, 2048 x 1536 (3MP sensors)
+    QHD = 18,     // Quad HD, 2560 x 1440 (5MP sensors)
+    WQXGA = 19,   // Wide Quad Extended Graphics Array, 2560 x 1600 (5MP sensors)
+    PFHD = 20,    // Portrait Full HD, 1080 x 1920 (5MP sensors)
+    QSXGA = 21,   // Quad Super Extended Graphics Array, 2560 x 1920 (5MP sensors)
+}
+
+impl FrameSize {
+    pub const ALL: [FrameSize; 22] = [
+        FrameSize::R96X96,
+        FrameSize::QQVGA,
+        FrameSize::QCIF,
+        FrameSize::HQVGA,
+        FrameSize::R240X240,
+        FrameSize::QVGA,
+        FrameSize::CIF,
+        FrameSize::HVGA,
+        FrameSize::VGA,
+        FrameSize::SVGA,
+        FrameSize::XGA,
+        FrameSize::HD,
+        FrameSize::SXGA,
+        FrameSize::UXGA,
+        FrameSize::FHD,
+        FrameSize::PHD,
+        FrameSize::P3MP,
+        FrameSize::QXGA,
+        FrameSize::QHD,
+        FrameSize::WQXGA,
+        FrameSize::PFHD,
+        FrameSize::QSXGA,
+    ];
+
+    // All frame sizes, in wire order
+    pub fn iter() -> impl Iterator<Item = FrameSize> {
+        Self::ALL.into_iter()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.dimensions().0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.dimensions().1
+    }
+
+    pub fn pixels(&self) -> u32 {
+        self.width() * self.height()
+    }
+
+    // Resolutions as produced by the esp32-camera library
+    pub fn dimensions(&self) -> (u32, u32) {
+        match self {
+            FrameSize::R96X96 => (96, 96),
+            FrameSize::QQVGA => (160, 120),
+            FrameSize::QCIF => (176, 144),
+            FrameSize::HQVGA => (240, 176),
+            FrameSize::R240X240 => (240, 240),
+            FrameSize::QVGA => (320, 240),
+            FrameSize::CIF => (400, 296),
+            FrameSize::HVGA => (480, 320),
+            FrameSize::VGA => (640, 480),
+            FrameSize::SVGA => (800, 600),
+            FrameSize::XGA => (1024, 768),
+            FrameSize::HD => (1280, 720),
+            FrameSize::SXGA => (1280, 1024),
+            FrameSize::UXGA => (1600, 1200),
+            FrameSize::FHD => (1920, 1080),
+            FrameSize::PHD => (720, 1280),
+            FrameSize::P3MP => (864, 1536),
+            FrameSize::QXGA => (2048, 1536),
+            FrameSize::QHD => (2560, 1440),
+            FrameSize::WQXGA => (2560, 1600),
+            FrameSize::PFHD => (1080, 1920),
+            FrameSize::QSXGA => (2560, 1920),
+        }
+    }
+
+    // Width to height ratio in lowest terms, eg. (4, 3) for SVGA
+    pub fn aspect_ratio(&self) -> (u32, u32) {
+        let (width, height) = self.dimensions();
+        let divisor = gcd(width, height);
+        (width / divisor, height / divisor)
+    }
+
+    // Whether both dimensions fit inside of `width` x `height`
+    pub fn fits_within(&self, width: u32, height: u32) -> bool {
+        self.width() <= width && self.height() <= height
+    }
+
+    // Largest frame size that fits inside of `width` x `height`, None if even the smallest does not
+    pub fn closest_not_above(width: u32, height: u32) -> Option<FrameSize> {
+        Self::iter()
+            .filter(|frame_size| frame_size.fits_within(width, height))
+            .max()
+    }
+
+    // Name as used by the esp32-camera library (without the FRAMESIZE_ prefix)
+    pub fn name(&self) -> &'static str {
+        match self {
+            FrameSize::R96X96 => "96X96",
+            FrameSize::QQVGA => "QQVGA",
+            FrameSize::QCIF => "QCIF",
+            FrameSize::HQVGA => "HQVGA",
+            FrameSize::R240X240 => "240X240",
+            FrameSize::QVGA => "QVGA",
+            FrameSize::CIF => "CIF",
+            FrameSize::HVGA => "HVGA",
+            FrameSize::VGA => "VGA",
+            FrameSize::SVGA => "SVGA",
+            FrameSize::XGA => "XGA",
+            FrameSize::HD => "HD",
+            FrameSize::SXGA => "SXGA",
+            FrameSize::UXGA => "UXGA",
+            FrameSize::FHD => "FHD",
+            FrameSize::PHD => "P_HD",
+            FrameSize::P3MP => "P_3MP",
+            FrameSize::QXGA => "QXGA",
+            FrameSize::QHD => "QHD",
+            FrameSize::WQXGA => "WQXGA",
+            FrameSize::PFHD => "P_FHD",
+            FrameSize::QSXGA => "QSXGA",
+        }
+    }
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    match b {
+        0 => a,
+        _ => gcd(b, a % b),
+    }
+}
+
+// Frame sizes are ordered by pixel count, then by width
+impl Ord for FrameSize {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.pixels()
+            .cmp(&other.pixels())
+            .then_with(|| self.width().cmp(&other.width()))
+    }
+}
+
+impl PartialOrd for FrameSize {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Parse either a name (eg. "SVGA", "svga", "P_HD") or exact dimensions (eg. "800x600")
+impl FromStr for FrameSize {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(frame_size) =
+            Self::iter().find(|frame_size| frame_size.name().eq_ignore_ascii_case(s))
+        {
+            return Ok(frame_size);
+        }
+
+        let invalid = ParseError::new("frame size");
+        let (width, height) = s.split_once(['x', 'X']).ok_or(invalid)?;
+        let width: u32 = width.trim().parse().map_err(|_| invalid)?;
+        let height: u32 = height.trim().parse().map_err(|_| invalid)?;
+        Self::iter()
+            .find(|frame_size| frame_size.dimensions() == (width, height))
+            .ok_or(invalid)
+    }
+}
+
+impl fmt::Display for FrameSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// rust enum -> wire value
+impl From<FrameSize> for u32 {
+    fn from(frame_size: FrameSize) -> Self {
+        frame_size as u32
+    }
+}
+
+// wire value -> rust enum
+impl TryFrom<u32> for FrameSize {
+    type Error = DecodeError;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        FrameSize::ALL
+            .into_iter()
+            .find(|frame_size| *frame_size as u32 == value)
+            .ok_or(DecodeError::InvalidFrameSize(value))
+    }
+}
+
+impl Default for FrameSize {
+    fn default() -> Self {
+        DEFAULT_FRAME_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    #[test]
+    fn ordered_by_pixels() {
+        assert!(FrameSize::QQVGA < FrameSize::QCIF);
+        assert!(FrameSize::SVGA < FrameSize::XGA);
+        assert!(FrameSize::UXGA < FrameSize::FHD);
+        assert_eq!(FrameSize::iter().max(), Some(FrameSize::QSXGA));
+        assert_eq!(FrameSize::iter().min(), Some(FrameSize::R96X96));
+    }
+
+    // Note: portrait sizes have as many pixels as their landscape counterparts
+    #[test]
+    fn equal_pixels_ordered_by_width() {
+        assert_eq!(FrameSize::HD.pixels(), FrameSize::PHD.pixels());
+        assert!(FrameSize::PHD < FrameSize::HD);
+        assert_eq!(FrameSize::FHD.pixels(), FrameSize::PFHD.pixels());
+        assert!(FrameSize::PFHD < FrameSize::FHD);
+        assert_ne!(FrameSize::HD.cmp(&FrameSize::PHD), Ordering::Equal);
+    }
+
+    // Note: no two sizes compare equal, so sorting gives one order
+    #[test]
+    fn order_is_total() {
+        let mut sorted: Vec<FrameSize> = FrameSize::iter().collect();
+        sorted.sort();
+        for pair in sorted.windows(2) {
+            assert_eq!(pair[0].cmp(&pair[1]), Ordering::Less, "{:?}", pair);
+        }
+    }
+
+    #[test]
+    fn parse_round_trip() {
+        for frame_size in FrameSize::iter() {
+            assert_eq!(frame_size.to_string().parse(), Ok(frame_size));
+            assert_eq!(frame_size.name().to_lowercase().parse(), Ok(frame_size));
+            let (width, height) = frame_size.dimensions();
+            assert_eq!(
+                alloc::format!("{}x{}", width, height).parse(),
+                Ok(frame_size)
+            );
+            assert_eq!(FrameSize::try_from(u32::from(frame_size)), Ok(frame_size));
+        }
+        assert_eq!(" 800 X 600 ".parse(), Ok(FrameSize::SVGA));
+    }
+
+    #[test]
+    fn invalid_frame_size_is_rejected() {
+        for s in [
+            "", "SVGA2", "800x", "x600", "800x601", "800*600", "-800x600",
+        ] {
+            assert!(s.parse::<FrameSize>().is_err(), "{:?}", s);
+        }
+        assert_eq!(
+            FrameSize::try_from(22),
+            Err(DecodeError::InvalidFrameSize(22))
+        );
+    }
+
+    #[test]
+    fn closest_not_above_clamps() {
+        // Below the smallest size
+        assert_eq!(FrameSize::closest_not_above(95, 96), None);
+        assert_eq!(FrameSize::closest_not_above(96, 95), None);
+        assert_eq!(FrameSize::closest_not_above(0, 0), None);
+        // Exactly the smallest and the largest
+        assert_eq!(
+            FrameSize::closest_not_above(96, 96),
+            Some(FrameSize::R96X96)
+        );
+        assert_eq!(
+            FrameSize::closest_not_above(2560, 1920),
+            Some(FrameSize::QSXGA)
+        );
+        assert_eq!(
+            FrameSize::closest_not_above(u32::MAX, u32::MAX),
+            Some(FrameSize::QSXGA)
+        );
+        // One pixel short of a size gives the next smaller one that fits
+        assert_eq!(
+            FrameSize::closest_not_above(1599, 1200),
+            Some(FrameSize::SXGA)
+        );
+        assert_eq!(
+            FrameSize::closest_not_above(1279, 1000),
+            Some(FrameSize::XGA)
+        );
+        // A portrait size fits where landscape ones of as many pixels do not
+        assert_eq!(
+            FrameSize::closest_not_above(800, 1280),
+            Some(FrameSize::PHD)
+        );
+    }
+
+    #[test]
+    fn aspect_ratio_in_lowest_terms() {
+        assert_eq!(FrameSize::SVGA.aspect_ratio(), (4, 3));
+        assert_eq!(FrameSize::HD.aspect_ratio(), (16, 9));
+        assert_eq!(FrameSize::PHD.aspect_ratio(), (9, 16));
+        assert_eq!(FrameSize::R96X96.aspect_ratio(), (1, 1));
+        assert_eq!(FrameSize::CIF.aspect_ratio(), (50, 37));
+    }
+}
@@ -0,0 +1,415 @@
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::session::RecordCipher;
+use crate::{DecodeError, Frame, ParseError, SessionError};
+
+// Message types of the handshake, which the controller starts each connection with before any
+// packet is accepted
+const TYPE_HELLO: u8 = 0x10;
+const TYPE_PROOF: u8 = 0x11;
+
+pub const NONCE_LEN: usize = 16;
+pub const PROOF_LEN: usize = 32;
+
+// Random bytes each side contributes to a session, so neither proofs nor records of one session
+// are accepted in another
+pub type Nonce = [u8; NONCE_LEN];
+pub type Proof = [u8; PROOF_LEN];
+
+// Labels keeping the proofs and keys of both sides apart
+const CONTROLLER_PROOF: &[u8] = b"espcam controller proof";
+const DEVICE_PROOF: &[u8] = b"espcam device proof";
+const CONTROLLER_KEY: &[u8] = b"espcam controller to device";
+const DEVICE_KEY: &[u8] = b"espcam device to controller";
+
+// Secret shared by the controller and its devices, given as hex (eg. from `openssl rand -hex 32`)
+#[derive(Clone, PartialEq, Eq)]
+pub struct PresharedKey(Vec<u8>);
+
+impl PresharedKey {
+    // In bytes, shorter keys could be guessed from a recorded handshake
+    pub const MIN_LEN: usize = 16;
+    pub const MAX_LEN: usize = 64;
+
+    pub fn new(bytes: &[u8]) -> Option<Self> {
+        (Self::MIN_LEN..=Self::MAX_LEN)
+            .contains(&bytes.len())
+            .then(|| PresharedKey(bytes.to_vec()))
+    }
+}
+
+// Note: the key itself is never printed
+impl fmt::Debug for PresharedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PresharedKey({} bytes)", self.0.len())
+    }
+}
+
+impl FromStr for PresharedKey {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = ParseError::new("key, use 32 to 128 hex digits");
+        let digits = s.trim().as_bytes();
+        if digits.len() % 2 != 0 {
+            return Err(error);
+        }
+        let bytes = digits
+            .chunks_exact(2)
+            .map(|pair| {
+                let pair = core::str::from_utf8(pair).map_err(|_| error)?;
+                u8::from_str_radix(pair, 16).map_err(|_| error)
+            })
+            .collect::<Result<Vec<u8>, ParseError>>()?;
+        PresharedKey::new(&bytes).ok_or(error)
+    }
+}
+
+// Side of a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Controller,
+    Device,
+}
+
+// Handshake messages sent by the controller
+//
+// 1. Hello: the controller's nonce, answered with a Challenge
+// 2. Proof: the controller's proof, answered with Accepted or an `ErrorCode::Unauthorized` error
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandshakeRequest {
+    Hello { nonce: Nonce },
+    Proof { proof: Proof },
+}
+
+impl HandshakeRequest {
+    // Longest payload of a handshake message, the device reads no more before the client is
+    // authenticated
+    pub const MAX_PAYLOAD_LEN: u32 = match NONCE_LEN > PROOF_LEN {
+        true => NONCE_LEN as u32,
+        false => PROOF_LEN as u32,
+    };
+
+    pub fn message_type(&self) -> u8 {
+        match self {
+            HandshakeRequest::Hello { .. } => TYPE_HELLO,
+            HandshakeRequest::Proof { .. } => TYPE_PROOF,
+        }
+    }
+
+    pub fn to_frame(&self, request_id: u32) -> Frame {
+        let payload = match self {
+            HandshakeRequest::Hello { nonce } => nonce.to_vec(),
+            HandshakeRequest::Proof { proof } => proof.to_vec(),
+        };
+        Frame::new(self.message_type(), request_id, payload)
+    }
+
+    // Try deserialize a handshake message received by the device, any other message (eg. a packet
+    // of a client that did not authenticate) is rejected
+    pub fn from_frame(frame: &Frame) -> Result<Self, DecodeError> {
+        let payload = frame.payload.as_slice();
+
+        match frame.message_type {
+            TYPE_HELLO => Ok(HandshakeRequest::Hello {
+                nonce: read_array(payload)?,
+            }),
+            TYPE_PROOF => Ok(HandshakeRequest::Proof {
+                proof: read_array(payload)?,
+            }),
+            message_type => Err(DecodeError::InvalidMessageType(message_type)),
+        }
+    }
+}
+
+// Handshake messages sent by the device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandshakeResponse {
+    // Payload: the device's nonce (16 bytes) followed by its proof (32 bytes)
+    Challenge { nonce: Nonce, proof: Proof },
+    // The controller's proof was verified, the session is encrypted from here on
+    Accepted,
+}
+
+impl HandshakeResponse {
+    pub fn message_type(&self) -> u8 {
+        match self {
+            HandshakeResponse::Challenge { .. } => TYPE_HELLO,
+            HandshakeResponse::Accepted => TYPE_PROOF,
+        }
+    }
+
+    pub fn to_frame(&self, request_id: u32) -> Frame {
+        let payload = match self {
+            HandshakeResponse::Challenge { nonce, proof } => [&nonce[..], &proof[..]].concat(),
+            HandshakeResponse::Accepted => Vec::new(),
+        };
+        Frame::new(self.message_type(), request_id, payload)
+    }
+
+    // Try deserialize a handshake message received by the controller
+    pub fn from_frame(frame: &Frame) -> Result<Self, DecodeError> {
+        let payload = frame.payload.as_slice();
+
+        match frame.message_type {
+            TYPE_HELLO => {
+                if payload.len() != NONCE_LEN + PROOF_LEN {
+                    return Err(DecodeError::InvalidPayloadLength {
+                        expected: NONCE_LEN + PROOF_LEN,
+                        actual: payload.len(),
+                    });
+                }
+                Ok(HandshakeResponse::Challenge {
+                    nonce: read_array(&payload[..NONCE_LEN])?,
+                    proof: read_array(&payload[NONCE_LEN..])?,
+                })
+            }
+            TYPE_PROOF => {
+                read_array::<0>(payload)?;
+                Ok(HandshakeResponse::Accepted)
+            }
+            message_type => Err(DecodeError::InvalidMessageType(message_type)),
+        }
+    }
+}
+
+// Mutual authentication with the preshared key: each side proves it knows the key with an HMAC
+// over both nonces, and the session keys are derived from the key and both nonces
+//
+// Note: the device proves itself first, so a controller does not hand its proof to an impostor
+pub struct Handshake {
+    key: PresharedKey,
+    transcript: [u8; 2 * NONCE_LEN],
+}
+
+impl Handshake {
+    pub fn new(key: &PresharedKey, controller_nonce: &Nonce, device_nonce: &Nonce) -> Self {
+        let mut transcript = [0; 2 * NONCE_LEN];
+        transcript[..NONCE_LEN].copy_from_slice(controller_nonce);
+        transcript[NONCE_LEN..].copy_from_slice(device_nonce);
+        Handshake {
+            key: key.clone(),
+            transcript,
+        }
+    }
+
+    // Proof that `role` knows the key
+    pub fn proof(&self, role: Role) -> Proof {
+        self.proof_mac(role).finalize().into_bytes().into()
+    }
+
+    // Note: compared in constant time so the proof can not be guessed byte by byte
+    pub fn verify(&self, role: Role, proof: &Proof) -> Result<(), SessionError> {
+        self.proof_mac(role)
+            .verify_slice(proof)
+            .map_err(|_| SessionError::BadProof(role))
+    }
+
+    // Ciphers for the records `role` sends and receives once both proofs are verified
+    pub fn ciphers(&self, role: Role) -> (RecordCipher, RecordCipher) {
+        let hkdf = Hkdf::<Sha256>::new(Some(&self.transcript), &self.key.0);
+        let cipher = |label: &[u8]| {
+            let mut key = [0; 32];
+            hkdf.expand(label, &mut key)
+                .expect("32 bytes is a valid length for HKDF-SHA256");
+            RecordCipher::new(ChaCha20Poly1305::new(&key.into()))
+        };
+        let (send, receive) = match role {
+            Role::Controller => (CONTROLLER_KEY, DEVICE_KEY),
+            Role::Device => (DEVICE_KEY, CONTROLLER_KEY),
+        };
+        (cipher(send), cipher(receive))
+    }
+
+    fn proof_mac(&self, role: Role) -> Hmac<Sha256> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.key.0)
+            .expect("HMAC takes keys of any length");
+        mac.update(match role {
+            Role::Controller => CONTROLLER_PROOF,
+            Role::Device => DEVICE_PROOF,
+        });
+        mac.update(&self.transcript);
+        mac
+    }
+}
+
+fn read_array<const N: usize>(payload: &[u8]) -> Result<[u8; N], DecodeError> {
+    payload
+        .try_into()
+        .map_err(|_| DecodeError::InvalidPayloadLength {
+            expected: N,
+            actual: payload.len(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTROLLER_NONCE: Nonce = [1; NONCE_LEN];
+    const DEVICE_NONCE: Nonce = [2; NONCE_LEN];
+
+    fn key(byte: u8) -> PresharedKey {
+        PresharedKey::new(&[byte; 32]).unwrap()
+    }
+
+    #[test]
+    fn key_parses_hex() {
+        let key: PresharedKey = "000102030405060708090a0b0c0d0e0F".parse().unwrap();
+        assert_eq!(key.0, (0..16).collect::<Vec<u8>>());
+        assert!(" 00112233445566778899aabbccddeeff\n"
+            .parse::<PresharedKey>()
+            .is_ok());
+    }
+
+    #[test]
+    fn invalid_key_is_rejected() {
+        // Too short, too long, odd length and not hex
+        assert!("00112233445566778899aabbccddee"
+            .parse::<PresharedKey>()
+            .is_err());
+        assert!("00".repeat(65).parse::<PresharedKey>().is_err());
+        assert!("00112233445566778899aabbccddeeff0"
+            .parse::<PresharedKey>()
+            .is_err());
+        assert!("00112233445566778899aabbccddeegg"
+            .parse::<PresharedKey>()
+            .is_err());
+        assert!("0011223344556677889€aabbccddeeff"
+            .parse::<PresharedKey>()
+            .is_err());
+    }
+
+    #[test]
+    fn key_is_not_printed() {
+        assert_eq!(alloc::format!("{:?}", key(0xab)), "PresharedKey(32 bytes)");
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        for request in [
+            HandshakeRequest::Hello {
+                nonce: CONTROLLER_NONCE,
+            },
+            HandshakeRequest::Proof {
+                proof: [3; PROOF_LEN],
+            },
+        ] {
+            let frame = request.to_frame(1);
+            assert!(frame.payload.len() <= HandshakeRequest::MAX_PAYLOAD_LEN as usize);
+            assert_eq!(HandshakeRequest::from_frame(&frame), Ok(request));
+        }
+        for response in [
+            HandshakeResponse::Challenge {
+                nonce: DEVICE_NONCE,
+                proof: [4; PROOF_LEN],
+            },
+            HandshakeResponse::Accepted,
+        ] {
+            assert_eq!(
+                HandshakeResponse::from_frame(&response.to_frame(1)),
+                Ok(response)
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_messages_are_rejected() {
+        // Note: a packet sent before authenticating
+        let frame = Frame::new(1, 1, Vec::new());
+        assert_eq!(
+            HandshakeRequest::from_frame(&frame),
+            Err(DecodeError::InvalidMessageType(1))
+        );
+        let frame = Frame::new(TYPE_HELLO, 1, alloc::vec![0; NONCE_LEN - 1]);
+        assert_eq!(
+            HandshakeRequest::from_frame(&frame),
+            Err(DecodeError::InvalidPayloadLength {
+                expected: NONCE_LEN,
+                actual: NONCE_LEN - 1
+            })
+        );
+        assert!(HandshakeResponse::from_frame(&frame).is_err());
+        let frame = Frame::new(TYPE_PROOF, 1, alloc::vec![0]);
+        assert!(HandshakeResponse::from_frame(&frame).is_err());
+    }
+
+    #[test]
+    fn proofs_verify_with_the_same_key() {
+        let controller = Handshake::new(&key(1), &CONTROLLER_NONCE, &DEVICE_NONCE);
+        let device = Handshake::new(&key(1), &CONTROLLER_NONCE, &DEVICE_NONCE);
+        assert_eq!(
+            device.verify(Role::Controller, &controller.proof(Role::Controller)),
+            Ok(())
+        );
+        assert_eq!(
+            controller.verify(Role::Device, &device.proof(Role::Device)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn proof_with_another_key_is_rejected() {
+        let controller = Handshake::new(&key(1), &CONTROLLER_NONCE, &DEVICE_NONCE);
+        let device = Handshake::new(&key(2), &CONTROLLER_NONCE, &DEVICE_NONCE);
+        assert_eq!(
+            device.verify(Role::Controller, &controller.proof(Role::Controller)),
+            Err(SessionError::BadProof(Role::Controller))
+        );
+        assert_eq!(
+            controller.verify(Role::Device, &device.proof(Role::Device)),
+            Err(SessionError::BadProof(Role::Device))
+        );
+    }
+
+    // Note: a proof is bound to its side and its session, so it can not be reflected or replayed
+    #[test]
+    fn proof_of_another_role_or_session_is_rejected() {
+        let handshake = Handshake::new(&key(1), &CONTROLLER_NONCE, &DEVICE_NONCE);
+        assert!(handshake
+            .verify(Role::Controller, &handshake.proof(Role::Device))
+            .is_err());
+
+        let other = Handshake::new(&key(1), &CONTROLLER_NONCE, &[3; NONCE_LEN]);
+        assert!(other
+            .verify(Role::Controller, &handshake.proof(Role::Controller))
+            .is_err());
+    }
+
+    #[test]
+    fn ciphers_pair_up() {
+        let handshake = Handshake::new(&key(1), &CONTROLLER_NONCE, &DEVICE_NONCE);
+        let (mut controller_send, mut controller_receive) = handshake.ciphers(Role::Controller);
+        let (mut device_send, mut device_receive) = handshake.ciphers(Role::Device);
+
+        let mut data = *b"capture";
+        let tag = controller_send.seal(&mut data);
+        assert_ne!(&data, b"capture");
+        assert_eq!(device_receive.open(&mut data, &tag), Ok(()));
+        assert_eq!(&data, b"capture");
+
+        let mut data = *b"jpeg";
+        let tag = device_send.seal(&mut data);
+        assert_eq!(controller_receive.open(&mut data, &tag), Ok(()));
+        assert_eq!(&data, b"jpeg");
+    }
+
+    #[test]
+    fn ciphers_of_another_session_do_not_open_records() {
+        let handshake = Handshake::new(&key(1), &CONTROLLER_NONCE, &DEVICE_NONCE);
+        let other = Handshake::new(&key(1), &CONTROLLER_NONCE, &[3; NONCE_LEN]);
+        let (mut send, _) = handshake.ciphers(Role::Controller);
+        let (_, mut receive) = other.ciphers(Role::Device);
+
+        let mut data = *b"capture";
+        let tag = send.seal(&mut data);
+        assert_eq!(receive.open(&mut data, &tag), Err(SessionError::BadRecord));
+    }
+}
@@ -0,0 +1,51 @@
+use crate::{DecodeError, PixelFormat};
+
+// Metadata sent ahead of the image data in a capture response, needed to decode raw formats
+//
+// Layout (big-endian): width (2) | height (2) | pixel format (4) | timestamp in µs since boot (8)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageInfo {
+    pub width: u16,
+    pub height: u16,
+    pub pixel_format: PixelFormat,
+    pub timestamp_us: u64,
+}
+
+impl ImageInfo {
+    pub const LEN: usize = 16;
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        let mut buf = [0; Self::LEN];
+        buf[0..2].copy_from_slice(&self.width.to_be_bytes());
+        buf[2..4].copy_from_slice(&self.height.to_be_bytes());
+        buf[4..8].copy_from_slice(&u32::from(self.pixel_format).to_be_bytes());
+        buf[8..16].copy_from_slice(&self.timestamp_us.to_be_bytes());
+        buf
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        if bytes.len() < Self::LEN {
+            return Err(DecodeError::InvalidPayloadLength {
+                expected: Self::LEN,
+                actual: bytes.len(),
+            });
+        }
+
+        let mut timestamp = [0; 8];
+        timestamp.copy_from_slice(&bytes[8..16]);
+
+        Ok(ImageInfo {
+            width: u16::from_be_bytes([bytes[0], bytes[1]]),
+            height: u16::from_be_bytes([bytes[2], bytes[3]]),
+            pixel_format: u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]])
+                .try_into()?,
+            timestamp_us: u64::from_be_bytes(timestamp),
+        })
+    }
+
+    // Size of the image data for raw formats, None for compressed formats
+    pub fn raw_len(&self) -> Option<usize> {
+        let bytes_per_pixel = self.pixel_format.bytes_per_pixel()?;
+        Some(self.width as usize * self.height as usize * bytes_per_pixel)
+    }
+}
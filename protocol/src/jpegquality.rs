@@ -0,0 +1,68 @@
+use core::fmt;
+use core::str::FromStr;
+
+use crate::{DecodeError, ParseError};
+
+pub const DEFAULT_JPEG_QUALITY: JpegQuality = JpegQuality(12);
+
+// JPEG compression quality used by the camera driver, lower values give larger, more detailed
+// images (min: 0, max: 63)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct JpegQuality(u8);
+
+impl JpegQuality {
+    pub const MIN: u8 = 0;
+    pub const MAX: u8 = 63;
+
+    pub fn new(value: u8) -> Option<Self> {
+        (value <= Self::MAX).then_some(JpegQuality(value))
+    }
+
+    pub fn get(&self) -> u8 {
+        self.0
+    }
+}
+
+// rust type -> wire value
+impl From<JpegQuality> for u32 {
+    fn from(jpeg_quality: JpegQuality) -> Self {
+        jpeg_quality.0 as u32
+    }
+}
+
+// wire value -> rust type
+impl TryFrom<u32> for JpegQuality {
+    type Error = DecodeError;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        u8::try_from(value)
+            .ok()
+            .and_then(JpegQuality::new)
+            .ok_or(DecodeError::InvalidJpegQuality(value))
+    }
+}
+
+impl Default for JpegQuality {
+    fn default() -> Self {
+        DEFAULT_JPEG_QUALITY
+    }
+}
+
+impl fmt::Display for JpegQuality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// Parse a number from 0 (best) to 63
+impl FromStr for JpegQuality {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.trim()
+            .parse()
+            .ok()
+            .and_then(JpegQuality::new)
+            .ok_or(ParseError::new("jpeg quality"))
+    }
+}
@@ -0,0 +1,50 @@
+// Wire format shared by the ESP32 device firmware and the controller
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+mod boardmodel;
+mod codec;
+mod crc;
+mod discovery;
+mod error;
+mod errorcode;
+mod frame;
+mod framesize;
+mod handshake;
+mod image;
+mod jpegquality;
+mod packet;
+mod pixelformat;
+mod sensor;
+mod sensormodel;
+mod session;
+mod settings;
+mod status;
+mod wifi;
+
+pub use boardmodel::BoardModel;
+pub use codec::{Reader, Writer};
+pub use discovery::{
+    Advertisement, SERVICE_TYPE, TXT_BOARD, TXT_FIRMWARE, TXT_PROTOCOL, TXT_SENSOR,
+};
+pub use error::{DecodeError, ParseError, SessionError};
+pub use errorcode::ErrorCode;
+pub use frame::{Frame, FrameHeader, MAGIC, MAX_PAYLOAD_LEN, PROTOCOL_VERSION};
+pub use framesize::{FrameSize, DEFAULT_FRAME_SIZE};
+pub use handshake::{
+    Handshake, HandshakeRequest, HandshakeResponse, Nonce, PresharedKey, Proof, Role, NONCE_LEN,
+    PROOF_LEN,
+};
+pub use image::ImageInfo;
+pub use jpegquality::{JpegQuality, DEFAULT_JPEG_QUALITY};
+pub use packet::{IncomingPacket, OutgoingPacket};
+pub use pixelformat::{PixelFormat, DEFAULT_PIXEL_FORMAT};
+pub use sensor::{ControlValue, SensorControl, SensorSettings, SpecialEffect, WhiteBalanceMode};
+pub use sensormodel::SensorModel;
+#[cfg(feature = "std")]
+pub use session::SecureStream;
+pub use session::{RecordCipher, MAX_RECORD_LEN, TAG_LEN};
+pub use settings::{DeviceSettings, DEFAULT_PORT};
+pub use status::{CameraStatus, DeviceStatus, ResetReason, SensorInfo, WifiStatus};
+pub use wifi::{AccessPointMode, WifiConfig, WifiCredentials};
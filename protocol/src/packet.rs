@@ -0,0 +1,484 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::{
+    ControlValue, DecodeError, DeviceSettings, DeviceStatus, ErrorCode, Frame, FrameSize,
+    ImageInfo, JpegQuality, PixelFormat, WifiConfig, WifiCredentials,
+};
+
+// Message types shared by requests and the responses that answer them
+const TYPE_CAPTURE: u8 = 1;
+const TYPE_SET_PIXEL_FORMAT: u8 = 2;
+const TYPE_SET_FRAME_SIZE: u8 = 3;
+const TYPE_RESTART: u8 = 4;
+const TYPE_PING: u8 = 5;
+const TYPE_SET_JPEG_QUALITY: u8 = 6;
+const TYPE_SET_CONTROL: u8 = 7;
+const TYPE_GET_STATUS: u8 = 8;
+const TYPE_SET_WIFI: u8 = 9;
+const TYPE_SET_WIFI_CONFIG: u8 = 10;
+const TYPE_SET_DEVICE_SETTINGS: u8 = 11;
+const TYPE_FACTORY_RESET: u8 = 12;
+// Sent instead of the regular response when a request fails
+const TYPE_ERROR: u8 = 0xff;
+
+// Packets for controlling/configuring the ESP32
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IncomingPacket {
+    Capture,
+    SetPixelFormat(PixelFormat),
+    SetFrameSize(FrameSize),
+    Restart,
+    // Keeps an idle session open and checks the device is responsive
+    Ping,
+    SetJpegQuality(JpegQuality),
+    SetControl(ControlValue),
+    GetStatus,
+    // Store the network to join, the device restarts to join it once the response has been sent
+    SetWifi(WifiCredentials),
+    // Store the station and access point setup, applied when the device restarts once the response
+    // has been sent
+    SetWifiConfig(WifiConfig),
+    // Store the name, port and whether camera settings are kept, applied when the device restarts
+    // once the response has been sent
+    SetDeviceSettings(DeviceSettings),
+    // Erase everything stored on the device (networks included), it restarts with the defaults
+    // once the response has been sent
+    FactoryReset,
+}
+
+impl IncomingPacket {
+    pub fn message_type(&self) -> u8 {
+        match self {
+            IncomingPacket::Capture => TYPE_CAPTURE,
+            IncomingPacket::SetPixelFormat(_) => TYPE_SET_PIXEL_FORMAT,
+            IncomingPacket::SetFrameSize(_) => TYPE_SET_FRAME_SIZE,
+            IncomingPacket::Restart => TYPE_RESTART,
+            IncomingPacket::Ping => TYPE_PING,
+            IncomingPacket::SetJpegQuality(_) => TYPE_SET_JPEG_QUALITY,
+            IncomingPacket::SetControl(_) => TYPE_SET_CONTROL,
+            IncomingPacket::GetStatus => TYPE_GET_STATUS,
+            IncomingPacket::SetWifi(_) => TYPE_SET_WIFI,
+            IncomingPacket::SetWifiConfig(_) => TYPE_SET_WIFI_CONFIG,
+            IncomingPacket::SetDeviceSettings(_) => TYPE_SET_DEVICE_SETTINGS,
+            IncomingPacket::FactoryReset => TYPE_FACTORY_RESET,
+        }
+    }
+
+    // Serialize packet into a frame for sending to the ESP32
+    pub fn to_frame(&self, request_id: u32) -> Frame {
+        let payload = match self {
+            IncomingPacket::SetPixelFormat(pixel_format) => {
+                u32::from(*pixel_format).to_be_bytes().to_vec()
+            }
+            IncomingPacket::SetFrameSize(frame_size) => {
+                u32::from(*frame_size).to_be_bytes().to_vec()
+            }
+            IncomingPacket::SetJpegQuality(jpeg_quality) => {
+                u32::from(*jpeg_quality).to_be_bytes().to_vec()
+            }
+            IncomingPacket::SetControl(control_value) => control_value.encode().to_vec(),
+            IncomingPacket::SetWifi(credentials) => credentials.encode(),
+            IncomingPacket::SetWifiConfig(config) => config.encode(),
+            IncomingPacket::SetDeviceSettings(settings) => settings.encode(),
+            IncomingPacket::Capture
+            | IncomingPacket::Restart
+            | IncomingPacket::Ping
+            | IncomingPacket::GetStatus
+            | IncomingPacket::FactoryReset => Vec::new(),
+        };
+
+        Frame::new(self.message_type(), request_id, payload)
+    }
+
+    // Try deserialize packet from a frame received by the ESP32
+    pub fn from_frame(frame: &Frame) -> Result<Self, DecodeError> {
+        let payload = frame.payload.as_slice();
+
+        match frame.message_type {
+            TYPE_CAPTURE => {
+                expect_len(payload, 0)?;
+                Ok(IncomingPacket::Capture)
+            }
+            TYPE_SET_PIXEL_FORMAT => Ok(IncomingPacket::SetPixelFormat(
+                read_u32(payload)?.try_into()?,
+            )),
+            TYPE_SET_FRAME_SIZE => Ok(IncomingPacket::SetFrameSize(read_u32(payload)?.try_into()?)),
+            TYPE_RESTART => {
+                expect_len(payload, 0)?;
+                Ok(IncomingPacket::Restart)
+            }
+            TYPE_PING => {
+                expect_len(payload, 0)?;
+                Ok(IncomingPacket::Ping)
+            }
+            TYPE_SET_JPEG_QUALITY => Ok(IncomingPacket::SetJpegQuality(
+                read_u32(payload)?.try_into()?,
+            )),
+            TYPE_SET_CONTROL => {
+                expect_len(payload, ControlValue::LEN)?;
+                Ok(IncomingPacket::SetControl(ControlValue::decode(payload)?))
+            }
+            TYPE_GET_STATUS => {
+                expect_len(payload, 0)?;
+                Ok(IncomingPacket::GetStatus)
+            }
+            TYPE_SET_WIFI => Ok(IncomingPacket::SetWifi(WifiCredentials::decode(payload)?)),
+            TYPE_SET_WIFI_CONFIG => Ok(IncomingPacket::SetWifiConfig(WifiConfig::decode(payload)?)),
+            TYPE_SET_DEVICE_SETTINGS => Ok(IncomingPacket::SetDeviceSettings(
+                DeviceSettings::decode(payload)?,
+            )),
+            TYPE_FACTORY_RESET => {
+                expect_len(payload, 0)?;
+                Ok(IncomingPacket::FactoryReset)
+            }
+            message_type => Err(DecodeError::InvalidMessageType(message_type)),
+        }
+    }
+}
+
+// Packet format for ESP32 responses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutgoingPacket {
+    // Payload: image info followed by the image data
+    Capture { info: ImageInfo, data: Vec<u8> },
+    SetPixelFormat(bool),
+    SetFrameSize(bool),
+    Restart(bool),
+    Ping,
+    SetJpegQuality(bool),
+    SetControl(bool),
+    Status(DeviceStatus),
+    SetWifi(bool),
+    SetWifiConfig(bool),
+    SetDeviceSettings(bool),
+    FactoryReset(bool),
+    // Payload: error code (2 bytes) followed by a UTF-8 message
+    Error { code: ErrorCode, message: String },
+}
+
+impl OutgoingPacket {
+    pub fn message_type(&self) -> u8 {
+        match self {
+            OutgoingPacket::Capture { .. } => TYPE_CAPTURE,
+            OutgoingPacket::SetPixelFormat(_) => TYPE_SET_PIXEL_FORMAT,
+            OutgoingPacket::SetFrameSize(_) => TYPE_SET_FRAME_SIZE,
+            OutgoingPacket::Restart(_) => TYPE_RESTART,
+            OutgoingPacket::Ping => TYPE_PING,
+            OutgoingPacket::SetJpegQuality(_) => TYPE_SET_JPEG_QUALITY,
+            OutgoingPacket::SetControl(_) => TYPE_SET_CONTROL,
+            OutgoingPacket::Status(_) => TYPE_GET_STATUS,
+            OutgoingPacket::SetWifi(_) => TYPE_SET_WIFI,
+            OutgoingPacket::SetWifiConfig(_) => TYPE_SET_WIFI_CONFIG,
+            OutgoingPacket::SetDeviceSettings(_) => TYPE_SET_DEVICE_SETTINGS,
+            OutgoingPacket::FactoryReset(_) => TYPE_FACTORY_RESET,
+            OutgoingPacket::Error { .. } => TYPE_ERROR,
+        }
+    }
+
+    // Serialize packet into a frame answering the request with `request_id`
+    pub fn to_frame(&self, request_id: u32) -> Frame {
+        let payload = match self {
+            OutgoingPacket::Capture { info, data } => capture_payload(info, data),
+            OutgoingPacket::SetPixelFormat(success)
+            | OutgoingPacket::SetFrameSize(success)
+            | OutgoingPacket::Restart(success)
+            | OutgoingPacket::SetJpegQuality(success)
+            | OutgoingPacket::SetControl(success)
+            | OutgoingPacket::SetWifi(success)
+            | OutgoingPacket::SetWifiConfig(success)
+            | OutgoingPacket::SetDeviceSettings(success)
+            | OutgoingPacket::FactoryReset(success) => alloc::vec![u8::from(*success)],
+            OutgoingPacket::Status(status) => status.encode(),
+            OutgoingPacket::Ping => Vec::new(),
+            OutgoingPacket::Error { code, message } => {
+                let mut payload = Vec::with_capacity(2 + message.len());
+                payload.extend_from_slice(&u16::from(*code).to_be_bytes());
+                payload.extend_from_slice(message.as_bytes());
+                payload
+            }
+        };
+
+        Frame::new(self.message_type(), request_id, payload)
+    }
+
+    // Same as `to_frame` but reuses the image data buffer instead of copying it
+    pub fn into_frame(self, request_id: u32) -> Frame {
+        match self {
+            OutgoingPacket::Capture { info, mut data } => {
+                data.splice(0..0, info.encode());
+                Frame::new(TYPE_CAPTURE, request_id, data)
+            }
+            packet => packet.to_frame(request_id),
+        }
+    }
+
+    // Write a capture response straight from borrowed image data (eg. a camera frame buffer)
+    // instead of copying it into an `OutgoingPacket::Capture` first
+    #[cfg(feature = "std")]
+    pub fn write_capture_to<W: std::io::Write>(
+        writer: &mut W,
+        request_id: u32,
+        info: &ImageInfo,
+        data: &[u8],
+    ) -> std::io::Result<()> {
+        Frame::write_parts_to(writer, TYPE_CAPTURE, request_id, &[&info.encode(), data])
+    }
+
+    // Try deserialize packet from a frame received by the controller
+    pub fn from_frame(frame: &Frame) -> Result<Self, DecodeError> {
+        let payload = frame.payload.as_slice();
+
+        match frame.message_type {
+            TYPE_CAPTURE => Ok(OutgoingPacket::Capture {
+                info: ImageInfo::decode(payload)?,
+                data: payload[ImageInfo::LEN..].to_vec(),
+            }),
+            TYPE_SET_PIXEL_FORMAT => Ok(OutgoingPacket::SetPixelFormat(read_bool(payload)?)),
+            TYPE_SET_FRAME_SIZE => Ok(OutgoingPacket::SetFrameSize(read_bool(payload)?)),
+            TYPE_RESTART => Ok(OutgoingPacket::Restart(read_bool(payload)?)),
+            TYPE_PING => {
+                expect_len(payload, 0)?;
+                Ok(OutgoingPacket::Ping)
+            }
+            TYPE_SET_JPEG_QUALITY => Ok(OutgoingPacket::SetJpegQuality(read_bool(payload)?)),
+            TYPE_SET_CONTROL => Ok(OutgoingPacket::SetControl(read_bool(payload)?)),
+            TYPE_GET_STATUS => Ok(OutgoingPacket::Status(DeviceStatus::decode(payload)?)),
+            TYPE_SET_WIFI => Ok(OutgoingPacket::SetWifi(read_bool(payload)?)),
+            TYPE_SET_WIFI_CONFIG => Ok(OutgoingPacket::SetWifiConfig(read_bool(payload)?)),
+            TYPE_SET_DEVICE_SETTINGS => Ok(OutgoingPacket::SetDeviceSettings(read_bool(payload)?)),
+            TYPE_FACTORY_RESET => Ok(OutgoingPacket::FactoryReset(read_bool(payload)?)),
+            TYPE_ERROR => {
+                if payload.len() < 2 {
+                    return Err(DecodeError::InvalidPayloadLength {
+                        expected: 2,
+                        actual: payload.len(),
+                    });
+                }
+                Ok(OutgoingPacket::Error {
+                    code: u16::from_be_bytes([payload[0], payload[1]]).into(),
+                    message: String::from_utf8_lossy(&payload[2..]).into_owned(),
+                })
+            }
+            message_type => Err(DecodeError::InvalidMessageType(message_type)),
+        }
+    }
+}
+
+fn capture_payload(info: &ImageInfo, data: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(ImageInfo::LEN + data.len());
+    payload.extend_from_slice(&info.encode());
+    payload.extend_from_slice(data);
+    payload
+}
+
+fn expect_len(payload: &[u8], expected: usize) -> Result<(), DecodeError> {
+    if payload.len() != expected {
+        return Err(DecodeError::InvalidPayloadLength {
+            expected,
+            actual: payload.len(),
+        });
+    }
+    Ok(())
+}
+
+fn read_u32(payload: &[u8]) -> Result<u32, DecodeError> {
+    expect_len(payload, 4)?;
+    Ok(u32::from_be_bytes([
+        payload[0], payload[1], payload[2], payload[3],
+    ]))
+}
+
+fn read_bool(payload: &[u8]) -> Result<bool, DecodeError> {
+    expect_len(payload, 1)?;
+    Ok(payload[0] != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    use super::*;
+    use crate::{AccessPointMode, ResetReason, SensorControl};
+
+    fn incoming_round_trip(packet: IncomingPacket) {
+        let frame = packet.to_frame(42);
+        assert_eq!(frame.request_id, 42);
+        assert_eq!(IncomingPacket::from_frame(&frame), Ok(packet));
+    }
+
+    fn outgoing_round_trip(packet: OutgoingPacket) {
+        let frame = packet.to_frame(42);
+        assert_eq!(frame.request_id, 42);
+        assert_eq!(OutgoingPacket::from_frame(&frame).as_ref(), Ok(&packet));
+        assert_eq!(packet.into_frame(42), frame);
+    }
+
+    #[test]
+    fn incoming_round_trip_every_variant() {
+        incoming_round_trip(IncomingPacket::Capture);
+        incoming_round_trip(IncomingPacket::SetPixelFormat(PixelFormat::GRAYSCALE));
+        incoming_round_trip(IncomingPacket::SetFrameSize(FrameSize::UXGA));
+        incoming_round_trip(IncomingPacket::Restart);
+        incoming_round_trip(IncomingPacket::Ping);
+        incoming_round_trip(IncomingPacket::SetJpegQuality(
+            JpegQuality::new(63).unwrap(),
+        ));
+        incoming_round_trip(IncomingPacket::SetControl(
+            SensorControl::AeLevel.value(-2).unwrap(),
+        ));
+        incoming_round_trip(IncomingPacket::GetStatus);
+        incoming_round_trip(IncomingPacket::SetWifi(
+            WifiCredentials::new("home", "password").unwrap(),
+        ));
+        incoming_round_trip(IncomingPacket::SetWifiConfig(
+            WifiConfig::new(false, AccessPointMode::Always, 11, "", "password").unwrap(),
+        ));
+        incoming_round_trip(IncomingPacket::SetDeviceSettings(
+            DeviceSettings::new("front-door", 9000, false).unwrap(),
+        ));
+        incoming_round_trip(IncomingPacket::FactoryReset);
+    }
+
+    #[test]
+    fn outgoing_round_trip_every_variant() {
+        outgoing_round_trip(OutgoingPacket::Capture {
+            info: ImageInfo {
+                width: 160,
+                height: 120,
+                pixel_format: PixelFormat::JPEG,
+                timestamp_us: 1_234_567,
+            },
+            data: vec![0xff, 0xd8, 0xff, 0xd9],
+        });
+        outgoing_round_trip(OutgoingPacket::SetPixelFormat(true));
+        outgoing_round_trip(OutgoingPacket::SetFrameSize(false));
+        outgoing_round_trip(OutgoingPacket::Restart(true));
+        outgoing_round_trip(OutgoingPacket::Ping);
+        outgoing_round_trip(OutgoingPacket::SetJpegQuality(true));
+        outgoing_round_trip(OutgoingPacket::SetControl(false));
+        outgoing_round_trip(OutgoingPacket::Status(DeviceStatus {
+            firmware_version: "1.2.3".to_string(),
+            uptime_ms: 5000,
+            free_heap: 100_000,
+            free_psram: 0,
+            reset_reason: ResetReason::PowerOn,
+            wifi: None,
+            camera: None,
+        }));
+        outgoing_round_trip(OutgoingPacket::SetWifi(true));
+        outgoing_round_trip(OutgoingPacket::SetWifiConfig(true));
+        outgoing_round_trip(OutgoingPacket::SetDeviceSettings(false));
+        outgoing_round_trip(OutgoingPacket::FactoryReset(true));
+        outgoing_round_trip(OutgoingPacket::Error {
+            code: ErrorCode::SensorSet,
+            message: "sensor rejected the setting".to_string(),
+        });
+        outgoing_round_trip(OutgoingPacket::Error {
+            code: ErrorCode::Unknown(999),
+            message: String::new(),
+        });
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn write_capture_matches_to_frame() {
+        let info = ImageInfo {
+            width: 2,
+            height: 1,
+            pixel_format: PixelFormat::RGB565,
+            timestamp_us: 7,
+        };
+        let data = [1, 2, 3, 4];
+        let mut bytes = Vec::new();
+        OutgoingPacket::write_capture_to(&mut bytes, 3, &info, &data).unwrap();
+        let packet = OutgoingPacket::Capture {
+            info,
+            data: data.to_vec(),
+        };
+        assert_eq!(bytes, packet.to_frame(3).encode());
+    }
+
+    #[test]
+    fn unknown_message_type_is_rejected() {
+        let frame = Frame::new(0x7f, 1, Vec::new());
+        assert_eq!(
+            IncomingPacket::from_frame(&frame),
+            Err(DecodeError::InvalidMessageType(0x7f))
+        );
+        assert_eq!(
+            OutgoingPacket::from_frame(&frame),
+            Err(DecodeError::InvalidMessageType(0x7f))
+        );
+        // Note: errors are only ever sent by the device
+        let frame = Frame::new(TYPE_ERROR, 1, vec![0, 1]);
+        assert_eq!(
+            IncomingPacket::from_frame(&frame),
+            Err(DecodeError::InvalidMessageType(TYPE_ERROR))
+        );
+    }
+
+    #[test]
+    fn wrong_payload_length_is_rejected() {
+        let frame = Frame::new(TYPE_CAPTURE, 1, vec![0]);
+        assert_eq!(
+            IncomingPacket::from_frame(&frame),
+            Err(DecodeError::InvalidPayloadLength {
+                expected: 0,
+                actual: 1
+            })
+        );
+        let frame = Frame::new(TYPE_SET_FRAME_SIZE, 1, vec![0, 0, 1]);
+        assert_eq!(
+            IncomingPacket::from_frame(&frame),
+            Err(DecodeError::InvalidPayloadLength {
+                expected: 4,
+                actual: 3
+            })
+        );
+        let frame = Frame::new(TYPE_SET_CONTROL, 1, vec![0; ControlValue::LEN + 1]);
+        assert!(IncomingPacket::from_frame(&frame).is_err());
+        let frame = Frame::new(TYPE_RESTART, 1, Vec::new());
+        assert_eq!(
+            OutgoingPacket::from_frame(&frame),
+            Err(DecodeError::InvalidPayloadLength {
+                expected: 1,
+                actual: 0
+            })
+        );
+        let frame = Frame::new(TYPE_CAPTURE, 1, vec![0; ImageInfo::LEN - 1]);
+        assert!(OutgoingPacket::from_frame(&frame).is_err());
+        let frame = Frame::new(TYPE_ERROR, 1, vec![0]);
+        assert!(OutgoingPacket::from_frame(&frame).is_err());
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        let frame = Frame::new(TYPE_SET_FRAME_SIZE, 1, 99u32.to_be_bytes().to_vec());
+        assert_eq!(
+            IncomingPacket::from_frame(&frame),
+            Err(DecodeError::InvalidFrameSize(99))
+        );
+        let frame = Frame::new(TYPE_SET_PIXEL_FORMAT, 1, 2u32.to_be_bytes().to_vec());
+        assert_eq!(
+            IncomingPacket::from_frame(&frame),
+            Err(DecodeError::InvalidPixelFormat(2))
+        );
+        let frame = Frame::new(TYPE_SET_JPEG_QUALITY, 1, 64u32.to_be_bytes().to_vec());
+        assert_eq!(
+            IncomingPacket::from_frame(&frame),
+            Err(DecodeError::InvalidJpegQuality(64))
+        );
+        let mut payload = vec![u8::from(SensorControl::Gain)];
+        payload.extend_from_slice(&31i32.to_be_bytes());
+        let frame = Frame::new(TYPE_SET_CONTROL, 1, payload);
+        assert_eq!(
+            IncomingPacket::from_frame(&frame),
+            Err(DecodeError::InvalidControlValue {
+                control: SensorControl::Gain.into(),
+                value: 31
+            })
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use core::fmt;
+use core::str::FromStr;
+
+use crate::{DecodeError, ParseError};
+
+pub const DEFAULT_PIXEL_FORMAT: PixelFormat = PixelFormat::JPEG;
+
+// supported pixel formats encapsulated in a Rust enum with transforms
+//
+// Note: the wire values follow the `pixformat_t` numbering of the esp32-camera library
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PixelFormat {
+    RGB565 = 0,    // RGB565, 2 bytes per pixel
+    YUV422 = 1,    // YUV422, 2 bytes per pixel
+    GRAYSCALE = 3, // Grayscale, 1 byte per pixel
+    JPEG = 4,      // JPEG, compressed image format
+}
+
+impl PixelFormat {
+    pub const ALL: [PixelFormat; 4] = [
+        PixelFormat::RGB565,
+        PixelFormat::YUV422,
+        PixelFormat::GRAYSCALE,
+        PixelFormat::JPEG,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PixelFormat::RGB565 => "RGB565",
+            PixelFormat::YUV422 => "YUV422",
+            PixelFormat::GRAYSCALE => "GRAYSCALE",
+            PixelFormat::JPEG => "JPEG",
+        }
+    }
+
+    // Bytes used by each pixel in raw formats, None for compressed formats
+    pub fn bytes_per_pixel(&self) -> Option<usize> {
+        match self {
+            PixelFormat::GRAYSCALE => Some(1),
+            PixelFormat::RGB565 | PixelFormat::YUV422 => Some(2),
+            PixelFormat::JPEG => None,
+        }
+    }
+}
+
+// rust enum -> wire value
+impl From<PixelFormat> for u32 {
+    fn from(pixel_format: PixelFormat) -> Self {
+        pixel_format as u32
+    }
+}
+
+// wire value -> rust enum
+impl TryFrom<u32> for PixelFormat {
+    type Error = DecodeError;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(PixelFormat::RGB565),
+            1 => Ok(PixelFormat::YUV422),
+            3 => Ok(PixelFormat::GRAYSCALE),
+            4 => Ok(PixelFormat::JPEG),
+            _ => Err(DecodeError::InvalidPixelFormat(value)),
+        }
+    }
+}
+
+impl Default for PixelFormat {
+    fn default() -> Self {
+        DEFAULT_PIXEL_FORMAT
+    }
+}
+
+// Parse a name, eg. "JPEG" or "rgb565"
+impl FromStr for PixelFormat {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|pixel_format| pixel_format.name().eq_ignore_ascii_case(s.trim()))
+            .ok_or(ParseError::new("pixel format"))
+    }
+}
+
+impl fmt::Display for PixelFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
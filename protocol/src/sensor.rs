@@ -0,0 +1,435 @@
+use core::fmt;
+use core::ops::RangeInclusive;
+
+use crate::DecodeError;
+
+// Image controls exposed by the sensor, each maps to one of the `sensor_t` setter functions
+//
+// Note: not every sensor implements every control (eg. the OV2640 has no sharpness setting), the
+// device reports an error for those
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SensorControl {
+    Brightness = 1,       // -2 to 2
+    Contrast = 2,         // -2 to 2
+    Saturation = 3,       // -2 to 2
+    Sharpness = 4,        // -2 to 2
+    SpecialEffect = 5,    // see SpecialEffect
+    AutoWhiteBalance = 6, // 0 = off, 1 = on
+    AwbGain = 7,          // 0 = off, 1 = on
+    WhiteBalanceMode = 8, // see WhiteBalanceMode, needs AutoWhiteBalance and AwbGain on
+    AutoExposure = 9,     // 0 = off (manual exposure), 1 = on
+    AutoExposureDsp = 10, // 0 = off, 1 = on
+    AeLevel = 11,         // -2 to 2, exposure target when AutoExposure is on
+    Exposure = 12,        // 0 to 1200, manual exposure when AutoExposure is off
+    AutoGain = 13,        // 0 = off (manual gain), 1 = on
+    Gain = 14,            // 0 to 30, manual gain when AutoGain is off
+    GainCeiling = 15,     // 0 to 6 (2x to 128x), gain limit when AutoGain is on
+    LensCorrection = 16,  // 0 = off, 1 = on
+    HMirror = 17,         // 0 = off, 1 = on
+    VFlip = 18,           // 0 = off, 1 = on
+}
+
+impl SensorControl {
+    pub const ALL: [SensorControl; 18] = [
+        SensorControl::Brightness,
+        SensorControl::Contrast,
+        SensorControl::Saturation,
+        SensorControl::Sharpness,
+        SensorControl::SpecialEffect,
+        SensorControl::AutoWhiteBalance,
+        SensorControl::AwbGain,
+        SensorControl::WhiteBalanceMode,
+        SensorControl::AutoExposure,
+        SensorControl::AutoExposureDsp,
+        SensorControl::AeLevel,
+        SensorControl::Exposure,
+        SensorControl::AutoGain,
+        SensorControl::Gain,
+        SensorControl::GainCeiling,
+        SensorControl::LensCorrection,
+        SensorControl::HMirror,
+        SensorControl::VFlip,
+    ];
+
+    // Values accepted by the sensor for this control
+    pub fn range(&self) -> RangeInclusive<i32> {
+        match self {
+            SensorControl::Brightness
+            | SensorControl::Contrast
+            | SensorControl::Saturation
+            | SensorControl::Sharpness
+            | SensorControl::AeLevel => -2..=2,
+            SensorControl::SpecialEffect => 0..=6,
+            SensorControl::WhiteBalanceMode => 0..=4,
+            SensorControl::Exposure => 0..=1200,
+            SensorControl::Gain => 0..=30,
+            SensorControl::GainCeiling => 0..=6,
+            SensorControl::AutoWhiteBalance
+            | SensorControl::AwbGain
+            | SensorControl::AutoExposure
+            | SensorControl::AutoExposureDsp
+            | SensorControl::AutoGain
+            | SensorControl::LensCorrection
+            | SensorControl::HMirror
+            | SensorControl::VFlip => 0..=1,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SensorControl::Brightness => "brightness",
+            SensorControl::Contrast => "contrast",
+            SensorControl::Saturation => "saturation",
+            SensorControl::Sharpness => "sharpness",
+            SensorControl::SpecialEffect => "special_effect",
+            SensorControl::AutoWhiteBalance => "awb",
+            SensorControl::AwbGain => "awb_gain",
+            SensorControl::WhiteBalanceMode => "wb_mode",
+            SensorControl::AutoExposure => "aec",
+            SensorControl::AutoExposureDsp => "aec_dsp",
+            SensorControl::AeLevel => "ae_level",
+            SensorControl::Exposure => "exposure",
+            SensorControl::AutoGain => "agc",
+            SensorControl::Gain => "gain",
+            SensorControl::GainCeiling => "gain_ceiling",
+            SensorControl::LensCorrection => "lens_correction",
+            SensorControl::HMirror => "hmirror",
+            SensorControl::VFlip => "vflip",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|control| control.name() == name)
+    }
+
+    // Pair the control with a value, None when the value is out of range
+    pub fn value(self, value: i32) -> Option<ControlValue> {
+        self.range().contains(&value).then_some(ControlValue {
+            control: self,
+            value,
+        })
+    }
+}
+
+// rust enum -> wire value
+impl From<SensorControl> for u8 {
+    fn from(control: SensorControl) -> Self {
+        control as u8
+    }
+}
+
+// wire value -> rust enum
+impl TryFrom<u8> for SensorControl {
+    type Error = DecodeError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Self::ALL
+            .into_iter()
+            .find(|control| *control as u8 == value)
+            .ok_or(DecodeError::InvalidSensorControl(value))
+    }
+}
+
+impl fmt::Display for SensorControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// A control with a value inside of its range
+//
+// Layout (big-endian): control (1) | value (4, signed)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ControlValue {
+    control: SensorControl,
+    value: i32,
+}
+
+impl ControlValue {
+    pub const LEN: usize = 5;
+
+    pub fn control(&self) -> SensorControl {
+        self.control
+    }
+
+    pub fn value(&self) -> i32 {
+        self.value
+    }
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        let mut buf = [0; Self::LEN];
+        buf[0] = self.control.into();
+        buf[1..5].copy_from_slice(&self.value.to_be_bytes());
+        buf
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        if bytes.len() < Self::LEN {
+            return Err(DecodeError::InvalidPayloadLength {
+                expected: Self::LEN,
+                actual: bytes.len(),
+            });
+        }
+
+        let control = SensorControl::try_from(bytes[0])?;
+        let value = i32::from_be_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]);
+        control
+            .value(value)
+            .ok_or(DecodeError::InvalidControlValue {
+                control: bytes[0],
+                value,
+            })
+    }
+}
+
+impl fmt::Display for ControlValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.control, self.value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SpecialEffect {
+    #[default]
+    NoEffect = 0,
+    Negative = 1,
+    Grayscale = 2,
+    RedTint = 3,
+    GreenTint = 4,
+    BlueTint = 5,
+    Sepia = 6,
+}
+
+impl SpecialEffect {
+    fn from_value(value: i32) -> Self {
+        match value {
+            1 => SpecialEffect::Negative,
+            2 => SpecialEffect::Grayscale,
+            3 => SpecialEffect::RedTint,
+            4 => SpecialEffect::GreenTint,
+            5 => SpecialEffect::BlueTint,
+            6 => SpecialEffect::Sepia,
+            _ => SpecialEffect::NoEffect,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum WhiteBalanceMode {
+    #[default]
+    Auto = 0,
+    Sunny = 1,
+    Cloudy = 2,
+    Office = 3,
+    Home = 4,
+}
+
+impl WhiteBalanceMode {
+    fn from_value(value: i32) -> Self {
+        match value {
+            1 => WhiteBalanceMode::Sunny,
+            2 => WhiteBalanceMode::Cloudy,
+            3 => WhiteBalanceMode::Office,
+            4 => WhiteBalanceMode::Home,
+            _ => WhiteBalanceMode::Auto,
+        }
+    }
+}
+
+// Current value of every sensor control, defaults match the esp32-camera sensor init
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SensorSettings {
+    pub brightness: i8,
+    pub contrast: i8,
+    pub saturation: i8,
+    pub sharpness: i8,
+    pub special_effect: SpecialEffect,
+    pub auto_white_balance: bool,
+    pub awb_gain: bool,
+    pub white_balance_mode: WhiteBalanceMode,
+    pub auto_exposure: bool,
+    pub auto_exposure_dsp: bool,
+    pub ae_level: i8,
+    pub exposure: u16,
+    pub auto_gain: bool,
+    pub gain: u8,
+    pub gain_ceiling: u8,
+    pub lens_correction: bool,
+    pub hmirror: bool,
+    pub vflip: bool,
+}
+
+impl SensorSettings {
+    // Note: values are in range by construction of ControlValue, so the casts can not truncate
+    pub fn set(&mut self, control_value: ControlValue) {
+        let value = control_value.value();
+        match control_value.control() {
+            SensorControl::Brightness => self.brightness = value as i8,
+            SensorControl::Contrast => self.contrast = value as i8,
+            SensorControl::Saturation => self.saturation = value as i8,
+            SensorControl::Sharpness => self.sharpness = value as i8,
+            SensorControl::SpecialEffect => self.special_effect = SpecialEffect::from_value(value),
+            SensorControl::AutoWhiteBalance => self.auto_white_balance = value != 0,
+            SensorControl::AwbGain => self.awb_gain = value != 0,
+            SensorControl::WhiteBalanceMode => {
+                self.white_balance_mode = WhiteBalanceMode::from_value(value)
+            }
+            SensorControl::AutoExposure => self.auto_exposure = value != 0,
+            SensorControl::AutoExposureDsp => self.auto_exposure_dsp = value != 0,
+            SensorControl::AeLevel => self.ae_level = value as i8,
+            SensorControl::Exposure => self.exposure = value as u16,
+            SensorControl::AutoGain => self.auto_gain = value != 0,
+            SensorControl::Gain => self.gain = value as u8,
+            SensorControl::GainCeiling => self.gain_ceiling = value as u8,
+            SensorControl::LensCorrection => self.lens_correction = value != 0,
+            SensorControl::HMirror => self.hmirror = value != 0,
+            SensorControl::VFlip => self.vflip = value != 0,
+        }
+    }
+
+    pub fn get(&self, control: SensorControl) -> i32 {
+        match control {
+            SensorControl::Brightness => self.brightness as i32,
+            SensorControl::Contrast => self.contrast as i32,
+            SensorControl::Saturation => self.saturation as i32,
+            SensorControl::Sharpness => self.sharpness as i32,
+            SensorControl::SpecialEffect => self.special_effect as i32,
+            SensorControl::AutoWhiteBalance => self.auto_white_balance as i32,
+            SensorControl::AwbGain => self.awb_gain as i32,
+            SensorControl::WhiteBalanceMode => self.white_balance_mode as i32,
+            SensorControl::AutoExposure => self.auto_exposure as i32,
+            SensorControl::AutoExposureDsp => self.auto_exposure_dsp as i32,
+            SensorControl::AeLevel => self.ae_level as i32,
+            SensorControl::Exposure => self.exposure as i32,
+            SensorControl::AutoGain => self.auto_gain as i32,
+            SensorControl::Gain => self.gain as i32,
+            SensorControl::GainCeiling => self.gain_ceiling as i32,
+            SensorControl::LensCorrection => self.lens_correction as i32,
+            SensorControl::HMirror => self.hmirror as i32,
+            SensorControl::VFlip => self.vflip as i32,
+        }
+    }
+
+    // Every control with its current value, eg. for re-applying the settings to a sensor
+    pub fn values(&self) -> impl Iterator<Item = ControlValue> + '_ {
+        SensorControl::ALL.into_iter().map(|control| ControlValue {
+            control,
+            value: self.get(control),
+        })
+    }
+}
+
+impl Default for SensorSettings {
+    fn default() -> Self {
+        SensorSettings {
+            brightness: 0,
+            contrast: 0,
+            saturation: 0,
+            sharpness: 0,
+            special_effect: SpecialEffect::NoEffect,
+            auto_white_balance: true,
+            awb_gain: true,
+            white_balance_mode: WhiteBalanceMode::Auto,
+            auto_exposure: true,
+            auto_exposure_dsp: false,
+            ae_level: 0,
+            exposure: 300,
+            auto_gain: true,
+            gain: 0,
+            gain_ceiling: 0,
+            lens_correction: true,
+            hmirror: false,
+            vflip: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control_value_round_trip() {
+        for control in SensorControl::ALL {
+            for value in [*control.range().start(), *control.range().end()] {
+                let control_value = control.value(value).unwrap();
+                assert_eq!(
+                    ControlValue::decode(&control_value.encode()),
+                    Ok(control_value)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn out_of_range_value_is_rejected() {
+        for control in SensorControl::ALL {
+            let value = control.range().end() + 1;
+            assert_eq!(control.value(value), None);
+            let mut bytes = [0; ControlValue::LEN];
+            bytes[0] = control.into();
+            bytes[1..].copy_from_slice(&value.to_be_bytes());
+            assert_eq!(
+                ControlValue::decode(&bytes),
+                Err(DecodeError::InvalidControlValue {
+                    control: control.into(),
+                    value
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_control_is_rejected() {
+        assert_eq!(
+            ControlValue::decode(&[0, 0, 0, 0, 0]),
+            Err(DecodeError::InvalidSensorControl(0))
+        );
+        assert_eq!(
+            ControlValue::decode(&[1, 0, 0]),
+            Err(DecodeError::InvalidPayloadLength {
+                expected: ControlValue::LEN,
+                actual: 3
+            })
+        );
+    }
+
+    #[test]
+    fn control_names_round_trip() {
+        for control in SensorControl::ALL {
+            assert_eq!(SensorControl::from_name(control.name()), Some(control));
+            assert_eq!(SensorControl::try_from(u8::from(control)), Ok(control));
+        }
+        assert_eq!(SensorControl::from_name("zoom"), None);
+    }
+
+    #[test]
+    fn settings_set_and_get_every_control() {
+        for control in SensorControl::ALL {
+            for value in control.range() {
+                let mut settings = SensorSettings::default();
+                settings.set(control.value(value).unwrap());
+                assert_eq!(settings.get(control), value, "{}", control);
+            }
+        }
+    }
+
+    #[test]
+    fn settings_values_round_trip() {
+        let mut settings = SensorSettings::default();
+        settings.set(SensorControl::SpecialEffect.value(6).unwrap());
+        settings.set(SensorControl::WhiteBalanceMode.value(3).unwrap());
+        settings.set(SensorControl::AeLevel.value(-1).unwrap());
+        settings.set(SensorControl::VFlip.value(1).unwrap());
+        assert_eq!(settings.special_effect, SpecialEffect::Sepia);
+        assert_eq!(settings.white_balance_mode, WhiteBalanceMode::Office);
+
+        let mut copy = SensorSettings::default();
+        for control_value in settings.values() {
+            copy.set(control_value);
+        }
+        assert_eq!(copy, settings);
+        assert_eq!(settings.values().count(), SensorControl::ALL.len());
+    }
+}
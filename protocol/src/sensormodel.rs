@@ -0,0 +1,96 @@
+use core::fmt;
+use core::str::FromStr;
+
+use crate::{FrameSize, ParseError};
+
+// Camera sensors supported by the esp32-camera library, identified by their product id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SensorModel {
+    OV2640,
+    OV3660,
+    OV5640,
+    OV7670,
+    OV7725,
+    Unknown(u16),
+}
+
+impl SensorModel {
+    // Sensors with known product ids
+    pub const KNOWN: [SensorModel; 5] = [
+        SensorModel::OV2640,
+        SensorModel::OV3660,
+        SensorModel::OV5640,
+        SensorModel::OV7670,
+        SensorModel::OV7725,
+    ];
+
+    pub fn from_pid(pid: u16) -> Self {
+        match pid {
+            0x26 => SensorModel::OV2640,
+            0x3660 => SensorModel::OV3660,
+            0x5640 => SensorModel::OV5640,
+            0x76 => SensorModel::OV7670,
+            0x77 => SensorModel::OV7725,
+            pid => SensorModel::Unknown(pid),
+        }
+    }
+
+    pub fn pid(&self) -> u16 {
+        match self {
+            SensorModel::OV2640 => 0x26,
+            SensorModel::OV3660 => 0x3660,
+            SensorModel::OV5640 => 0x5640,
+            SensorModel::OV7670 => 0x76,
+            SensorModel::OV7725 => 0x77,
+            SensorModel::Unknown(pid) => *pid,
+        }
+    }
+
+    // Name of the sensor, None for sensors this version does not know
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            SensorModel::OV2640 => Some("OV2640"),
+            SensorModel::OV3660 => Some("OV3660"),
+            SensorModel::OV5640 => Some("OV5640"),
+            SensorModel::OV7670 => Some("OV7670"),
+            SensorModel::OV7725 => Some("OV7725"),
+            SensorModel::Unknown(_) => None,
+        }
+    }
+
+    // Largest frame size the sensor can produce, None for sensors this version does not know
+    pub fn max_frame_size(&self) -> Option<FrameSize> {
+        match self {
+            SensorModel::OV2640 => Some(FrameSize::UXGA),
+            SensorModel::OV3660 => Some(FrameSize::QXGA),
+            SensorModel::OV5640 => Some(FrameSize::QSXGA),
+            SensorModel::OV7670 | SensorModel::OV7725 => Some(FrameSize::VGA),
+            SensorModel::Unknown(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for SensorModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "unknown sensor ({:#06x})", self.pid()),
+        }
+    }
+}
+
+// Parse a known sensor name, eg. "OV2640" or "ov2640"
+impl FromStr for SensorModel {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::KNOWN
+            .into_iter()
+            .find(|model| {
+                model
+                    .name()
+                    .is_some_and(|name| name.eq_ignore_ascii_case(s.trim()))
+            })
+            .ok_or(ParseError::new("sensor model"))
+    }
+}
@@ -0,0 +1,295 @@
+use chacha20poly1305::aead::AeadInPlace;
+use chacha20poly1305::{ChaCha20Poly1305, Nonce as AeadNonce, Tag};
+
+use crate::SessionError;
+
+// Once the handshake is done, frames are sent in encrypted records:
+//
+// | length of the rest of the record (4) | ciphertext (up to MAX_RECORD_LEN) | tag (16) |
+//
+// Each direction has its own key and counts its records, the count is the nonce so a record that
+// is replayed, reordered or dropped fails to decrypt
+pub const MAX_RECORD_LEN: usize = 16 * 1024;
+pub const TAG_LEN: usize = 16;
+#[cfg(feature = "std")]
+const LEN_LEN: usize = 4;
+
+// Encrypts or decrypts the records of one direction of a session
+pub struct RecordCipher {
+    aead: ChaCha20Poly1305,
+    sequence: u64,
+}
+
+impl RecordCipher {
+    pub(crate) fn new(aead: ChaCha20Poly1305) -> Self {
+        RecordCipher { aead, sequence: 0 }
+    }
+
+    // Encrypt the next record in place, returning its tag
+    pub fn seal(&mut self, data: &mut [u8]) -> [u8; TAG_LEN] {
+        let nonce = self.next_nonce();
+        self.aead
+            .encrypt_in_place_detached(&nonce, &[], data)
+            .expect("records are shorter than ChaCha20Poly1305 allows")
+            .into()
+    }
+
+    // Decrypt the next record in place
+    pub fn open(&mut self, data: &mut [u8], tag: &[u8; TAG_LEN]) -> Result<(), SessionError> {
+        let nonce = self.next_nonce();
+        self.aead
+            .decrypt_in_place_detached(&nonce, &[], data, Tag::from_slice(tag))
+            .map_err(|_| SessionError::BadRecord)
+    }
+
+    // Note: a session would have to send 2^64 records for the count to repeat
+    fn next_nonce(&mut self) -> AeadNonce {
+        let mut nonce = AeadNonce::default();
+        nonce[4..].copy_from_slice(&self.sequence.to_be_bytes());
+        self.sequence += 1;
+        nonce
+    }
+}
+
+// Stream of an authenticated session, frames are read from and written to it as from the
+// underlying stream (eg. TcpStream)
+//
+// Writes are collected into records of up to MAX_RECORD_LEN, which are sent when full and on flush
+#[cfg(feature = "std")]
+pub struct SecureStream<S> {
+    stream: S,
+    send: RecordCipher,
+    receive: RecordCipher,
+    // Decrypted data of the last record received, read up to `read_pos`
+    read_buf: alloc::vec::Vec<u8>,
+    read_pos: usize,
+    // Length placeholder followed by the data of the next record
+    write_buf: alloc::vec::Vec<u8>,
+}
+
+#[cfg(feature = "std")]
+impl<S> SecureStream<S> {
+    // Wrap a stream with the ciphers from `Handshake::ciphers`
+    pub fn new(stream: S, (send, receive): (RecordCipher, RecordCipher)) -> Self {
+        SecureStream {
+            stream,
+            send,
+            receive,
+            read_buf: alloc::vec::Vec::new(),
+            read_pos: 0,
+            write_buf: alloc::vec![0; LEN_LEN],
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S: std::io::Read> SecureStream<S> {
+    fn read_record(&mut self) -> std::io::Result<()> {
+        self.read_buf.clear();
+        self.read_pos = 0;
+
+        let mut len_bytes = [0; LEN_LEN];
+        self.stream.read_exact(&mut len_bytes)?;
+        let len = u32::from_be_bytes(len_bytes);
+        if len as usize > MAX_RECORD_LEN + TAG_LEN {
+            return Err(SessionError::RecordTooLarge(len).into());
+        }
+        let Some(data_len) = (len as usize).checked_sub(TAG_LEN) else {
+            return Err(SessionError::BadRecord.into());
+        };
+
+        let mut record = alloc::vec![0; len as usize];
+        self.stream.read_exact(&mut record)?;
+        let (data, tag) = record.split_at_mut(data_len);
+        let tag: &[u8; TAG_LEN] = (&*tag).try_into().expect("tag is TAG_LEN bytes");
+        self.receive.open(data, tag)?;
+        record.truncate(data_len);
+        self.read_buf = record;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S: std::io::Read> std::io::Read for SecureStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // Note: empty records carry nothing to read, the next one is waited for
+        while self.read_pos == self.read_buf.len() {
+            self.read_record()?;
+        }
+        let len = buf.len().min(self.read_buf.len() - self.read_pos);
+        buf[..len].copy_from_slice(&self.read_buf[self.read_pos..self.read_pos + len]);
+        self.read_pos += len;
+        Ok(len)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S: std::io::Write> SecureStream<S> {
+    fn send_record(&mut self) -> std::io::Result<()> {
+        let len = (self.write_buf.len() - LEN_LEN + TAG_LEN) as u32;
+        self.write_buf[..LEN_LEN].copy_from_slice(&len.to_be_bytes());
+        let tag = self.send.seal(&mut self.write_buf[LEN_LEN..]);
+        self.write_buf.extend_from_slice(&tag);
+        let written = self.stream.write_all(&self.write_buf);
+        self.write_buf.truncate(LEN_LEN);
+        written
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S: std::io::Write> std::io::Write for SecureStream<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.write_buf.len() == LEN_LEN + MAX_RECORD_LEN {
+            self.send_record()?;
+        }
+        let len = buf
+            .len()
+            .min(LEN_LEN + MAX_RECORD_LEN - self.write_buf.len());
+        self.write_buf.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.write_buf.len() > LEN_LEN {
+            self.send_record()?;
+        }
+        self.stream.flush()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::io::{Read, Write};
+
+    use super::*;
+    use crate::{Frame, Handshake, PresharedKey, Role};
+
+    fn handshake() -> Handshake {
+        let key = PresharedKey::new(&[7; 32]).unwrap();
+        Handshake::new(&key, &[1; 16], &[2; 16])
+    }
+
+    // Records the controller wrote, to be read by the device
+    fn send(data: &[&[u8]]) -> Vec<u8> {
+        let mut controller = SecureStream::new(Vec::new(), handshake().ciphers(Role::Controller));
+        for data in data {
+            controller.write_all(data).unwrap();
+            controller.flush().unwrap();
+        }
+        controller.get_ref().clone()
+    }
+
+    fn device(records: Vec<u8>) -> SecureStream<std::io::Cursor<Vec<u8>>> {
+        SecureStream::new(
+            std::io::Cursor::new(records),
+            handshake().ciphers(Role::Device),
+        )
+    }
+
+    fn session_error(err: std::io::Error) -> SessionError {
+        *err.into_inner()
+            .expect("session errors carry their cause")
+            .downcast::<SessionError>()
+            .expect("error is a session error")
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let frames = [
+            Frame::new(1, 1, Vec::new()),
+            Frame::new(2, 2, vec![0xab; 3 * MAX_RECORD_LEN + 5]),
+        ];
+        let mut controller = SecureStream::new(Vec::new(), handshake().ciphers(Role::Controller));
+        for frame in &frames {
+            frame.write_to(&mut controller).unwrap();
+        }
+
+        let mut device = device(controller.get_ref().clone());
+        for frame in &frames {
+            assert_eq!(&Frame::read_from(&mut device).unwrap(), frame);
+        }
+    }
+
+    #[test]
+    fn records_are_encrypted() {
+        let records = send(&[b"secret capture"]);
+        assert_eq!(records.len(), LEN_LEN + 14 + TAG_LEN);
+        assert!(!records.windows(6).any(|window| window == b"secret"));
+    }
+
+    #[test]
+    fn tampered_record_is_rejected() {
+        let mut records = send(&[b"set frame size"]);
+        records[LEN_LEN] ^= 1;
+        let err = device(records).read(&mut [0; 32]).unwrap_err();
+        assert_eq!(session_error(err), SessionError::BadRecord);
+    }
+
+    #[test]
+    fn tampered_tag_is_rejected() {
+        let mut records = send(&[b"set frame size"]);
+        let last = records.len() - 1;
+        records[last] ^= 1;
+        let err = device(records).read(&mut [0; 32]).unwrap_err();
+        assert_eq!(session_error(err), SessionError::BadRecord);
+    }
+
+    #[test]
+    fn replayed_record_is_rejected() {
+        let records = send(&[b"factory reset"]);
+        let mut replayed = records.clone();
+        replayed.extend_from_slice(&records);
+
+        let mut device = device(replayed);
+        let mut buf = [0; 13];
+        device.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"factory reset");
+        let err = device.read(&mut buf).unwrap_err();
+        assert_eq!(session_error(err), SessionError::BadRecord);
+    }
+
+    #[test]
+    fn reordered_records_are_rejected() {
+        let first = send(&[b"first"]);
+        let both = send(&[b"first", b"second"]);
+        let mut reordered = both[first.len()..].to_vec();
+        reordered.extend_from_slice(&first);
+        let err = device(reordered).read(&mut [0; 8]).unwrap_err();
+        assert_eq!(session_error(err), SessionError::BadRecord);
+    }
+
+    #[test]
+    fn record_of_the_other_direction_is_rejected() {
+        let records = send(&[b"ping"]);
+        let mut controller = SecureStream::new(
+            std::io::Cursor::new(records),
+            handshake().ciphers(Role::Controller),
+        );
+        let err = controller.read(&mut [0; 4]).unwrap_err();
+        assert_eq!(session_error(err), SessionError::BadRecord);
+    }
+
+    #[test]
+    fn oversized_record_is_rejected() {
+        let len = (MAX_RECORD_LEN + TAG_LEN + 1) as u32;
+        let err = device(len.to_be_bytes().to_vec())
+            .read(&mut [0; 4])
+            .unwrap_err();
+        assert_eq!(session_error(err), SessionError::RecordTooLarge(len));
+    }
+
+    #[test]
+    fn record_shorter_than_tag_is_rejected() {
+        let mut records = ((TAG_LEN - 1) as u32).to_be_bytes().to_vec();
+        records.extend_from_slice(&[0; TAG_LEN - 1]);
+        let err = device(records).read(&mut [0; 4]).unwrap_err();
+        assert_eq!(session_error(err), SessionError::BadRecord);
+    }
+}
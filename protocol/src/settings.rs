@@ -0,0 +1,136 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::codec::{Reader, Writer};
+use crate::{DecodeError, ParseError};
+
+// Port devices listen on unless their settings say otherwise
+pub const DEFAULT_PORT: u16 = 8080;
+
+// How the device presents itself and whether it keeps camera settings changed over the protocol,
+// stored on the device and applied when it restarts
+//
+// Layout: name length (1) | name (ASCII) | port (2) | persist camera settings (1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceSettings {
+    name: String,
+    port: u16,
+    persist_camera: bool,
+}
+
+impl DeviceSettings {
+    // Names are mDNS host names, one DNS label
+    pub const MAX_NAME_LEN: usize = 32;
+
+    // An empty name keeps the firmware's default (eg. espcam-a1b2c3)
+    pub fn new(name: &str, port: u16, persist_camera: bool) -> Result<Self, ParseError> {
+        let name_valid = name.len() <= Self::MAX_NAME_LEN
+            && name
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+            && !name.starts_with('-')
+            && !name.ends_with('-');
+        if !name_valid {
+            return Err(ParseError::new(
+                "device name, use up to 32 lowercase letters, digits and '-'",
+            ));
+        }
+        if port == 0 {
+            return Err(ParseError::new("port"));
+        }
+        Ok(DeviceSettings {
+            name: name.to_string(),
+            port,
+            persist_camera,
+        })
+    }
+
+    // Empty for the firmware's default
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    // Whether frame size, pixel format, JPEG quality and sensor controls set over the protocol
+    // are kept across restarts
+    pub fn persist_camera(&self) -> bool {
+        self.persist_camera
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.str(&self.name);
+        writer.u16(self.port);
+        writer.bool(self.persist_camera);
+        writer.finish()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(bytes);
+        let name = reader.str()?;
+        let port = reader.u16()?;
+        let persist_camera = reader.bool()?;
+        reader.finish()?;
+        DeviceSettings::new(&name, port, persist_camera)
+            .map_err(|_| DecodeError::InvalidDeviceSettings)
+    }
+}
+
+impl Default for DeviceSettings {
+    fn default() -> Self {
+        DeviceSettings {
+            name: String::new(),
+            port: DEFAULT_PORT,
+            persist_camera: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for settings in [
+            DeviceSettings::default(),
+            DeviceSettings::new("front-door", 9000, false).unwrap(),
+            DeviceSettings::new(&"a".repeat(DeviceSettings::MAX_NAME_LEN), 1, true).unwrap(),
+        ] {
+            assert_eq!(DeviceSettings::decode(&settings.encode()), Ok(settings));
+        }
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        assert!(DeviceSettings::new("Front-Door", 8080, true).is_err());
+        assert!(DeviceSettings::new("front door", 8080, true).is_err());
+        assert!(DeviceSettings::new("-front", 8080, true).is_err());
+        assert!(DeviceSettings::new("front-", 8080, true).is_err());
+        assert!(DeviceSettings::new(&"a".repeat(33), 8080, true).is_err());
+        assert!(DeviceSettings::new("front", 0, true).is_err());
+
+        // Note: a name with a capital letter and port 0
+        assert_eq!(
+            DeviceSettings::decode(&[1, b'A', 0x1f, 0x90, 1]),
+            Err(DecodeError::InvalidDeviceSettings)
+        );
+        assert_eq!(
+            DeviceSettings::decode(&[1, b'a', 0, 0, 1]),
+            Err(DecodeError::InvalidDeviceSettings)
+        );
+        assert!(DeviceSettings::decode(&[1, b'a', 0x1f, 0x90]).is_err());
+        assert!(DeviceSettings::decode(&[1, b'a', 0x1f, 0x90, 1, 0]).is_err());
+    }
+
+    #[test]
+    fn default_keeps_firmware_name() {
+        let settings = DeviceSettings::default();
+        assert_eq!(settings.name(), "");
+        assert_eq!(settings.port(), DEFAULT_PORT);
+        assert!(settings.persist_camera());
+    }
+}
@@ -0,0 +1,304 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::net::Ipv4Addr;
+
+use crate::codec::{Reader, Writer};
+use crate::{
+    ControlValue, DecodeError, FrameSize, JpegQuality, PixelFormat, SensorModel, SensorSettings,
+};
+
+// Why the device last restarted, values follow `esp_reset_reason_t`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResetReason {
+    Unknown = 0,
+    PowerOn = 1,
+    External = 2,
+    Software = 3,
+    Panic = 4,
+    InterruptWatchdog = 5,
+    TaskWatchdog = 6,
+    Watchdog = 7,
+    DeepSleep = 8,
+    Brownout = 9,
+    Sdio = 10,
+}
+
+impl From<u8> for ResetReason {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => ResetReason::PowerOn,
+            2 => ResetReason::External,
+            3 => ResetReason::Software,
+            4 => ResetReason::Panic,
+            5 => ResetReason::InterruptWatchdog,
+            6 => ResetReason::TaskWatchdog,
+            7 => ResetReason::Watchdog,
+            8 => ResetReason::DeepSleep,
+            9 => ResetReason::Brownout,
+            10 => ResetReason::Sdio,
+            _ => ResetReason::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for ResetReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+// Camera sensor detected by the driver
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SensorInfo {
+    pub pid: u16,
+    pub name: String,
+    pub max_frame_size: FrameSize,
+}
+
+impl SensorInfo {
+    pub fn model(&self) -> SensorModel {
+        SensorModel::from_pid(self.pid)
+    }
+
+    // Note: compares dimensions rather than pixel counts, eg. an OV2640 (max UXGA, 1600 x 1200)
+    // can not produce P_HD (720 x 1280) despite it having fewer pixels
+    pub fn supports(&self, frame_size: FrameSize) -> bool {
+        let (width, height) = self.max_frame_size.dimensions();
+        frame_size.fits_within(width, height)
+    }
+}
+
+// Current camera configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CameraStatus {
+    pub sensor: SensorInfo,
+    pub frame_size: FrameSize,
+    pub pixel_format: PixelFormat,
+    pub jpeg_quality: JpegQuality,
+    pub sensor_settings: SensorSettings,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WifiStatus {
+    pub rssi: i8,
+    pub ip: Ipv4Addr,
+}
+
+// Response to `IncomingPacket::GetStatus`
+//
+// Layout (big-endian, strings are a length byte followed by UTF-8):
+//
+// | firmware version (str) | uptime ms (8) | free heap (4) | free PSRAM (4) | reset reason (1) |
+// | wifi connected (1) | [ rssi (1, signed) | ip (4) ] |
+// | camera initialized (1) | [ sensor pid (2) | sensor name (str) | max frame size (4) |
+//   frame size (4) | pixel format (4) | jpeg quality (1) | control count (1) |
+//   controls (5 each, see ControlValue) ] |
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceStatus {
+    pub firmware_version: String,
+    pub uptime_ms: u64,
+    pub free_heap: u32,
+    pub free_psram: u32,
+    pub reset_reason: ResetReason,
+    // None while not connected to an access point
+    pub wifi: Option<WifiStatus>,
+    // None when the camera failed to initialize
+    pub camera: Option<CameraStatus>,
+}
+
+impl DeviceStatus {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.str(&self.firmware_version);
+        writer.u64(self.uptime_ms);
+        writer.u32(self.free_heap);
+        writer.u32(self.free_psram);
+        writer.u8(self.reset_reason as u8);
+
+        writer.bool(self.wifi.is_some());
+        if let Some(wifi) = &self.wifi {
+            writer.u8(wifi.rssi as u8);
+            writer.bytes(&wifi.ip.octets());
+        }
+
+        writer.bool(self.camera.is_some());
+        if let Some(camera) = &self.camera {
+            writer.u16(camera.sensor.pid);
+            writer.str(&camera.sensor.name);
+            writer.u32(camera.sensor.max_frame_size.into());
+            writer.u32(camera.frame_size.into());
+            writer.u32(camera.pixel_format.into());
+            writer.u8(camera.jpeg_quality.get());
+
+            let controls: Vec<ControlValue> = camera.sensor_settings.values().collect();
+            writer.u8(controls.len() as u8);
+            for control_value in controls {
+                writer.bytes(&control_value.encode());
+            }
+        }
+
+        writer.finish()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(bytes);
+        let firmware_version = reader.str()?;
+        let uptime_ms = reader.u64()?;
+        let free_heap = reader.u32()?;
+        let free_psram = reader.u32()?;
+        let reset_reason = ResetReason::from(reader.u8()?);
+
+        let wifi = match reader.bool()? {
+            true => Some(WifiStatus {
+                rssi: reader.u8()? as i8,
+                ip: Ipv4Addr::from(reader.array::<4>()?),
+            }),
+            false => None,
+        };
+
+        let camera = match reader.bool()? {
+            true => {
+                let sensor = SensorInfo {
+                    pid: reader.u16()?,
+                    name: reader.str()?,
+                    max_frame_size: reader.u32()?.try_into()?,
+                };
+                let frame_size = reader.u32()?.try_into()?;
+                let pixel_format = reader.u32()?.try_into()?;
+                let jpeg_quality = (reader.u8()? as u32).try_into()?;
+
+                // Note: controls unknown to this version are skipped so newer devices can add them
+                let mut sensor_settings = SensorSettings::default();
+                for _ in 0..reader.u8()? {
+                    if let Ok(control_value) =
+                        ControlValue::decode(reader.bytes(ControlValue::LEN)?)
+                    {
+                        sensor_settings.set(control_value);
+                    }
+                }
+
+                Some(CameraStatus {
+                    sensor,
+                    frame_size,
+                    pixel_format,
+                    jpeg_quality,
+                    sensor_settings,
+                })
+            }
+            false => None,
+        };
+
+        reader.finish()?;
+        Ok(DeviceStatus {
+            firmware_version,
+            uptime_ms,
+            free_heap,
+            free_psram,
+            reset_reason,
+            wifi,
+            camera,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::*;
+    use crate::SensorControl;
+
+    fn status() -> DeviceStatus {
+        let mut sensor_settings = SensorSettings::default();
+        sensor_settings.set(SensorControl::Brightness.value(2).unwrap());
+        sensor_settings.set(SensorControl::Exposure.value(1200).unwrap());
+        DeviceStatus {
+            firmware_version: "0.3.0".to_string(),
+            uptime_ms: 86_400_000,
+            free_heap: 120_000,
+            free_psram: 4_000_000,
+            reset_reason: ResetReason::Brownout,
+            wifi: Some(WifiStatus {
+                rssi: -67,
+                ip: Ipv4Addr::new(192, 168, 1, 40),
+            }),
+            camera: Some(CameraStatus {
+                sensor: SensorInfo {
+                    pid: 0x26,
+                    name: "OV2640".to_string(),
+                    max_frame_size: FrameSize::UXGA,
+                },
+                frame_size: FrameSize::VGA,
+                pixel_format: PixelFormat::JPEG,
+                jpeg_quality: JpegQuality::new(10).unwrap(),
+                sensor_settings,
+            }),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let status = status();
+        assert_eq!(DeviceStatus::decode(&status.encode()), Ok(status));
+    }
+
+    #[test]
+    fn round_trip_without_wifi_and_camera() {
+        let status = DeviceStatus {
+            wifi: None,
+            camera: None,
+            ..status()
+        };
+        assert_eq!(DeviceStatus::decode(&status.encode()), Ok(status));
+    }
+
+    #[test]
+    fn unknown_reset_reason_decodes_as_unknown() {
+        let mut bytes = status().encode();
+        // Note: the reset reason follows the version string, uptime, free heap and free PSRAM
+        bytes[1 + "0.3.0".len() + 8 + 4 + 4] = 200;
+        let status = DeviceStatus::decode(&bytes).unwrap();
+        assert_eq!(status.reset_reason, ResetReason::Unknown);
+    }
+
+    // Note: a newer device may report controls this version does not know
+    #[test]
+    fn unknown_controls_are_skipped() {
+        let status = status();
+        let mut bytes = status.encode();
+        let count = bytes.len() - SensorControl::ALL.len() * ControlValue::LEN - 1;
+        bytes[count] += 1;
+        bytes.extend_from_slice(&[0xee, 0, 0, 0, 1]);
+        assert_eq!(DeviceStatus::decode(&bytes), Ok(status));
+    }
+
+    #[test]
+    fn truncated_is_rejected() {
+        let bytes = status().encode();
+        for len in 0..bytes.len() {
+            assert!(
+                DeviceStatus::decode(&bytes[..len]).is_err(),
+                "decoded {} of {} bytes",
+                len,
+                bytes.len()
+            );
+        }
+    }
+
+    #[test]
+    fn trailing_bytes_are_rejected() {
+        let mut bytes = status().encode();
+        bytes.push(0);
+        assert!(DeviceStatus::decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn supports_compares_dimensions() {
+        let sensor = status().camera.unwrap().sensor;
+        assert!(sensor.supports(FrameSize::UXGA));
+        assert!(sensor.supports(FrameSize::HD));
+        assert!(!sensor.supports(FrameSize::PHD));
+        assert!(!sensor.supports(FrameSize::QXGA));
+    }
+}
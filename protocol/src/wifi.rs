@@ -0,0 +1,371 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::RangeInclusive;
+use core::str::FromStr;
+
+use crate::codec::{Reader, Writer};
+use crate::{DecodeError, ParseError};
+
+// Network the device joins as a station, set over the protocol (eg. while it is being provisioned)
+// and stored on the device
+//
+// Layout: SSID length (1) | SSID (UTF-8) | password length (1) | password (UTF-8)
+#[derive(Clone, PartialEq, Eq)]
+pub struct WifiCredentials {
+    ssid: String,
+    password: String,
+}
+
+impl WifiCredentials {
+    // Limits of 802.11 and WPA2-PSK, in bytes
+    pub const MAX_SSID_LEN: usize = 32;
+    pub const MIN_PASSWORD_LEN: usize = 8;
+    pub const MAX_PASSWORD_LEN: usize = 64;
+
+    // An empty password is for an open network
+    pub fn new(ssid: &str, password: &str) -> Option<Self> {
+        let ssid_valid = !ssid.is_empty() && ssid.len() <= Self::MAX_SSID_LEN;
+        let password_valid = password.is_empty()
+            || (Self::MIN_PASSWORD_LEN..=Self::MAX_PASSWORD_LEN).contains(&password.len());
+        (ssid_valid && password_valid).then(|| WifiCredentials {
+            ssid: ssid.to_string(),
+            password: password.to_string(),
+        })
+    }
+
+    pub fn ssid(&self) -> &str {
+        &self.ssid
+    }
+
+    pub fn password(&self) -> &str {
+        &self.password
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.str(&self.ssid);
+        writer.str(&self.password);
+        writer.finish()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(bytes);
+        let mut read_str = || {
+            let len = reader.u8()? as usize;
+            String::from_utf8(reader.bytes(len)?.to_vec())
+                .map_err(|_| DecodeError::InvalidWifiCredentials)
+        };
+        let ssid = read_str()?;
+        let password = read_str()?;
+        reader.finish()?;
+        WifiCredentials::new(&ssid, &password).ok_or(DecodeError::InvalidWifiCredentials)
+    }
+}
+
+// Note: the password is never printed, eg. when the device logs the packets it receives
+impl fmt::Debug for WifiCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WifiCredentials")
+            .field("ssid", &self.ssid)
+            .field("open", &self.password.is_empty())
+            .finish()
+    }
+}
+
+// When the device runs its own access point
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AccessPointMode {
+    // Never, the device is only reachable on the networks it joins
+    Off = 0,
+    // While no network is known or none of the known ones can be joined, so the device can be
+    // provisioned
+    Provisioning = 1,
+    // Alongside the station, or on its own when the station is off
+    Always = 2,
+}
+
+impl AccessPointMode {
+    pub const ALL: [AccessPointMode; 3] = [
+        AccessPointMode::Off,
+        AccessPointMode::Provisioning,
+        AccessPointMode::Always,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AccessPointMode::Off => "off",
+            AccessPointMode::Provisioning => "provisioning",
+            AccessPointMode::Always => "always",
+        }
+    }
+}
+
+impl TryFrom<u8> for AccessPointMode {
+    type Error = DecodeError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Self::ALL
+            .into_iter()
+            .find(|mode| *mode as u8 == value)
+            .ok_or(DecodeError::InvalidWifiConfig)
+    }
+}
+
+impl FromStr for AccessPointMode {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|mode| mode.name().eq_ignore_ascii_case(s.trim()))
+            .ok_or(ParseError::new(
+                "access point mode, use off, provisioning or always",
+            ))
+    }
+}
+
+impl fmt::Display for AccessPointMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// Radio setup of the device: whether it joins networks as a station and when it runs its access
+// point, which uses WPA2 when it has a password
+//
+// Layout: station (1) | access point mode (1) | channel (1) | SSID length (1) | SSID (UTF-8) |
+// password length (1) | password (UTF-8)
+#[derive(Clone, PartialEq, Eq)]
+pub struct WifiConfig {
+    station: bool,
+    access_point: AccessPointMode,
+    channel: u8,
+    ssid: String,
+    password: String,
+}
+
+impl WifiConfig {
+    pub const CHANNELS: RangeInclusive<u8> = 1..=13;
+
+    // An empty SSID names the access point after the device, an empty password leaves it open
+    //
+    // Note: an access point that is always on needs a password, and a device without station
+    // needs its access point always on to stay reachable
+    pub fn new(
+        station: bool,
+        access_point: AccessPointMode,
+        channel: u8,
+        ssid: &str,
+        password: &str,
+    ) -> Result<Self, ParseError> {
+        if !station && access_point != AccessPointMode::Always {
+            return Err(ParseError::new(
+                "wifi mode, without station the access point has to be always on",
+            ));
+        }
+        if !Self::CHANNELS.contains(&channel) {
+            return Err(ParseError::new("channel, use 1 to 13"));
+        }
+        if ssid.len() > WifiCredentials::MAX_SSID_LEN {
+            return Err(ParseError::new("access point SSID, use up to 32 bytes"));
+        }
+        match password.is_empty() {
+            true if access_point == AccessPointMode::Always => {
+                return Err(ParseError::new(
+                    "access point password, one is needed to keep the access point on",
+                ));
+            }
+            true => {}
+            false => {
+                let len = password.len();
+                if !(WifiCredentials::MIN_PASSWORD_LEN..=WifiCredentials::MAX_PASSWORD_LEN)
+                    .contains(&len)
+                {
+                    return Err(ParseError::new("access point password, use 8 to 64 bytes"));
+                }
+            }
+        }
+        Ok(WifiConfig {
+            station,
+            access_point,
+            channel,
+            ssid: ssid.to_string(),
+            password: password.to_string(),
+        })
+    }
+
+    // Whether the device joins the networks it knows
+    pub fn station(&self) -> bool {
+        self.station
+    }
+
+    pub fn access_point(&self) -> AccessPointMode {
+        self.access_point
+    }
+
+    // Channel of the access point while the station is not connected, once it is the access
+    // point follows the station to the channel of its network
+    pub fn channel(&self) -> u8 {
+        self.channel
+    }
+
+    // Empty for the device's own name
+    pub fn ssid(&self) -> &str {
+        &self.ssid
+    }
+
+    // Empty for an open access point
+    pub fn password(&self) -> &str {
+        &self.password
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.bool(self.station);
+        writer.u8(self.access_point as u8);
+        writer.u8(self.channel);
+        writer.str(&self.ssid);
+        writer.str(&self.password);
+        writer.finish()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(bytes);
+        let station = reader.bool()?;
+        let access_point = AccessPointMode::try_from(reader.u8()?)?;
+        let channel = reader.u8()?;
+        let mut read_str = || {
+            let len = reader.u8()? as usize;
+            String::from_utf8(reader.bytes(len)?.to_vec())
+                .map_err(|_| DecodeError::InvalidWifiConfig)
+        };
+        let ssid = read_str()?;
+        let password = read_str()?;
+        reader.finish()?;
+        WifiConfig::new(station, access_point, channel, &ssid, &password)
+            .map_err(|_| DecodeError::InvalidWifiConfig)
+    }
+}
+
+// Setup of a device that was never configured: it joins the networks it is given and runs an open
+// access point until it has one
+impl Default for WifiConfig {
+    fn default() -> Self {
+        WifiConfig {
+            station: true,
+            access_point: AccessPointMode::Provisioning,
+            channel: 1,
+            ssid: String::new(),
+            password: String::new(),
+        }
+    }
+}
+
+// Note: the password is never printed
+impl fmt::Debug for WifiConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WifiConfig")
+            .field("station", &self.station)
+            .field("access_point", &self.access_point)
+            .field("channel", &self.channel)
+            .field("ssid", &self.ssid)
+            .field("open", &self.password.is_empty())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn credentials_round_trip() {
+        for credentials in [
+            WifiCredentials::new("home", "password").unwrap(),
+            WifiCredentials::new("open network", "").unwrap(),
+            WifiCredentials::new(&"s".repeat(32), &"p".repeat(64)).unwrap(),
+        ] {
+            assert_eq!(
+                WifiCredentials::decode(&credentials.encode()),
+                Ok(credentials)
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_credentials_are_rejected() {
+        assert_eq!(WifiCredentials::new("", "password"), None);
+        assert_eq!(WifiCredentials::new(&"s".repeat(33), ""), None);
+        assert_eq!(WifiCredentials::new("home", "short"), None);
+        assert_eq!(WifiCredentials::new("home", &"p".repeat(65)), None);
+
+        // Note: an SSID and a password too short for WPA2
+        let bytes = [4, b'h', b'o', b'm', b'e', 3, b'a', b'b', b'c'];
+        assert_eq!(
+            WifiCredentials::decode(&bytes),
+            Err(DecodeError::InvalidWifiCredentials)
+        );
+        assert_eq!(
+            WifiCredentials::decode(&[2, 0xff, 0xfe, 0]),
+            Err(DecodeError::InvalidWifiCredentials)
+        );
+        assert!(WifiCredentials::decode(&[4, b'h', b'o']).is_err());
+
+        let mut bytes = WifiCredentials::new("home", "").unwrap().encode();
+        bytes.push(0);
+        assert!(WifiCredentials::decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn config_round_trip() {
+        for config in [
+            WifiConfig::default(),
+            WifiConfig::new(true, AccessPointMode::Off, 1, "", "").unwrap(),
+            WifiConfig::new(true, AccessPointMode::Provisioning, 13, "setup", "").unwrap(),
+            WifiConfig::new(false, AccessPointMode::Always, 6, "garage-cam", "password").unwrap(),
+        ] {
+            assert_eq!(WifiConfig::decode(&config.encode()), Ok(config));
+        }
+    }
+
+    #[test]
+    fn invalid_config_is_rejected() {
+        // Note: without station nor an access point that is always on the device is unreachable
+        assert!(WifiConfig::new(false, AccessPointMode::Provisioning, 1, "", "password").is_err());
+        assert!(WifiConfig::new(true, AccessPointMode::Off, 0, "", "").is_err());
+        assert!(WifiConfig::new(true, AccessPointMode::Off, 14, "", "").is_err());
+        assert!(WifiConfig::new(true, AccessPointMode::Off, 1, &"s".repeat(33), "").is_err());
+        assert!(WifiConfig::new(true, AccessPointMode::Always, 1, "", "").is_err());
+        assert!(WifiConfig::new(true, AccessPointMode::Provisioning, 1, "", "short").is_err());
+
+        // Station off, access point only while provisioning
+        assert_eq!(
+            WifiConfig::decode(&[0, 1, 1, 0, 0]),
+            Err(DecodeError::InvalidWifiConfig)
+        );
+        // Unknown access point mode
+        assert_eq!(
+            WifiConfig::decode(&[1, 3, 1, 0, 0]),
+            Err(DecodeError::InvalidWifiConfig)
+        );
+        // Channel 0
+        assert_eq!(
+            WifiConfig::decode(&[1, 0, 0, 0, 0]),
+            Err(DecodeError::InvalidWifiConfig)
+        );
+        assert!(WifiConfig::decode(&[1, 0, 1, 0]).is_err());
+        assert!(WifiConfig::decode(&[1, 0, 1, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn access_point_mode_names_round_trip() {
+        for mode in AccessPointMode::ALL {
+            assert_eq!(mode.name().parse(), Ok(mode));
+            assert_eq!(AccessPointMode::try_from(mode as u8), Ok(mode));
+        }
+        assert_eq!("ALWAYS".parse(), Ok(AccessPointMode::Always));
+        assert!("sometimes".parse::<AccessPointMode>().is_err());
+    }
+}
@@ -0,0 +1,42 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use mdns_sd::{IfKind, ServiceDaemon, ServiceInfo};
+use protocol::{Advertisement, SERVICE_TYPE};
+
+// Advertise the simulator the way the firmware advertises a device, the service stays up while the daemon lives
+//
+// Note: loopback is included so the controller can find the simulator on the same host
+pub fn advertise(
+    name: &str,
+    ip: Ipv4Addr,
+    port: u16,
+    advertisement: &Advertisement,
+) -> mdns_sd::Result<ServiceDaemon> {
+    let daemon = ServiceDaemon::new()?;
+    daemon.enable_interface(IfKind::LoopbackV4)?;
+
+    let properties = advertisement.txt_records();
+    let properties: Vec<(&str, &str)> = properties
+        .iter()
+        .map(|(key, value)| (*key, value.as_str()))
+        .collect();
+    let ty_domain = format!("{}.local.", SERVICE_TYPE);
+    let host_name = format!("{}.local.", name);
+    // Each interface answers with its own address, unless the simulator only listens on one
+    let service = match ip.is_unspecified() {
+        true => ServiceInfo::new(&ty_domain, name, &host_name, (), port, &properties[..])?
+            .enable_addr_auto(),
+        false => ServiceInfo::new(
+            &ty_domain,
+            name,
+            &host_name,
+            IpAddr::V4(ip),
+            port,
+            &properties[..],
+        )?,
+    };
+
+    daemon.register(service)?;
+    println!("sim: advertising {}.{}", name, SERVICE_TYPE);
+    Ok(daemon)
+}
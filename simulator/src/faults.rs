@@ -0,0 +1,84 @@
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use rand::Rng;
+
+// Failures injected to test how clients cope with an unreliable device
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Faults {
+    // Added before each response
+    pub latency: Duration,
+    // Limits how fast responses are written, None for no limit
+    pub bytes_per_sec: Option<u32>,
+    // Chance for each write to cut the connection off partway
+    pub drop_rate: f64,
+    // Chance for each capture to fail as if no frame buffer was available
+    pub capture_failure_rate: f64,
+}
+
+impl Faults {
+    pub fn capture_fails(&self) -> bool {
+        roll(self.capture_failure_rate)
+    }
+}
+
+// TcpStream that is slow and drops connections according to `Faults`
+pub struct FaultyStream {
+    stream: TcpStream,
+    faults: Faults,
+    // Set once a response has been flushed, the latency is added before the next one starts
+    response_started: bool,
+}
+
+impl FaultyStream {
+    pub fn new(stream: TcpStream, faults: Faults) -> Self {
+        FaultyStream {
+            stream,
+            faults,
+            response_started: false,
+        }
+    }
+}
+
+impl Read for FaultyStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for FaultyStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.response_started {
+            self.response_started = true;
+            thread::sleep(self.faults.latency);
+        }
+        if roll(self.faults.drop_rate) {
+            println!("sim: dropping connection");
+            let _ = self.stream.write(&buf[..buf.len() / 2]);
+            let _ = self.stream.shutdown(Shutdown::Both);
+            return Err(io::ErrorKind::ConnectionAborted.into());
+        }
+
+        let Some(bytes_per_sec) = self.faults.bytes_per_sec else {
+            return self.stream.write(buf);
+        };
+        // Note: writes at most a tenth of a second worth of data at a time
+        let chunk_len = buf.len().min((bytes_per_sec as usize / 10).max(1));
+        let written = self.stream.write(&buf[..chunk_len])?;
+        thread::sleep(Duration::from_secs_f64(
+            written as f64 / bytes_per_sec as f64,
+        ));
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.response_started = false;
+        self.stream.flush()
+    }
+}
+
+fn roll(rate: f64) -> bool {
+    rate > 0.0 && rand::thread_rng().gen_bool(rate.min(1.0))
+}
@@ -0,0 +1,55 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use image::imageops::FilterType;
+
+// JPEG files captured by a real device, served in a loop
+pub struct ImageDirectory {
+    paths: Vec<PathBuf>,
+}
+
+impl ImageDirectory {
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .and_then(|extension| extension.to_str())
+                    .is_some_and(|extension| {
+                        extension.eq_ignore_ascii_case("jpg")
+                            || extension.eq_ignore_ascii_case("jpeg")
+                    })
+            })
+            .collect();
+        if paths.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no .jpg files in {}", dir.display()),
+            ));
+        }
+
+        paths.sort();
+        println!("sim: serving {} images from {}", paths.len(), dir.display());
+        Ok(ImageDirectory { paths })
+    }
+
+    // RGB888 pixels of image `index` (wrapping around), scaled and cropped to `width` x `height`
+    pub fn frame(&self, width: u32, height: u32, index: u32) -> Option<Vec<u8>> {
+        let path = &self.paths[index as usize % self.paths.len()];
+        let image = match image::open(path) {
+            Ok(image) => image,
+            Err(err) => {
+                println!("sim: error reading {}: {}", path.display(), err);
+                return None;
+            }
+        };
+
+        Some(
+            image
+                .resize_to_fill(width, height, FilterType::Triangle)
+                .into_rgb8()
+                .into_raw(),
+        )
+    }
+}
@@ -0,0 +1,172 @@
+// Stands in for an ESP32 on the host, speaking the device protocol on the same port
+//
+// eg. `cargo run -p simulator -- --images captures/` and then `camctl --device 127.0.0.1 capture`, with the same
+// DEVICE_KEY set for both
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use clap::Parser;
+use device_core::{
+    colour_bars, Camera, CameraSensor, CameraSettings, ConfigStore, FrameSource, MockCamera,
+};
+use protocol::{Advertisement, PresharedKey, SensorModel, DEFAULT_PORT};
+
+mod discovery;
+mod faults;
+mod images;
+mod storage;
+mod system;
+
+use faults::Faults;
+use images::ImageDirectory;
+use storage::SimStorage;
+use system::{SimSystem, FIRMWARE_VERSION};
+
+#[derive(Debug, Parser)]
+#[command(about = "Simulated ESP32 camera device")]
+struct Args {
+    #[arg(
+        long,
+        default_value = "0.0.0.0:8080",
+        help = "Address to accept connections on, the device listens on port 8080"
+    )]
+    listen: SocketAddr,
+    #[arg(
+        long,
+        env = "DEVICE_KEY",
+        hide_env_values = true,
+        help = "Key clients have to prove they know, as hex (eg. from `openssl rand -hex 32`)"
+    )]
+    key: PresharedKey,
+    #[arg(
+        long,
+        help = "Serve the .jpg files in this directory instead of colour bars"
+    )]
+    images: Option<PathBuf>,
+    #[arg(
+        long,
+        default_value = "OV2640",
+        help = "Sensor to report, limits the frame sizes that can be set"
+    )]
+    sensor: SensorModel,
+    #[arg(
+        long,
+        help = "Fail camera initialization as if no sensor was connected"
+    )]
+    no_camera: bool,
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Delay added to every response, in milliseconds"
+    )]
+    latency_ms: u64,
+    #[arg(long, help = "Limit responses to this many bytes per second")]
+    bytes_per_sec: Option<u32>,
+    #[arg(
+        long,
+        default_value_t = 0.0,
+        help = "Chance (0 to 1) for each write to drop the connection partway"
+    )]
+    drop_rate: f64,
+    #[arg(
+        long,
+        default_value_t = 0.0,
+        help = "Chance (0 to 1) for each capture to fail"
+    )]
+    capture_failure_rate: f64,
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Advertise the simulator over mDNS under this name, as a device does"
+    )]
+    advertise: Option<String>,
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Keep the device config in this file across runs, instead of only in memory"
+    )]
+    config: Option<PathBuf>,
+}
+
+fn main() -> io::Result<()> {
+    let args = Args::parse();
+    let faults = Faults {
+        latency: Duration::from_millis(args.latency_ms),
+        bytes_per_sec: args.bytes_per_sec,
+        drop_rate: args.drop_rate,
+        capture_failure_rate: args.capture_failure_rate,
+    };
+    let images = args
+        .images
+        .as_deref()
+        .map(ImageDirectory::open)
+        .transpose()?
+        .map(Arc::new);
+
+    let sensor = args.sensor;
+    let no_camera = args.no_camera;
+    let new_camera = move |settings: &CameraSettings| -> Camera<MockCamera> {
+        let mut backend = MockCamera::with_source(sensor, frame_source(images.clone(), faults));
+        backend.set_fail_init(no_camera);
+        let camera_sensor = CameraSensor::new(backend, settings);
+        if let Err(err) = &camera_sensor {
+            println!("error: {}", err);
+        }
+        camera_sensor
+    };
+
+    let config = ConfigStore::open(SimStorage::new(args.config));
+    let settings = config.get().settings;
+    // Note: the simulator listens and advertises as its arguments say, unlike the device
+    if !settings.name().is_empty() || settings.port() != DEFAULT_PORT {
+        println!(
+            "sim: ignoring stored name {:?} and port {}, see --advertise and --listen",
+            settings.name(),
+            settings.port()
+        );
+    }
+
+    let camera = Arc::new(Mutex::new(new_camera(&config.get().camera)));
+    let ip = match args.listen.ip() {
+        IpAddr::V4(ip) if !ip.is_unspecified() => ip,
+        _ => Ipv4Addr::LOCALHOST,
+    };
+    let system = SimSystem::new(camera.clone(), Box::new(new_camera), config, faults, ip);
+
+    let listener = TcpListener::bind(args.listen)?;
+    println!("sim: listening on {}", args.listen);
+
+    let _advertisement = match &args.advertise {
+        Some(name) => {
+            let sensor = (!args.no_camera).then_some(args.sensor);
+            let listen_ip = match args.listen.ip() {
+                IpAddr::V4(ip) => ip,
+                IpAddr::V6(_) => Ipv4Addr::UNSPECIFIED,
+            };
+            let advertisement = Advertisement::new(None, sensor, FIRMWARE_VERSION);
+            Some(
+                discovery::advertise(name, listen_ip, args.listen.port(), &advertisement)
+                    .map_err(io::Error::other)?,
+            )
+        }
+        None => None,
+    };
+    device_core::serve(listener, camera, Arc::new(system), args.key)
+}
+
+// Frames from the image directory or colour bars, failing captures as configured
+fn frame_source(images: Option<Arc<ImageDirectory>>, faults: Faults) -> FrameSource {
+    Box::new(move |width, height, index| {
+        if faults.capture_fails() {
+            println!("sim: failing capture");
+            return None;
+        }
+        match &images {
+            Some(images) => images.frame(width, height, index),
+            None => Some(colour_bars(width, height, index)),
+        }
+    })
+}
@@ -0,0 +1,64 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Mutex, PoisonError};
+
+use device_core::{ConfigStorage, DeviceError};
+
+// Stands in for the device's NVS: the config is kept in a file, or only in memory without one
+pub struct SimStorage {
+    path: Option<PathBuf>,
+    memory: Mutex<Option<Vec<u8>>>,
+}
+
+impl SimStorage {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        SimStorage {
+            path,
+            memory: Mutex::new(None),
+        }
+    }
+}
+
+impl ConfigStorage for SimStorage {
+    fn read(&self) -> Result<Option<Vec<u8>>, DeviceError> {
+        let Some(path) = &self.path else {
+            return Ok(self
+                .memory
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone());
+        };
+        match fs::read(path) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(storage_error(err)),
+        }
+    }
+
+    fn write(&self, bytes: &[u8]) -> Result<(), DeviceError> {
+        let Some(path) = &self.path else {
+            *self.memory.lock().unwrap_or_else(PoisonError::into_inner) = Some(bytes.to_vec());
+            return Ok(());
+        };
+        // Note: written to the side first, a config cut off halfway would be lost
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, bytes).map_err(storage_error)?;
+        fs::rename(&tmp_path, path).map_err(storage_error)
+    }
+
+    fn erase(&self) -> Result<(), DeviceError> {
+        let Some(path) = &self.path else {
+            *self.memory.lock().unwrap_or_else(PoisonError::into_inner) = None;
+            return Ok(());
+        };
+        match fs::remove_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(storage_error(err)),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn storage_error(err: io::Error) -> DeviceError {
+    DeviceError::Storage(err.to_string())
+}
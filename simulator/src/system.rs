@@ -0,0 +1,99 @@
+use std::io;
+use std::net::{Ipv4Addr, TcpStream};
+use std::sync::{Mutex, PoisonError};
+use std::time::Instant;
+
+use device_core::{Camera, CameraSettings, ConfigStore, MockCamera, SharedCamera, System};
+use protocol::{CameraStatus, DeviceStatus, ResetReason, WifiStatus};
+
+use crate::faults::{Faults, FaultyStream};
+use crate::storage::SimStorage;
+
+pub const FIRMWARE_VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), "-sim");
+// Memory an ESP32 with PSRAM typically has left once the firmware is running
+const FREE_HEAP: u32 = 180 * 1024;
+const FREE_PSRAM: u32 = 3 * 1024 * 1024;
+// Signal strength of a station sitting next to its access point
+const RSSI: i8 = -45;
+
+// Creates the camera with the given settings, as the device does when it boots
+pub type NewCamera = Box<dyn Fn(&CameraSettings) -> Camera<MockCamera> + Send + Sync>;
+
+// Stands in for the device, restarting it recreates the camera from the stored settings
+pub struct SimSystem {
+    camera: SharedCamera<MockCamera>,
+    new_camera: NewCamera,
+    config: ConfigStore<SimStorage>,
+    faults: Faults,
+    ip: Ipv4Addr,
+    boot: Mutex<Boot>,
+}
+
+struct Boot {
+    time: Instant,
+    reset_reason: ResetReason,
+}
+
+impl SimSystem {
+    pub fn new(
+        camera: SharedCamera<MockCamera>,
+        new_camera: NewCamera,
+        config: ConfigStore<SimStorage>,
+        faults: Faults,
+        ip: Ipv4Addr,
+    ) -> Self {
+        SimSystem {
+            camera,
+            new_camera,
+            config,
+            faults,
+            ip,
+            boot: Mutex::new(Boot {
+                time: Instant::now(),
+                reset_reason: ResetReason::PowerOn,
+            }),
+        }
+    }
+}
+
+impl System for SimSystem {
+    type Stream = FaultyStream;
+    type Storage = SimStorage;
+
+    // Note: frames are generated on the session thread, which the device leaves to the driver
+    const SESSION_STACK_SIZE: usize = 2 * 1024 * 1024;
+
+    fn device_status(&self, camera: Option<CameraStatus>) -> DeviceStatus {
+        let boot = self.boot.lock().unwrap_or_else(PoisonError::into_inner);
+        DeviceStatus {
+            firmware_version: FIRMWARE_VERSION.into(),
+            uptime_ms: boot.time.elapsed().as_millis() as u64,
+            free_heap: FREE_HEAP,
+            free_psram: FREE_PSRAM,
+            reset_reason: boot.reset_reason,
+            wifi: Some(WifiStatus {
+                rssi: RSSI,
+                ip: self.ip,
+            }),
+            camera,
+        }
+    }
+
+    fn open_stream(&self, stream: TcpStream) -> io::Result<FaultyStream> {
+        Ok(FaultyStream::new(stream, self.faults))
+    }
+
+    fn config(&self) -> &ConfigStore<SimStorage> {
+        &self.config
+    }
+
+    fn restart(&self) {
+        // Note: sessions of other clients stay open, unlike on the device
+        *self.camera.lock().unwrap_or_else(PoisonError::into_inner) =
+            (self.new_camera)(&self.config.get().camera);
+        *self.boot.lock().unwrap_or_else(PoisonError::into_inner) = Boot {
+            time: Instant::now(),
+            reset_reason: ResetReason::Software,
+        };
+    }
+}